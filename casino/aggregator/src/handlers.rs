@@ -1,21 +1,32 @@
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
 use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
-use axum::extract::State;
+use axum::extract::{Query, State};
 use axum::headers::Authorization;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::{Json, TypedHeader};
+use chrono::{DateTime, Utc};
 use futures_util::{Stream, StreamExt};
 use headers::authorization::Bearer;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use super::key_costs::KeyCosts;
 use super::keystore::KeyStore;
 use super::websocket::{handle_emit, handle_recv, MessageSendError};
 use countdown::CountdownRequest;
-use csgofloat::{CsgoFloatClient, CsgoFloatFetchError};
+use csgofloat::CsgoFloatClient;
 use steam::errors::MarketPriceFetchError;
-use steam::{MarketPriceClient, UnhydratedUnlock, Unlock};
+use steam::{
+    EventKinds, HistoryBatch, ItemDescription, ItemTraits, MarketPriceClient, PriceHistoryError,
+    PriceSnapshot, SessionState, StoredUnlock, TradeUp, UnboxValue, UnhydratedTradeUp,
+    UnhydratedTransfer, UnhydratedUnlock, Unlock,
+};
 use store::{Store, StoreError};
 
 #[derive(Debug, Error)]
@@ -34,8 +45,8 @@ pub enum HydrationError {
     CasePrice(MarketPriceFetchError),
     #[error("error fetching item price: {0}")]
     ItemPrice(MarketPriceFetchError),
-    #[error("error fetching float information: {0}")]
-    FloatInfo(#[from] CsgoFloatFetchError),
+    #[error("error looking up past prices: {0}")]
+    PriceHistory(#[from] PriceHistoryError),
 }
 
 #[derive(Debug, Error)]
@@ -66,12 +77,6 @@ impl IntoResponse for SaveItemsError {
     }
 }
 
-impl From<CsgoFloatFetchError> for SaveItemsError {
-    fn from(e: CsgoFloatFetchError) -> Self {
-        SaveItemsError::HydratingItem(HydrationError::FloatInfo(e))
-    }
-}
-
 #[derive(Debug, Error)]
 pub enum GetStateError {
     #[error("error hydrating items: {0}")]
@@ -88,12 +93,6 @@ impl IntoResponse for GetStateError {
     }
 }
 
-impl From<CsgoFloatFetchError> for GetStateError {
-    fn from(e: CsgoFloatFetchError) -> Self {
-        GetStateError::HydratingItem(HydrationError::FloatInfo(e))
-    }
-}
-
 #[derive(Debug, Error)]
 #[error("error getting data stream: {0}")]
 pub struct StreamError(#[from] StoreError);
@@ -104,81 +103,198 @@ impl IntoResponse for StreamError {
     }
 }
 
+#[derive(Debug, Error)]
+pub enum PriceLookupError {
+    #[error("no price recorded for {0}")]
+    NotFound(String),
+    #[error("error reading price history: {0}")]
+    History(#[from] PriceHistoryError),
+}
+
+impl IntoResponse for PriceLookupError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            PriceLookupError::NotFound(_) => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        (status, self.to_string()).into_response()
+    }
+}
+
+/// The last Steam session state a collector told us about.
+#[derive(Clone, Debug, Serialize)]
+pub struct SessionStatus {
+    #[serde(flatten)]
+    state: SessionState,
+    reported_at: DateTime<Utc>,
+}
+
+/// What CSGOFloat told us about a set of inspect links.
+#[derive(Default)]
+struct FloatInfo {
+    items: HashMap<String, ItemDescription>,
+    /// Links CSGOFloat has rejected for good, so there's no use retrying.
+    rejected: HashSet<String>,
+}
+
 pub struct Handler {
     store: Store,
     key_store: KeyStore,
-    csgofloat_client: CsgoFloatClient,
+    csgofloat_client: Option<Arc<CsgoFloatClient>>,
+    // Inspect links of items we couldn't get float information for.
+    float_pending: Arc<Mutex<HashSet<String>>>,
     market_price_client: MarketPriceClient,
+    key_costs: KeyCosts,
     countdown_admin: String,
+    event_kinds: EventKinds,
+    // Only informational, so we don't bother persisting these.
+    sessions: RwLock<HashMap<String, SessionStatus>>,
 }
 
 impl Handler {
     pub fn new(
         store: Store,
         key_store: KeyStore,
-        csgofloat_client: CsgoFloatClient,
+        csgofloat_client: Option<CsgoFloatClient>,
         market_price_client: MarketPriceClient,
         countdown_admin: String,
+        event_kinds: EventKinds,
     ) -> Self {
         Self {
             store,
             key_store,
-            csgofloat_client,
+            csgofloat_client: csgofloat_client.map(Arc::new),
+            float_pending: Arc::new(Mutex::new(HashSet::new())),
             market_price_client,
+            key_costs: KeyCosts::default(),
             countdown_admin,
+            event_kinds,
+            sessions: RwLock::new(HashMap::new()),
         }
     }
 
-    pub async fn save(
-        &self,
-        key: &str,
-        items: Vec<UnhydratedUnlock>,
-    ) -> Result<(), SaveItemsError> {
-        if items.is_empty() {
-            return Ok(());
+    /// Sets what we count keys as costing, instead of $2.49 each.
+    pub fn with_key_costs(mut self, key_costs: KeyCosts) -> Self {
+        self.key_costs = key_costs;
+        self
+    }
+
+    pub fn save_session(&self, key: &str, state: SessionState) -> Result<(), SaveItemsError> {
+        let name = self.key_store.get_user(key).ok_or(SaveItemsError::BadKey)?;
+        if state == SessionState::NotAuthenticated {
+            log::warn!("{name}'s collector is no longer logged in to steam");
         }
 
+        let status = SessionStatus {
+            state,
+            reported_at: Utc::now(),
+        };
+        self.sessions.write().unwrap().insert(name, status);
+
+        Ok(())
+    }
+
+    pub fn get_sessions(&self) -> HashMap<String, SessionStatus> {
+        self.sessions.read().unwrap().clone()
+    }
+
+    pub async fn save(&self, key: &str, mut batch: HistoryBatch) -> Result<(), SaveItemsError> {
         let name = self.key_store.get_user(key).ok_or(SaveItemsError::BadKey)?;
-        let items = items
+
+        let dropped = batch.retain_kinds(&self.event_kinds);
+        if !dropped.is_empty() {
+            let mut counts: Vec<String> = dropped
+                .iter()
+                .map(|(kind, count)| format!("{count} {kind}"))
+                .collect();
+            counts.sort();
+            log::warn!(
+                "dropped events from {name} of kinds we don't accept ({}), see --event-kinds",
+                counts.join(", ")
+            );
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let unlocks = batch
+            .unlocks
             .into_iter()
             .map(|u| UnhydratedUnlock {
                 name: name.clone(),
                 ..u
             })
             .collect::<Vec<_>>();
+        let trade_ups = batch
+            .trade_ups
+            .into_iter()
+            .map(|t| UnhydratedTradeUp {
+                name: name.clone(),
+                ..t
+            })
+            .collect::<Vec<_>>();
+        let transfers = batch
+            .transfers
+            .into_iter()
+            .map(|t| UnhydratedTransfer {
+                name: name.clone(),
+                ..t
+            })
+            .collect::<Vec<_>>();
 
-        let urls: Vec<&str> = items.iter().map(|i| i.item_market_link.as_str()).collect();
-        let float_info = self.csgofloat_client.get_bulk(&urls).await?;
+        let urls: Vec<&str> = unlocks
+            .iter()
+            .map(|i| i.item_market_link.as_str())
+            .chain(trade_ups.iter().map(|t| t.item_market_link.as_str()))
+            .collect();
+        let float_info = self.float_info(&urls).await;
+
+        for item in unlocks {
+            let mut hydrated = self.hydrate_unlock(&item, None, &float_info).await?;
+
+            // Without earlier prices on record, what it's worth now is the
+            // best guess at what it was worth when it was opened.
+            let unbox_value = hydrated.unbox_value.clone().unwrap_or_else(|| UnboxValue {
+                case_value: hydrated.case_value.clone(),
+                item_value: hydrated.item_value.clone(),
+            });
+            hydrated.unbox_value = Some(unbox_value.clone());
+            let stored = StoredUnlock {
+                entry: item,
+                unbox_value: Some(unbox_value),
+            };
 
-        for item in items {
-            let item_value = self
-                .market_price_client
-                .get(&item.item_market_name)
+            self.store
+                .append_entry(&stored)
                 .await
-                .map_err(HydrationError::ItemPrice)?;
-            let case_value = self
-                .market_price_client
-                .get(item.case.get_name())
+                .map_err(SaveItemsError::SavingItem)?;
+            self.store
+                .publish_unlock(&hydrated)
                 .await
-                .map_err(HydrationError::CasePrice)?;
+                .map_err(SaveItemsError::PublishingItem)?;
+        }
 
-            let hydrated = Unlock {
-                key: item.key.clone(),
-                case: item.case.clone(),
-                case_value,
-                item: float_info.get(&item.item_market_link).unwrap().clone(),
+        for trade_up in trade_ups {
+            let hydrated = self.hydrate_trade_up(&trade_up, &float_info).await?;
 
-                item_value,
-                at: item.at,
-                name: item.name.clone(),
-            };
+            self.store
+                .append_trade_up(&trade_up)
+                .await
+                .map_err(SaveItemsError::SavingItem)?;
+            self.store
+                .publish_trade_up(&hydrated)
+                .await
+                .map_err(SaveItemsError::PublishingItem)?;
+        }
 
+        for transfer in transfers {
             self.store
-                .append_entry(&item)
+                .append_transfer(&transfer)
                 .await
                 .map_err(SaveItemsError::SavingItem)?;
             self.store
-                .publish_unlock(&hydrated)
+                .publish_transfer(&transfer)
                 .await
                 .map_err(SaveItemsError::PublishingItem)?;
         }
@@ -192,39 +308,276 @@ impl Handler {
             return Ok(vec![]);
         }
 
-        let urls: Vec<&str> = state.iter().map(|e| e.item_market_link.as_ref()).collect();
+        let urls: Vec<&str> = state
+            .iter()
+            .map(|s| s.entry.item_market_link.as_ref())
+            .collect();
 
-        let csgofloat_info = self.csgofloat_client.get_bulk(&urls).await?;
+        let csgofloat_info = self.float_info(&urls).await;
         let mut entries = Vec::with_capacity(state.len());
-        for entry in state.into_iter() {
-            let item_value = self
-                .market_price_client
-                .get(&entry.item_market_name)
-                .await
-                .map_err(HydrationError::ItemPrice)?;
-            let case_value = self
-                .market_price_client
-                .get(entry.case.get_name())
-                .await
-                .map_err(HydrationError::CasePrice)?;
+        for stored in state.iter() {
+            let unbox_value = stored.unbox_value.clone();
+            entries.push(
+                self.hydrate_unlock(&stored.entry, unbox_value, &csgofloat_info)
+                    .await?,
+            );
+        }
+
+        Ok(entries)
+    }
 
-            let f = csgofloat_info.get(&entry.item_market_link).unwrap().clone();
+    pub async fn get_trade_ups(&self) -> Result<Vec<TradeUp>, GetStateError> {
+        let state = self.store.get_trade_ups().await?;
+        if state.is_empty() {
+            return Ok(vec![]);
+        }
 
-            entries.push(Unlock {
-                key: entry.key,
-                case: entry.case,
-                case_value,
-                item: f,
-                item_value,
+        let urls: Vec<&str> = state.iter().map(|e| e.item_market_link.as_ref()).collect();
 
-                at: entry.at,
-                name: entry.name,
-            });
+        let csgofloat_info = self.float_info(&urls).await;
+        let mut entries = Vec::with_capacity(state.len());
+        for entry in state.iter() {
+            entries.push(self.hydrate_trade_up(entry, &csgofloat_info).await?);
         }
 
         Ok(entries)
     }
 
+    pub async fn get_transfers(&self) -> Result<Vec<UnhydratedTransfer>, GetStateError> {
+        let transfers = self.store.get_transfers().await?;
+
+        Ok(transfers)
+    }
+
+    /// Float information for the given inspect links, from CSGOFloat if it's
+    /// configured and working, noting which links it rejected for good.
+    async fn float_info(&self, urls: &[&str]) -> FloatInfo {
+        let client = match &self.csgofloat_client {
+            Some(c) => c,
+            None => return FloatInfo::default(),
+        };
+
+        let mut info = FloatInfo::default();
+        for (url, res) in client.get_bulk(urls).await {
+            match res {
+                Ok(item) => {
+                    info.items.insert(url, item);
+                }
+                Err(e) => {
+                    log::warn!("error fetching float information for {url}: {e}");
+                    if !e.is_transient() {
+                        info.rejected.insert(url);
+                    }
+                }
+            }
+        }
+
+        info
+    }
+
+    /// Float information for an item if we have it, or whatever we can make
+    /// up without it. Also returns whether the float is still pending, which
+    /// it isn't if CSGOFloat has rejected the item for good.
+    fn item_description(
+        &self,
+        market_link: &str,
+        market_name: &str,
+        traits: &ItemTraits,
+        float_info: &FloatInfo,
+    ) -> (ItemDescription, bool) {
+        if let Some(item) = float_info.items.get(market_link) {
+            return (item.clone(), false);
+        }
+
+        let pending = !float_info.rejected.contains(market_link);
+        if pending && self.csgofloat_client.is_some() {
+            let mut queued = self.float_pending.lock().unwrap();
+            queued.insert(market_link.to_string());
+        }
+        let item = ItemDescription::partial(
+            market_name,
+            traits.rarity.as_deref(),
+            traits.exterior.as_deref(),
+        );
+
+        (item, pending)
+    }
+
+    /// Periodically retries fetching float information for items we had to
+    /// go without, so it's cached by the next time they're asked for.
+    pub fn float_retrier(&self, interval: Duration) -> impl Future<Output = ()> + Send + 'static {
+        let client = self.csgofloat_client.clone();
+        let pending = Arc::clone(&self.float_pending);
+
+        async move {
+            let client = match client {
+                Some(c) => c,
+                None => return,
+            };
+
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+
+                let links: Vec<String> = pending.lock().unwrap().iter().cloned().collect();
+                if links.is_empty() {
+                    continue;
+                }
+
+                log::info!("retrying float information for {} items", links.len());
+                for link in links {
+                    match client.get(&link).await {
+                        Ok(_) => {
+                            pending.lock().unwrap().remove(&link);
+                        }
+                        Err(e) if !e.is_transient() => {
+                            log::warn!("giving up on float information for {link}: {e}");
+                            pending.lock().unwrap().remove(&link);
+                        }
+                        // e.g. CSGOFloat is down, so the rest would fail too.
+                        Err(e) => {
+                            log::warn!("still can't get float information: {e}");
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// The prices of a case and its item last recorded before it was
+    /// opened, if we have both.
+    async fn unbox_value_from_history(
+        &self,
+        entry: &UnhydratedUnlock,
+    ) -> Result<Option<UnboxValue>, HydrationError> {
+        let history = self.market_price_client.history();
+        let case_value = history.at(entry.case.get_name(), entry.at).await?;
+        let item_value = history.at(&entry.item_market_name, entry.at).await?;
+
+        Ok(case_value.zip(item_value).map(|(c, i)| UnboxValue {
+            case_value: c.prices,
+            item_value: i.prices,
+        }))
+    }
+
+    async fn hydrate_unlock(
+        &self,
+        entry: &UnhydratedUnlock,
+        unbox_value: Option<UnboxValue>,
+        float_info: &FloatInfo,
+    ) -> Result<Unlock, HydrationError> {
+        let item_value = self
+            .market_price_client
+            .get(&entry.item_market_name)
+            .await
+            .map_err(HydrationError::ItemPrice)?;
+        let case_value = self
+            .market_price_client
+            .get(entry.case.get_name())
+            .await
+            .map_err(HydrationError::CasePrice)?;
+        let (item, float_pending) = self.item_description(
+            &entry.item_market_link,
+            &entry.item_market_name,
+            &entry.item_traits,
+            float_info,
+        );
+        let unbox_value = match unbox_value {
+            Some(v) => Some(v),
+            None => self.unbox_value_from_history(entry).await?,
+        };
+
+        let key_cost = match &entry.key {
+            Some(key) => self
+                .key_costs
+                .get(key.get_name(), self.market_price_client.currency()),
+            None => None,
+        };
+        let key_cost_basis = match &entry.key {
+            Some(_) => key_cost,
+            None => Some(Decimal::ZERO),
+        };
+        let case_cost = unbox_value
+            .as_ref()
+            .map_or(&case_value, |v| &v.case_value)
+            .price();
+        let cost_basis = case_cost.zip(key_cost_basis).map(|(c, k)| c + k);
+        let profit = item_value.price().zip(cost_basis).map(|(i, c)| i - c);
+
+        Ok(Unlock {
+            key: entry.key.clone(),
+            case: entry.case.clone(),
+            case_value,
+            item,
+            item_traits: entry.item_traits.clone(),
+            item_value,
+            float_pending,
+            unbox_value,
+            key_cost,
+            cost_basis,
+            profit,
+
+            at: entry.at,
+            name: entry.name.clone(),
+        })
+    }
+
+    async fn hydrate_trade_up(
+        &self,
+        entry: &UnhydratedTradeUp,
+        float_info: &FloatInfo,
+    ) -> Result<TradeUp, HydrationError> {
+        let item_value = self
+            .market_price_client
+            .get(&entry.item_market_name)
+            .await
+            .map_err(HydrationError::ItemPrice)?;
+        let (item, float_pending) = self.item_description(
+            &entry.item_market_link,
+            &entry.item_market_name,
+            &entry.item_traits,
+            float_info,
+        );
+
+        Ok(TradeUp {
+            inputs: entry.inputs.clone(),
+            item,
+            item_value,
+            float_pending,
+
+            at: entry.at,
+            name: entry.name.clone(),
+        })
+    }
+
+    /// The latest recorded prices of an item, or the last ones recorded at
+    /// or before the given time.
+    pub async fn get_price(
+        &self,
+        market_name: &str,
+        at: Option<DateTime<Utc>>,
+    ) -> Result<PriceSnapshot, PriceLookupError> {
+        let history = self.market_price_client.history();
+        let snapshot = match at {
+            Some(at) => history.at(market_name, at).await?,
+            None => history.latest(market_name).await?,
+        };
+
+        snapshot.ok_or_else(|| PriceLookupError::NotFound(market_name.to_string()))
+    }
+
+    pub async fn get_price_history(
+        &self,
+        market_name: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<PriceSnapshot>, PriceLookupError> {
+        let history = self.market_price_client.history();
+
+        Ok(history.between(market_name, from, to).await?)
+    }
+
     pub async fn unlock_event_stream(&self) -> Result<impl Stream<Item = Unlock>, StreamError> {
         let stream = self.store.get_unlock_stream().await?;
 
@@ -246,13 +599,93 @@ pub async fn handle_state(
     state.get_state().await.map(Json::from)
 }
 
+pub async fn handle_trade_ups(
+    State(state): State<Arc<Handler>>,
+) -> Result<Json<Vec<TradeUp>>, GetStateError> {
+    state.get_trade_ups().await.map(Json::from)
+}
+
+pub async fn handle_transfers(
+    State(state): State<Arc<Handler>>,
+) -> Result<Json<Vec<UnhydratedTransfer>>, GetStateError> {
+    state.get_transfers().await.map(Json::from)
+}
+
+/// Body of an upload request. Older collectors upload a bare list of unlocks.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum UploadRequest {
+    Unlocks(Vec<UnhydratedUnlock>),
+    Batch(HistoryBatch),
+}
+
+impl From<UploadRequest> for HistoryBatch {
+    fn from(req: UploadRequest) -> Self {
+        match req {
+            UploadRequest::Unlocks(unlocks) => HistoryBatch {
+                unlocks,
+                ..Default::default()
+            },
+            UploadRequest::Batch(batch) => batch,
+        }
+    }
+}
+
 pub async fn handle_upload(
     State(state): State<Arc<Handler>>,
     TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
-    Json(body): Json<Vec<UnhydratedUnlock>>,
+    Json(body): Json<UploadRequest>,
+) -> Result<(), SaveItemsError> {
+    let key = auth.0.token();
+    state.save(key, body.into()).await
+}
+
+pub async fn handle_session_report(
+    State(state): State<Arc<Handler>>,
+    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    Json(body): Json<SessionState>,
 ) -> Result<(), SaveItemsError> {
     let key = auth.0.token();
-    state.save(key, body).await
+    state.save_session(key, body)
+}
+
+pub async fn handle_sessions(
+    State(state): State<Arc<Handler>>,
+) -> Json<HashMap<String, SessionStatus>> {
+    Json(state.get_sessions())
+}
+
+#[derive(Deserialize)]
+pub struct PriceQuery {
+    name: String,
+    at: Option<DateTime<Utc>>,
+}
+
+pub async fn handle_price(
+    State(state): State<Arc<Handler>>,
+    Query(query): Query<PriceQuery>,
+) -> Result<Json<PriceSnapshot>, PriceLookupError> {
+    state.get_price(&query.name, query.at).await.map(Json::from)
+}
+
+#[derive(Deserialize)]
+pub struct PriceHistoryQuery {
+    name: String,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+pub async fn handle_price_history(
+    State(state): State<Arc<Handler>>,
+    Query(query): Query<PriceHistoryQuery>,
+) -> Result<Json<Vec<PriceSnapshot>>, PriceLookupError> {
+    let from = query.from.unwrap_or(DateTime::<Utc>::MIN_UTC);
+    let to = query.to.unwrap_or_else(Utc::now);
+
+    state
+        .get_price_history(&query.name, from, to)
+        .await
+        .map(Json::from)
 }
 
 pub async fn handle_countdown_request(
@@ -290,6 +723,32 @@ pub async fn handle_websocket(
     })
 }
 
+pub async fn handle_trade_up_websocket(
+    State(state): State<Arc<Handler>>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    ws.on_upgrade(|socket| async move {
+        if let Ok(stream) = state.store.get_trade_up_stream().await.map(Box::pin) {
+            if let Err(e) = handle_upgraded_websocket(stream, socket).await {
+                log::error!("error serving websocket: {e}");
+            }
+        }
+    })
+}
+
+pub async fn handle_transfer_websocket(
+    State(state): State<Arc<Handler>>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    ws.on_upgrade(|socket| async move {
+        if let Ok(stream) = state.store.get_transfer_stream().await.map(Box::pin) {
+            if let Err(e) = handle_upgraded_websocket(stream, socket).await {
+                log::error!("error serving websocket: {e}");
+            }
+        }
+    })
+}
+
 pub async fn handle_sync_websocket(
     State(state): State<Arc<Handler>>,
     ws: WebSocketUpgrade,
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use steam::Currency;
+use thiserror::Error;
+use tokio::fs::File;
+use tokio::io::{self, AsyncReadExt};
+
+const DEFAULT_KEY: &str = "default";
+
+/// What a key costs to buy from Valve. Keys can't be resold, so the market
+/// can't tell us.
+#[derive(Debug, Deserialize)]
+pub struct KeyCosts {
+    // Key name (or "default") -> currency -> cost.
+    #[serde(flatten)]
+    costs: HashMap<String, HashMap<Currency, Decimal>>,
+}
+
+impl Default for KeyCosts {
+    fn default() -> Self {
+        let usd = HashMap::from([(Currency::USD, Decimal::new(249, 2))]);
+        Self::new(HashMap::from([(DEFAULT_KEY.to_string(), usd)]))
+    }
+}
+
+impl KeyCosts {
+    /// Loads a YAML map of key names to the cost of that key in each
+    /// currency. Keys not listed cost whatever is listed under `default`.
+    pub async fn load_from_file<P: AsRef<Path>>(p: P) -> Result<Self, KeyCostsLoadError> {
+        let mut data: Vec<u8> = Vec::new();
+        File::open(p).await?.read_to_end(&mut data).await?;
+
+        Ok(serde_yaml::from_slice(&data)?)
+    }
+
+    pub fn new(costs: HashMap<String, HashMap<Currency, Decimal>>) -> Self {
+        Self { costs }
+    }
+
+    /// The cost of the named key in the given currency, if we know it.
+    pub fn get(&self, key_name: &str, currency: Currency) -> Option<Decimal> {
+        [key_name, DEFAULT_KEY]
+            .into_iter()
+            .find_map(|k| self.costs.get(k)?.get(&currency))
+            .copied()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum KeyCostsLoadError {
+    #[error("io error: {0}")]
+    IO(#[from] io::Error),
+    #[error("ser/deserialisation error: {0}")]
+    Serde(#[from] serde_yaml::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal::Decimal;
+    use steam::Currency;
+
+    use super::KeyCosts;
+
+    #[test]
+    fn test_key_costs() {
+        let costs: KeyCosts = serde_yaml::from_str(
+            "default: {USD: 2.49, EUR: 2.35}\n\"Operation Riptide Case Key\": {USD: 2.60}\n",
+        )
+        .unwrap();
+
+        let riptide = "Operation Riptide Case Key";
+        assert_eq!(
+            costs.get(riptide, Currency::USD),
+            Some(Decimal::new(260, 2))
+        );
+        assert_eq!(
+            costs.get(riptide, Currency::EUR),
+            Some(Decimal::new(235, 2))
+        );
+        assert_eq!(
+            costs.get("Clutch Case Key", Currency::USD),
+            Some(Decimal::new(249, 2))
+        );
+        assert_eq!(costs.get("Clutch Case Key", Currency::GBP), None);
+    }
+}
@@ -4,14 +4,17 @@ use std::sync::Arc;
 use axum::routing;
 use thiserror::Error;
 
+pub mod key_costs;
 pub mod keystore;
 mod websocket;
 
 mod handlers;
-pub use self::handlers::Handler;
 use self::handlers::{
-    handle_countdown_request, handle_state, handle_sync_websocket, handle_upload, handle_websocket,
+    handle_countdown_request, handle_price, handle_price_history, handle_session_report,
+    handle_sessions, handle_state, handle_sync_websocket, handle_trade_up_websocket,
+    handle_trade_ups, handle_transfer_websocket, handle_transfers, handle_upload, handle_websocket,
 };
+pub use self::handlers::{Handler, HandlerError, PriceLookupError, SessionStatus};
 
 async fn ctrl_c() {
     tokio::signal::ctrl_c().await.unwrap();
@@ -23,20 +26,129 @@ async fn ctrl_c() {
 #[error("Failed to serve http: {0}")]
 pub struct ServingError(#[from] hyper::Error);
 
-pub async fn serve(bind_addr: &SocketAddr, handler: Handler) -> Result<(), ServingError> {
+/// Builds the routes served by the aggregator.
+pub fn router(handler: Handler) -> routing::Router {
     let handler = Arc::new(handler);
-    let app = routing::Router::new()
+    routing::Router::new()
         .route("/", routing::get(handle_state))
         .route("/upload", routing::post(handle_upload))
+        .route("/session", routing::post(handle_session_report))
+        .route("/sessions", routing::get(handle_sessions))
         .route("/stream", routing::get(handle_websocket))
+        .route("/trade_ups", routing::get(handle_trade_ups))
+        .route("/trade_up_stream", routing::get(handle_trade_up_websocket))
+        .route("/transfers", routing::get(handle_transfers))
+        .route("/transfer_stream", routing::get(handle_transfer_websocket))
+        .route("/prices", routing::get(handle_price))
+        .route("/prices/history", routing::get(handle_price_history))
         .route("/countdown", routing::post(handle_countdown_request))
         .route("/sync", routing::get(handle_sync_websocket))
-        .with_state(handler);
+        .with_state(handler)
+}
 
+pub async fn serve(bind_addr: &SocketAddr, handler: Handler) -> Result<(), ServingError> {
     axum::Server::bind(bind_addr)
-        .serve(app.into_make_service())
+        .serve(router(handler).into_make_service())
         .with_graceful_shutdown(ctrl_c())
         .await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+    use collector::Collector;
+    use csgofloat::CsgoFloatClient;
+    use mock_steam::Fixtures;
+    use steam::{
+        EventKind, Id, MarketPriceClient, PriceSource, RetryPolicy, SteamClient, SteamCredentials,
+        SteamMarket,
+    };
+    use store::Store;
+
+    use super::keystore::KeyStore;
+    use super::{router, Handler};
+
+    #[tokio::test]
+    #[ignore = "needs a scratch redis server at REDIS_URL"]
+    async fn test_collector_to_aggregator() {
+        let redis_url =
+            std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+        let base = mock_steam::spawn(Fixtures::default());
+
+        let store = Store::new(redis_url.as_str()).await.unwrap();
+        let csgo_float = CsgoFloatClient::new("key", redis_url.as_str())
+            .await
+            .unwrap()
+            .with_base_url(base.as_str());
+        let market_price_client = MarketPriceClient::new(redis_url.as_str())
+            .await
+            .unwrap()
+            .with_sources([
+                Arc::new(SteamMarket::new().with_base_url(base.as_str())) as Arc<dyn PriceSource>
+            ]);
+        let keys = HashMap::from([("psk".to_string(), "mock".to_string())]);
+        let handler = Handler::new(
+            store,
+            KeyStore::new(keys),
+            Some(csgo_float),
+            market_price_client,
+            "mock".to_string(),
+            EventKind::DEFAULT.into_iter().collect(),
+        );
+
+        let addr = ([127, 0, 0, 1], 0).into();
+        let server = axum::Server::bind(&addr).serve(router(handler).into_make_service());
+        let aggregator_url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        let id = Id::try_from_url_with_base(
+            "https://steamcommunity.com/id/mock",
+            &base,
+            &RetryPolicy::default(),
+        )
+        .await
+        .unwrap();
+        let creds = SteamCredentials::new("session".to_string(), "token".to_string());
+        let collector = Collector::new(
+            format!("{aggregator_url}/upload"),
+            SteamClient::new(id, creds),
+            "psk".to_string(),
+            Duration::from_secs(10),
+            None,
+        )
+        .await
+        .unwrap();
+        let until = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        collector.backfill(until).await.unwrap();
+
+        let state = reqwest::get(&aggregator_url)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(state.contains("AK-47 | Redline (Field-Tested)"));
+        assert!(state.contains("\"unbox_value\":{"));
+
+        let price = reqwest::Client::new()
+            .get(format!("{aggregator_url}/prices"))
+            .query(&[("name", "AK-47 | Redline (Field-Tested)")])
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(price.contains("\"currency\":\"USD\""));
+    }
+}
@@ -1,13 +1,18 @@
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::Arc;
 
-use clap::Parser;
-use csgofloat::{CsgoFloatClient, CsgoFloatClientCreateError};
+use clap::{Parser, ValueEnum};
+use csgofloat::{Auth, CsgoFloatClient, CsgoFloatClientCreateError};
 use redis::ConnectionInfo;
-use steam::{MarketPriceClient, MarketPriceClientCreateError};
+use steam::{
+    Currency, EventKind, MarketPriceClient, MarketPriceClientCreateError, PriceSheet,
+    PriceSheetLoadError, PriceSource, Skinport, SteamMarket,
+};
 use store::{Store, StoreError};
 use thiserror::Error;
 
+use aggregator::key_costs::{KeyCosts, KeyCostsLoadError};
 use aggregator::keystore::{KeyStore, KeyStoreLoadSaveError};
 use aggregator::{serve, Handler, ServingError};
 
@@ -32,33 +37,125 @@ enum AggregatorError {
     CreatingStore(#[from] StoreError),
     #[error("error loading keystore: {0}")]
     LoadingKeystore(#[from] KeyStoreLoadSaveError),
+    #[error("error loading key costs: {0}")]
+    LoadingKeyCosts(#[from] KeyCostsLoadError),
     #[error("error creating steam market price client: {0}")]
     CreatingMarketPriceClient(#[from] MarketPriceClientCreateError),
+    #[error("error loading price sheet: {0}")]
+    LoadingPriceSheet(#[from] PriceSheetLoadError),
+    #[error("the sheet price source needs --price-sheet")]
+    NoPriceSheet,
+    #[error("the self-hosted inspect backend needs --inspect-url")]
+    NoInspectUrl,
     #[error("error serving http: {0}")]
     ServingHTTP(#[from] ServingError),
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum InspectBackend {
+    /// The public CSGOFloat API
+    Hosted,
+    /// An inspect server of our own that speaks the same protocol
+    SelfHosted,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum InspectAuthScheme {
+    /// Send the token as-is, as CSGOFloat does
+    Key,
+    /// Send the token as a bearer token
+    Bearer,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum PriceSourceKind {
+    Steam,
+    Skinport,
+    Sheet,
+}
+
 #[derive(Parser)]
 #[command(version)]
 struct Args {
     /// URL to connect to Redis with"
     #[arg(short, long, env, default_value = "redis://redis:6379")]
     redis_url: ConnectionInfo,
-    /// API key for CSGOFloat
+    /// API key for CSGOFloat. Without one, items are shown without their
+    /// float or paint seed
     #[arg(short, long, env)]
-    csgofloat_key: String,
+    csgofloat_key: Option<String>,
     /// Address to bind server to
     #[arg(short, long, env, default_value = "0.0.0.0:7000")]
     bind_addr: SocketAddr,
     /// Location of user keystore file
     #[arg(short, long, env, default_value = "./keystore.yaml")]
     keystore_path: PathBuf,
+    /// Location of a YAML file of what each key costs in each currency, if
+    /// not $2.49
+    #[arg(long, env)]
+    key_costs_path: Option<PathBuf>,
     /// Level to log at
     #[arg(short, long, env, default_value = "info")]
     log_level: log::LevelFilter,
     /// Friendly name of the user who may trigger countdowns
     #[arg(short, long, env)]
     countdown_admin: String,
+    /// Kinds of inventory history event to accept from collectors
+    #[arg(
+        short,
+        long,
+        env,
+        value_delimiter = ',',
+        default_value = "unbox,trade_up"
+    )]
+    event_kinds: Vec<EventKind>,
+    /// Base URL of the CSGOFloat API
+    #[arg(long, env, default_value = "https://api.csgofloat.com")]
+    csgofloat_url: String,
+    /// Where to look up item floats and paint seeds
+    #[arg(long, env, value_enum, default_value = "hosted")]
+    inspect_backend: InspectBackend,
+    /// Base URL of the self-hosted inspect server
+    #[arg(long, env)]
+    inspect_url: Option<String>,
+    /// Token for the self-hosted inspect server, if it needs one
+    #[arg(long, env, hide_env_values = true)]
+    inspect_token: Option<String>,
+    /// How to send --inspect-token to the self-hosted inspect server
+    #[arg(long, env, value_enum, default_value = "bearer")]
+    inspect_auth_scheme: InspectAuthScheme,
+    /// Most items to look up in one bulk request
+    #[arg(long, env, default_value = "50")]
+    inspect_bulk_limit: usize,
+    /// Most CSGOFloat lookups to make at once, when bulk lookups fail
+    #[arg(long, env, default_value = "4")]
+    csgofloat_concurrency: usize,
+    /// Interval to retry fetching float information CSGOFloat couldn't give
+    /// us earlier
+    #[arg(long, env, default_value = "5m")]
+    float_retry_interval: humantime::Duration,
+    /// Base URL of the Steam Community site, used for market prices
+    #[arg(long, env, default_value = "https://steamcommunity.com")]
+    steam_community_url: String,
+    /// Currency to fetch market prices in, as an ISO 4217 code
+    #[arg(long, env, default_value = "USD")]
+    currency: Currency,
+    /// Interval to refresh the market prices of every item seen so far
+    #[arg(long, env, default_value = "6h")]
+    price_refresh_interval: humantime::Duration,
+    /// Time to wait between market price requests while refreshing, to stay
+    /// within Steam's rate limits
+    #[arg(long, env, default_value = "3s")]
+    price_request_delay: humantime::Duration,
+    /// Where to get market prices from, in the order to try them
+    #[arg(long, env, value_delimiter = ',', default_value = "steam")]
+    price_sources: Vec<PriceSourceKind>,
+    /// CSV or JSON file of fixed prices, for the sheet price source
+    #[arg(long, env)]
+    price_sheet: Option<PathBuf>,
+    /// Base URL of the Skinport API, for the skinport price source
+    #[arg(long, env, default_value = "https://api.skinport.com")]
+    skinport_url: String,
 }
 
 async fn real_main() -> Result<(), AggregatorError> {
@@ -67,9 +164,66 @@ async fn real_main() -> Result<(), AggregatorError> {
     logging::init(args.log_level);
 
     let keystore = KeyStore::load_from_file(args.keystore_path).await?;
+    let key_costs = match &args.key_costs_path {
+        Some(path) => KeyCosts::load_from_file(path).await?,
+        None => KeyCosts::default(),
+    };
     let store = Store::new(args.redis_url.clone()).await?;
-    let csgo_float = CsgoFloatClient::new(args.csgofloat_key, args.redis_url.clone()).await?;
-    let market_price_client = MarketPriceClient::new(args.redis_url).await?;
+    let inspect = match args.inspect_backend {
+        InspectBackend::Hosted => args
+            .csgofloat_key
+            .map(|key| (args.csgofloat_url, Auth::Key(key))),
+        InspectBackend::SelfHosted => {
+            let url = args.inspect_url.ok_or(AggregatorError::NoInspectUrl)?;
+            let auth = match (args.inspect_token, args.inspect_auth_scheme) {
+                (None, _) => Auth::None,
+                (Some(token), InspectAuthScheme::Key) => Auth::Key(token),
+                (Some(token), InspectAuthScheme::Bearer) => Auth::Bearer(token),
+            };
+            Some((url, auth))
+        }
+    };
+    let csgo_float = match inspect {
+        Some((url, auth)) => Some(
+            CsgoFloatClient::new(auth, args.redis_url.clone())
+                .await?
+                .with_base_url(url)
+                .with_bulk_limit(args.inspect_bulk_limit)
+                .with_concurrency(args.csgofloat_concurrency),
+        ),
+        None => {
+            log::warn!("no csgofloat key given, items will have no float information");
+            None
+        }
+    };
+
+    let mut price_sources: Vec<Arc<dyn PriceSource>> = Vec::new();
+    for kind in &args.price_sources {
+        let source: Arc<dyn PriceSource> = match kind {
+            PriceSourceKind::Steam => {
+                Arc::new(SteamMarket::new().with_base_url(&args.steam_community_url))
+            }
+            PriceSourceKind::Skinport => {
+                Arc::new(Skinport::new().with_base_url(&args.skinport_url))
+            }
+            PriceSourceKind::Sheet => {
+                let path = args
+                    .price_sheet
+                    .as_ref()
+                    .ok_or(AggregatorError::NoPriceSheet)?;
+                Arc::new(PriceSheet::load(path, args.currency).await?)
+            }
+        };
+        price_sources.push(source);
+    }
+    let market_price_client = MarketPriceClient::new(args.redis_url)
+        .await?
+        .with_sources(price_sources)
+        .with_currency(args.currency);
+
+    let refresher = market_price_client.clone();
+    let (interval, delay) = (*args.price_refresh_interval, *args.price_request_delay);
+    tokio::spawn(async move { refresher.run_refresher(interval, delay).await });
 
     let h = Handler::new(
         store,
@@ -77,7 +231,10 @@ async fn real_main() -> Result<(), AggregatorError> {
         csgo_float,
         market_price_client,
         args.countdown_admin,
-    );
+        args.event_kinds.into_iter().collect(),
+    )
+    .with_key_costs(key_costs);
+    tokio::spawn(h.float_retrier(*args.float_retry_interval));
 
     serve(&args.bind_addr, h).await?;
 
@@ -1,6 +1,7 @@
 use std::path::Path;
 
 use serde::{Deserialize, Serialize};
+use steam::{EventKind, Language};
 use thiserror::Error;
 use tokio::fs::File;
 use tokio::io::{self, AsyncReadExt};
@@ -13,10 +14,34 @@ pub enum ConfigLoadError {
     Serde(#[from] serde_yaml::Error),
 }
 
+/// Where to read a user's inventory history from.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SourceConfig {
+    /// Scrape the Steam Community site, using the user's cookies
+    #[default]
+    Community,
+    /// Use the official Steam Web API. Only supports trades.
+    WebApi { api_key: String },
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Config {
     pub steam_profile_url: String,
     pub pre_shared_key: String,
+    /// Kinds of inventory history event to upload
+    #[serde(default = "default_event_kinds")]
+    pub event_kinds: Vec<EventKind>,
+    /// Language to read Steam's Inventory History in
+    #[serde(default)]
+    pub language: Language,
+    /// Where to read inventory history from
+    #[serde(default)]
+    pub source: SourceConfig,
+}
+
+fn default_event_kinds() -> Vec<EventKind> {
+    EventKind::DEFAULT.to_vec()
 }
 
 impl Config {
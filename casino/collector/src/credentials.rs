@@ -0,0 +1,436 @@
+//! Loading, saving and asking for the Steam credentials we log in with.
+//!
+//! Credentials are kept encrypted at rest, with a key derived from a
+//! passphrase or, failing that, a random key kept in a file next to them.
+
+use std::path::{Path, PathBuf};
+
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use steam::{CredentialParseError, SteamCredentials};
+use thiserror::Error;
+use tokio::fs;
+use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+#[derive(Debug, Error)]
+pub enum CredentialPromptError {
+    #[error("error parsing credentials: {0}")]
+    CredentialParse(#[from] CredentialParseError),
+    #[error("io error: {0}")]
+    IO(#[from] io::Error),
+}
+
+pub async fn prompt_for_credentials() -> Result<SteamCredentials, CredentialPromptError> {
+    let mut stdin = BufReader::new(io::stdin());
+    let mut stdout = io::stdout();
+
+    stdout.flush().await?;
+    stdout
+        .write_all("Please enter steam cookie (or see `collector login --help` and `collector import --help`):\n>".as_bytes())
+        .await?;
+    let mut buf = String::new();
+    if stdin.read_line(&mut buf).await? == 0 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+
+    let creds = SteamCredentials::try_from_cookie_str(buf.as_str())?;
+
+    Ok(creds)
+}
+
+/// Asks the user for a line of input, e.g. a username or Steam Guard code.
+pub async fn prompt_line(prompt: &str) -> Result<String, io::Error> {
+    let mut stdin = BufReader::new(io::stdin());
+    let mut stdout = io::stdout();
+
+    stdout.write_all(prompt.as_bytes()).await?;
+    stdout.flush().await?;
+    let mut buf = String::new();
+    if stdin.read_line(&mut buf).await? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(buf.trim().to_string())
+}
+
+/// Asks the user for a password, without echoing it to the terminal.
+pub async fn prompt_password(prompt: &str) -> Result<String, io::Error> {
+    let prompt = prompt.to_string();
+    tokio::task::spawn_blocking(move || rpassword::prompt_password(prompt)).await?
+}
+
+const ENVELOPE_VERSION: u32 = 1;
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+#[derive(Debug, Error)]
+pub enum CredentialLoadSaveError {
+    #[error("error parsing json: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error("io error: {0}")]
+    IO(#[from] io::Error),
+    #[error("error decoding base64: {0}")]
+    Decoding(#[from] base64::DecodeError),
+    #[error("error deriving key from passphrase: {0}")]
+    KeyDerivation(argon2::Error),
+    #[error("could not decrypt credentials, the passphrase or key file may be wrong")]
+    Decrypting,
+    #[error("credentials were saved by a newer version (format {0})")]
+    UnsupportedVersion(u32),
+    #[error("credentials were encrypted with a {0}, but none was given")]
+    MissingKey(KeyKind),
+    #[error("key file is not a valid key")]
+    InvalidKeyFile,
+}
+
+/// What a credentials file was encrypted with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyKind {
+    Passphrase,
+    KeyFile,
+}
+
+impl std::fmt::Display for KeyKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyKind::Passphrase => write!(f, "passphrase"),
+            KeyKind::KeyFile => write!(f, "key file"),
+        }
+    }
+}
+
+/// Where the key to encrypt credentials with comes from.
+#[derive(Clone, Debug)]
+pub enum KeySource {
+    /// Derived from a passphrase with Argon2id.
+    Passphrase(String),
+    /// A random key kept in a file, created on first save.
+    KeyFile(PathBuf),
+}
+
+impl KeySource {
+    fn kind(&self) -> KeyKind {
+        match self {
+            KeySource::Passphrase(_) => KeyKind::Passphrase,
+            KeySource::KeyFile(_) => KeyKind::KeyFile,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    version: u32,
+    key: KeyKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    salt: Option<String>,
+    nonce: String,
+    ciphertext: String,
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+/// Writes a file only its owner can read, replacing whatever was there.
+async fn write_private(path: &Path, contents: &[u8]) -> Result<(), io::Error> {
+    // Write alongside and rename over the original, so it's never left half
+    // written and any looser permissions it had don't carry over.
+    let tmp = path.with_extension("tmp");
+    match fs::remove_file(&tmp).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => (),
+    }
+
+    let mut opts = fs::OpenOptions::new();
+    opts.write(true).create_new(true);
+    #[cfg(unix)]
+    opts.mode(0o600);
+    let mut f = opts.open(&tmp).await?;
+    f.write_all(contents).await?;
+    f.sync_all().await?;
+    drop(f);
+
+    fs::rename(&tmp, path).await
+}
+
+/// Overwrites a file before removing it, so the old contents don't linger
+/// on disk. Missing files are ignored.
+async fn shred(path: &Path) -> Result<(), io::Error> {
+    let len = match fs::metadata(path).await {
+        Ok(meta) => meta.len(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    let mut f = fs::OpenOptions::new().write(true).open(path).await?;
+    f.write_all(&vec![0u8; len as usize]).await?;
+    f.sync_all().await?;
+    drop(f);
+
+    fs::remove_file(path).await
+}
+
+/// An encrypted file holding the Steam credentials we log in with.
+#[derive(Clone, Debug)]
+pub struct CredentialStore {
+    path: PathBuf,
+    key_source: KeySource,
+}
+
+impl CredentialStore {
+    pub fn new<P: Into<PathBuf>>(path: P, key_source: KeySource) -> Self {
+        Self {
+            path: path.into(),
+            key_source,
+        }
+    }
+
+    /// A store encrypted with the given passphrase, or with a key file next
+    /// to it (e.g. `.creds.key` for `.creds.json`) if there isn't one.
+    pub fn with_passphrase<P: Into<PathBuf>>(path: P, passphrase: Option<String>) -> Self {
+        let path = path.into();
+        let key_source = match passphrase {
+            Some(passphrase) => KeySource::Passphrase(passphrase),
+            None => KeySource::KeyFile(path.with_extension("key")),
+        };
+
+        Self::new(path, key_source)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn key_kind(&self) -> KeyKind {
+        self.key_source.kind()
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    async fn key(&self, salt: Option<&[u8]>, create: bool) -> Result<Key, CredentialLoadSaveError> {
+        let mut key = Key::default();
+        match &self.key_source {
+            KeySource::Passphrase(passphrase) => {
+                let salt = salt.ok_or(CredentialLoadSaveError::Decrypting)?;
+                Argon2::default()
+                    .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+                    .map_err(CredentialLoadSaveError::KeyDerivation)?;
+            }
+            KeySource::KeyFile(path) => {
+                let encoded = match fs::read_to_string(path).await {
+                    Ok(encoded) => encoded,
+                    Err(e) if create && e.kind() == io::ErrorKind::NotFound => {
+                        let encoded = base64::encode(random_bytes::<KEY_LEN>());
+                        write_private(path, encoded.as_bytes()).await?;
+                        encoded
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+
+                let bytes = base64::decode(encoded.trim())?;
+                if bytes.len() != KEY_LEN {
+                    return Err(CredentialLoadSaveError::InvalidKeyFile);
+                }
+                key.copy_from_slice(&bytes);
+            }
+        }
+
+        Ok(key)
+    }
+
+    /// Loads and decrypts the stored credentials. Files saved before
+    /// credentials were encrypted are read as they are, and encrypted in
+    /// place.
+    pub async fn load(&self) -> Result<SteamCredentials, CredentialLoadSaveError> {
+        let contents = fs::read(&self.path).await?;
+        let envelope: Envelope = match serde_json::from_slice(&contents) {
+            Ok(envelope) => envelope,
+            Err(_) => {
+                let creds = serde_json::from_slice(&contents)?;
+                log::info!(
+                    "encrypting plaintext credentials in {}",
+                    self.path.display()
+                );
+                self.save(&creds).await?;
+                return Ok(creds);
+            }
+        };
+
+        if envelope.version > ENVELOPE_VERSION {
+            return Err(CredentialLoadSaveError::UnsupportedVersion(
+                envelope.version,
+            ));
+        }
+        if envelope.key != self.key_kind() {
+            return Err(CredentialLoadSaveError::MissingKey(envelope.key));
+        }
+
+        let salt = envelope.salt.as_deref().map(base64::decode).transpose()?;
+        let key = self.key(salt.as_deref(), false).await?;
+        let nonce = base64::decode(&envelope.nonce)?;
+        if nonce.len() != NONCE_LEN {
+            return Err(CredentialLoadSaveError::Decrypting);
+        }
+        let plaintext = XChaCha20Poly1305::new(&key)
+            .decrypt(
+                XNonce::from_slice(&nonce),
+                base64::decode(&envelope.ciphertext)?.as_slice(),
+            )
+            .map_err(|_| CredentialLoadSaveError::Decrypting)?;
+
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+
+    /// Encrypts and saves credentials, readable only by the current user.
+    pub async fn save(&self, creds: &SteamCredentials) -> Result<(), CredentialLoadSaveError> {
+        let salt = match self.key_source {
+            KeySource::Passphrase(_) => Some(random_bytes::<SALT_LEN>()),
+            KeySource::KeyFile(_) => None,
+        };
+        let key = self.key(salt.as_ref().map(|s| s.as_slice()), true).await?;
+        let nonce = random_bytes::<NONCE_LEN>();
+        let ciphertext = XChaCha20Poly1305::new(&key)
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                serde_json::to_vec(creds)?.as_slice(),
+            )
+            .map_err(|_| CredentialLoadSaveError::Decrypting)?;
+
+        let envelope = Envelope {
+            version: ENVELOPE_VERSION,
+            key: self.key_kind(),
+            salt: salt.map(base64::encode),
+            nonce: base64::encode(nonce),
+            ciphertext: base64::encode(ciphertext),
+        };
+        write_private(&self.path, &serde_json::to_vec(&envelope)?).await?;
+
+        Ok(())
+    }
+
+    /// Re-encrypts credentials under a new key, from the given passphrase or
+    /// a freshly generated key file, returning the store to use from now on.
+    pub async fn rotate_key(
+        self,
+        creds: &SteamCredentials,
+        passphrase: Option<String>,
+    ) -> Result<Self, CredentialLoadSaveError> {
+        let rotated = Self::with_passphrase(self.path.clone(), passphrase);
+        let old_key = match &self.key_source {
+            KeySource::KeyFile(path) => Some(path.as_path()),
+            KeySource::Passphrase(_) => None,
+        };
+
+        // Only get rid of the old key once the credentials are saved under
+        // the new one, so they're never left unreadable.
+        match &rotated.key_source {
+            KeySource::KeyFile(new_key) if Some(new_key.as_path()) == old_key => {
+                // The new key file would replace the old one, so keep it
+                // elsewhere until the old one is gone.
+                let tmp_key = new_key.with_extension("key.new");
+                shred(&tmp_key).await?;
+                Self::new(self.path.clone(), KeySource::KeyFile(tmp_key.clone()))
+                    .save(creds)
+                    .await?;
+                shred(new_key).await?;
+                fs::rename(&tmp_key, new_key).await?;
+            }
+            _ => {
+                rotated.save(creds).await?;
+                if let Some(old_key) = old_key {
+                    shred(old_key).await?;
+                }
+            }
+        }
+
+        Ok(rotated)
+    }
+
+    /// Securely deletes the stored credentials, along with their key file.
+    pub async fn wipe(&self) -> Result<(), io::Error> {
+        shred(&self.path).await?;
+        if let KeySource::KeyFile(path) = &self.key_source {
+            shred(path).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use steam::SteamCredentials;
+
+    use super::{CredentialLoadSaveError, CredentialStore};
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("{name}-{}.json", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn test_store_round_trip() {
+        let creds = SteamCredentials::new("session".to_string(), "token".to_string());
+
+        let path = temp_path("creds-passphrase");
+        let store = CredentialStore::with_passphrase(path.clone(), Some("hunter2".to_string()));
+        store.save(&creds).await.unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("token"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert_eq!(store.load().await.unwrap().as_string(), creds.as_string());
+
+        let wrong = CredentialStore::with_passphrase(path.clone(), Some("hunter3".to_string()));
+        assert!(matches!(
+            wrong.load().await,
+            Err(CredentialLoadSaveError::Decrypting)
+        ));
+
+        let store = store.rotate_key(&creds, None).await.unwrap();
+        assert_eq!(store.load().await.unwrap().as_string(), creds.as_string());
+        store.wipe().await.unwrap();
+        assert!(!path.exists() && !path.with_extension("key").exists());
+    }
+
+    #[tokio::test]
+    async fn test_store_rotates_key_file() {
+        let creds = SteamCredentials::new("session".to_string(), "token".to_string());
+        let path = temp_path("creds-rotate");
+        let key_path = path.with_extension("key");
+
+        let store = CredentialStore::with_passphrase(path.clone(), None);
+        store.save(&creds).await.unwrap();
+        let old_key = std::fs::read_to_string(&key_path).unwrap();
+
+        let store = store.rotate_key(&creds, None).await.unwrap();
+        assert_ne!(std::fs::read_to_string(&key_path).unwrap(), old_key);
+        assert!(!path.with_extension("key.new").exists());
+        assert_eq!(store.load().await.unwrap().as_string(), creds.as_string());
+        store.wipe().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_store_migrates_plaintext() {
+        let creds = SteamCredentials::new("session".to_string(), "token".to_string());
+        let path = temp_path("creds-plaintext");
+        std::fs::write(&path, serde_json::to_vec(&creds).unwrap()).unwrap();
+
+        let store = CredentialStore::with_passphrase(path.clone(), None);
+        assert_eq!(store.load().await.unwrap().as_string(), creds.as_string());
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("token"));
+        assert_eq!(store.load().await.unwrap().as_string(), creds.as_string());
+        store.wipe().await.unwrap();
+    }
+}
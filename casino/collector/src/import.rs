@@ -0,0 +1,262 @@
+//! Pulls Steam login cookies out of the places a browser can export them to,
+//! so players don't have to copy them out by hand.
+
+use std::io;
+use std::path::Path;
+
+use rusqlite::{Connection, OpenFlags};
+use serde::Deserialize;
+use steam::{CredentialParseError, SteamCredentials};
+use thiserror::Error;
+
+const STEAM_COMMUNITY_DOMAIN: &str = "steamcommunity.com";
+// Lines for HttpOnly cookies are prefixed like a comment in cookies.txt.
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+#[derive(Debug, Error)]
+pub enum CredentialImportError {
+    #[error("io error: {0}")]
+    IO(#[from] io::Error),
+    #[error("error reading firefox cookie database: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("error parsing HAR file: {0}")]
+    Har(#[from] serde_json::Error),
+    #[error("could not split curl command into arguments")]
+    CurlSyntax,
+    #[error("no cookies for {STEAM_COMMUNITY_DOMAIN} found")]
+    NoSteamCookies,
+    #[error("error parsing cookies: {0}")]
+    Parse(#[from] CredentialParseError),
+}
+
+fn is_steam_domain(domain: &str) -> bool {
+    let domain = domain.trim_start_matches('.');
+    domain == STEAM_COMMUNITY_DOMAIN || domain.ends_with(&format!(".{STEAM_COMMUNITY_DOMAIN}"))
+}
+
+fn is_steam_url(url: &str) -> bool {
+    url.split("://")
+        .nth(1)
+        .and_then(|rest| rest.split(['/', ':', '?']).next())
+        .map(is_steam_domain)
+        .unwrap_or(false)
+}
+
+fn from_pairs(pairs: Vec<(String, String)>) -> Result<SteamCredentials, CredentialImportError> {
+    if pairs.is_empty() {
+        return Err(CredentialImportError::NoSteamCookies);
+    }
+
+    Ok(SteamCredentials::try_from_cookies(pairs)?)
+}
+
+/// Reads cookies from a Firefox profile's `cookies.sqlite`, or the profile
+/// directory containing it.
+pub fn from_firefox(path: &Path) -> Result<SteamCredentials, CredentialImportError> {
+    let path = match path.is_dir() {
+        true => path.join("cookies.sqlite"),
+        false => path.to_path_buf(),
+    };
+
+    // Firefox holds a lock on the database while it's running, so open it as
+    // immutable to read it anyway.
+    let uri = format!("file:{}?immutable=1", path.display());
+    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI;
+    let conn = Connection::open_with_flags(uri, flags)?;
+
+    let mut stmt =
+        conn.prepare("SELECT host, name, value FROM moz_cookies ORDER BY lastAccessed")?;
+    let pairs = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .filter_map(|row| match row {
+            Ok((host, name, value)) if is_steam_domain(&host) => Some(Ok((name, value))),
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    from_pairs(pairs)
+}
+
+/// Reads cookies from a Netscape-format `cookies.txt`, as written by curl and
+/// most cookie-export browser extensions.
+pub fn from_cookies_txt(contents: &str) -> Result<SteamCredentials, CredentialImportError> {
+    let pairs = contents
+        .lines()
+        .map(|l| l.strip_prefix(HTTP_ONLY_PREFIX).unwrap_or(l))
+        .filter(|l| !l.starts_with('#'))
+        .filter_map(|l| {
+            let fields: Vec<&str> = l.trim_end_matches('\r').split('\t').collect();
+            match fields[..] {
+                [domain, _, _, _, _, name, value] if is_steam_domain(domain) => {
+                    Some((name.to_string(), value.to_string()))
+                }
+                _ => None,
+            }
+        })
+        .collect();
+
+    from_pairs(pairs)
+}
+
+/// Reads cookies from a request copied out of browser dev tools with "Copy as
+/// cURL" (the bash/POSIX flavour).
+pub fn from_curl(command: &str) -> Result<SteamCredentials, CredentialImportError> {
+    let command = command.replace("\\\r\n", " ").replace("\\\n", " ");
+    let args = shlex::split(&command).ok_or(CredentialImportError::CurlSyntax)?;
+
+    let mut cookie_strs = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-b" | "--cookie" => cookie_strs.extend(args.next().cloned()),
+            "-H" | "--header" => {
+                let header = args.next().and_then(|h| h.split_once(':'));
+                if let Some((name, value)) = header {
+                    if name.trim().eq_ignore_ascii_case("cookie") {
+                        cookie_strs.push(value.trim().to_string());
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    if cookie_strs.is_empty() {
+        return Err(CredentialImportError::NoSteamCookies);
+    }
+
+    Ok(SteamCredentials::try_from_cookie_str(
+        cookie_strs.join("; "),
+    )?)
+}
+
+#[derive(Deserialize)]
+struct Har {
+    log: HarLog,
+}
+
+#[derive(Deserialize)]
+struct HarLog {
+    entries: Vec<HarEntry>,
+}
+
+#[derive(Deserialize)]
+struct HarEntry {
+    request: HarRequest,
+}
+
+#[derive(Deserialize)]
+struct HarRequest {
+    url: String,
+    #[serde(default)]
+    cookies: Vec<HarCookie>,
+}
+
+#[derive(Deserialize)]
+struct HarCookie {
+    name: String,
+    value: String,
+}
+
+/// Reads cookies from the requests to Steam in a HAR export from browser dev
+/// tools. Later requests take precedence.
+pub fn from_har(contents: &str) -> Result<SteamCredentials, CredentialImportError> {
+    let har: Har = serde_json::from_str(contents)?;
+    let pairs = har
+        .log
+        .entries
+        .into_iter()
+        .filter(|e| is_steam_url(&e.request.url))
+        .flat_map(|e| e.request.cookies)
+        .map(|c| (c.name, c.value))
+        .collect();
+
+    from_pairs(pairs)
+}
+
+#[cfg(test)]
+mod test {
+    use rusqlite::Connection;
+
+    use super::{from_cookies_txt, from_curl, from_firefox, from_har};
+
+    const SESSION_ID: &str = "0123456789abcdef01234567";
+    const LOGIN_TOKEN: &str =
+        "76561198000000001%7C%7CeyJhbGciOiJFZERTQSJ9.eyJzdWIiOiI3NjU2MSJ9.c2ln";
+    const EXPECTED: &str = "sessionid=0123456789abcdef01234567; steamLoginSecure=76561198000000001%7C%7CeyJhbGciOiJFZERTQSJ9.eyJzdWIiOiI3NjU2MSJ9.c2ln";
+
+    #[test]
+    fn test_from_firefox() {
+        let path = std::env::temp_dir().join(format!("cookies-{}.sqlite", std::process::id()));
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(&format!(
+            "CREATE TABLE moz_cookies (host TEXT, name TEXT, value TEXT, lastAccessed INTEGER);
+             INSERT INTO moz_cookies VALUES ('.example.com', 'sessionid', 'notthisone', 3);
+             INSERT INTO moz_cookies VALUES ('steamcommunity.com', 'sessionid', '{SESSION_ID}', 1);
+             INSERT INTO moz_cookies VALUES ('steamcommunity.com', 'steamLoginSecure', '{LOGIN_TOKEN}', 2);"
+        ))
+        .unwrap();
+        drop(conn);
+
+        let creds = from_firefox(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(creds.unwrap().as_string(), EXPECTED);
+    }
+
+    #[test]
+    fn test_from_cookies_txt() {
+        let contents = format!(
+            "# Netscape HTTP Cookie File\n\
+             .example.com\tTRUE\t/\tFALSE\t0\tsessionid\tnotthisone\n\
+             steamcommunity.com\tFALSE\t/\tTRUE\t0\tsessionid\t{SESSION_ID}\n\
+             #HttpOnly_steamcommunity.com\tFALSE\t/\tTRUE\t0\tsteamLoginSecure\t{LOGIN_TOKEN}\n"
+        );
+
+        let creds = from_cookies_txt(&contents).unwrap();
+        assert_eq!(creds.as_string(), EXPECTED);
+    }
+
+    #[test]
+    fn test_from_curl() {
+        let command = format!(
+            "curl 'https://steamcommunity.com/id/mock/inventoryhistory/' \\\n  \
+             -H 'accept: text/html' \\\n  \
+             -b 'timezoneOffset=0,0; sessionid={SESSION_ID}; steamLoginSecure={LOGIN_TOKEN}' \\\n  \
+             --compressed"
+        );
+
+        let creds = from_curl(&command).unwrap();
+        assert_eq!(creds.as_string(), EXPECTED);
+
+        let command = format!(
+            "curl 'https://steamcommunity.com/' -H 'Cookie: sessionid={SESSION_ID}; steamLoginSecure={LOGIN_TOKEN}'"
+        );
+        let creds = from_curl(&command).unwrap();
+        assert_eq!(creds.as_string(), EXPECTED);
+    }
+
+    #[test]
+    fn test_from_har() {
+        let contents = format!(
+            r#"{{"log": {{"version": "1.2", "entries": [
+                {{"request": {{"method": "GET", "url": "https://store.steampowered.com/", "cookies": [
+                    {{"name": "sessionid", "value": "notthisone"}}
+                ]}}}},
+                {{"request": {{"method": "GET", "url": "https://steamcommunity.com/market/", "cookies": [
+                    {{"name": "sessionid", "value": "{SESSION_ID}"}},
+                    {{"name": "steamLoginSecure", "value": "{LOGIN_TOKEN}"}}
+                ]}}}}
+            ]}}}}"#
+        );
+
+        let creds = from_har(&contents).unwrap();
+        assert_eq!(creds.as_string(), EXPECTED);
+    }
+}
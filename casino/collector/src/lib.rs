@@ -1,35 +1,68 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use chrono::{DateTime, Utc};
 use reqwest::header::AUTHORIZATION;
 use reqwest::{Client, IntoUrl, Url};
-use tokio::time::interval;
+use tokio::fs;
+use tokio::io;
+use tokio::time::{interval, sleep, Instant};
 
 use steam::errors::FetchItemsError;
-use steam::{InventoryId, SteamClient, UnhydratedUnlock};
+use steam::{
+    FetchedHistory, HistoryBatch, HistorySource, InventoryId, RowDiagnostic, SessionState,
+    SteamClient, SteamCredentials, UnresolvedItem,
+};
 use thiserror::Error;
 
+use self::credentials::{prompt_for_credentials, CredentialPromptError, CredentialStore};
+
 pub mod config;
+pub mod credentials;
+pub mod import;
+
+// Wait between backfill pages, so we don't get rate-limited by Steam.
+const BACKFILL_PAGE_DELAY: Duration = Duration::from_secs(2);
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// How long before our Steam session expires to start warning about it.
+const EXPIRY_WARNING: Duration = Duration::from_secs(60 * 60);
+// How many polls to hold back newer events for, waiting for an unresolved
+// item to turn up in our inventory, before giving up on it.
+const MAX_UNRESOLVED_ATTEMPTS: u32 = 5;
 
 #[derive(Debug, Error)]
 #[error("given url was not valid: {0}")]
 pub struct UrlParseError(reqwest::Error);
 
-pub struct Collector {
+pub struct Collector<S> {
     collection_url: Url,
     http_client: Client,
-    steam_client: SteamClient,
+    source: S,
     auth_header: String,
 
     poll_interval: Duration,
+    // Set while Steam is rate-limiting us, so we skip polls until then.
+    next_poll_at: Option<Instant>,
     last_unboxing: Option<DateTime<Utc>>,
     last_known_item: Option<InventoryId>,
+    // History ids we have already sent which share the timestamp of
+    // last_unboxing. Steam only gives us minute precision, so these will be
+    // returned again until something newer happens.
+    last_seen_ids: HashSet<String>,
+    // How many polls each unresolved event has held back newer events for,
+    // by history id.
+    unresolved_attempts: HashMap<String, u32>,
+
+    session_check_interval: Duration,
+    credential_store: Option<CredentialStore>,
+    debug_dir: Option<PathBuf>,
 }
 
-impl Collector {
+impl<S: HistorySource> Collector<S> {
     pub async fn new<U>(
         collection_url: U,
-        steam_client: SteamClient,
+        source: S,
         pre_shared_key: String,
         poll_interval: Duration,
         start_time: Option<DateTime<Utc>>,
@@ -44,17 +77,44 @@ impl Collector {
         Ok(Self {
             collection_url,
             http_client,
-            steam_client,
+            source,
             auth_header,
 
             poll_interval,
+            next_poll_at: None,
             last_unboxing: start_time,
             last_known_item: None,
+            last_seen_ids: HashSet::new(),
+            unresolved_attempts: HashMap::new(),
+
+            session_check_interval: SESSION_CHECK_INTERVAL,
+            credential_store: None,
+            debug_dir: None,
         })
     }
 
+    /// Sets how often to check that our Steam session is still valid.
+    pub fn with_session_check_interval(mut self, interval: Duration) -> Self {
+        self.session_check_interval = interval;
+        self
+    }
+
+    /// Sets a credential store to reload from, and save to, when our Steam
+    /// session expires.
+    pub fn with_credential_store(mut self, credential_store: Option<CredentialStore>) -> Self {
+        self.credential_store = credential_store;
+        self
+    }
+
+    /// Sets a directory to save history rows we fail to parse to.
+    pub fn with_debug_dir(mut self, debug_dir: Option<PathBuf>) -> Self {
+        self.debug_dir = debug_dir;
+        self
+    }
+
     pub async fn run(&mut self) -> Result<(), CollectorError> {
         let mut tick = interval(self.poll_interval);
+        let mut session_tick = interval(self.session_check_interval);
         log::info!(
             "checking for new items every {} seconds",
             self.poll_interval.as_secs()
@@ -62,36 +122,282 @@ impl Collector {
 
         loop {
             tokio::select! {
-                _ = tick.tick() => self.poll().await?,
+                _ = tick.tick() => self.poll_or_back_off().await?,
+                _ = session_tick.tick() => self.check_session().await?,
                 _ = tokio::signal::ctrl_c() => return Ok(()),
             }
         }
     }
 
+    /// Polls for new items, waiting it out if Steam is rate-limiting us, or
+    /// logging in again if our session has expired, rather than giving up.
+    async fn poll_or_back_off(&mut self) -> Result<(), CollectorError> {
+        if let Some(at) = self.next_poll_at {
+            if Instant::now() < at {
+                log::debug!("still rate limited, skipping poll");
+                return Ok(());
+            }
+            self.next_poll_at = None;
+        }
+
+        match self.poll().await {
+            Err(CollectorError::FetchingItems(e)) if e.is_not_authenticated() => {
+                self.reauthenticate().await
+            }
+            Err(CollectorError::FetchingItems(e)) if e.rate_limited().is_some() => {
+                let wait = e
+                    .rate_limited()
+                    .and_then(|r| r.retry_after)
+                    .unwrap_or(self.poll_interval);
+                log::warn!("rate limited by steam, waiting {}s", wait.as_secs());
+                self.next_poll_at = Some(Instant::now() + wait);
+                Ok(())
+            }
+            res => res,
+        }
+    }
+
     async fn poll(&mut self) -> Result<(), CollectorError> {
         log::debug!("checking for new items");
         let since = self.last_unboxing.as_ref();
         log::debug!("last item timestamp: {since:?}");
         let last_item = self.last_known_item.as_ref();
-        let mut new_items = self
-            .steam_client
+        let fetched = self
+            .source
             .fetch_history_for_new_items(since, last_item)
             .await?;
+        self.report_problems(&fetched).await;
 
-        if new_items.is_empty() {
-            log::debug!("no new items");
-            return Ok(());
+        let mut new_items = fetched.batch;
+        new_items.remove_seen(&self.last_seen_ids);
+        if let Some(first) = self.hold_back_from(&fetched.unresolved) {
+            new_items.retain_before(&first);
         }
 
+        let last_at = match new_items.latest() {
+            Some(latest) => latest,
+            None => {
+                log::debug!("no new items");
+                return Ok(());
+            }
+        };
+
         self.send_results(&new_items).await?;
-        let last = new_items.remove(0);
-        self.last_unboxing = Some(last.at);
-        self.last_known_item = Some(last.inventory_id);
+        if self.last_unboxing != Some(last_at) {
+            self.last_seen_ids.clear();
+        }
+        self.last_seen_ids
+            .extend(new_items.history_ids_at(&last_at));
+        self.last_unboxing = Some(last_at);
+        if let Some(last_id) = new_items.latest_inventory_id() {
+            self.last_known_item = Some(last_id);
+        }
+
+        Ok(())
+    }
+
+    /// Finds the time of the first event we couldn't find the item for, but
+    /// may be able to next time, so we can hold back everything from it
+    /// rather than moving past it. Events which can't resolve, or which have
+    /// been held back too many times, are skipped instead.
+    fn hold_back_from(&mut self, unresolved: &[UnresolvedItem]) -> Option<DateTime<Utc>> {
+        let mut attempts = HashMap::new();
+        let mut first = None;
+        for item in unresolved.iter().filter(|u| u.reason.is_transient()) {
+            let tries = self
+                .unresolved_attempts
+                .get(&item.history_id)
+                .map_or(1, |n| n.saturating_add(1));
+            attempts.insert(item.history_id.clone(), tries);
+
+            if tries > MAX_UNRESOLVED_ATTEMPTS {
+                if tries == MAX_UNRESOLVED_ATTEMPTS + 1 {
+                    log::error!(
+                        "giving up on {} {} after {MAX_UNRESOLVED_ATTEMPTS} attempts",
+                        item.kind,
+                        item.history_id
+                    );
+                }
+                continue;
+            }
+
+            first = Some(first.map_or(item.at, |at: DateTime<Utc>| at.min(item.at)));
+        }
+
+        self.unresolved_attempts = attempts;
+        first
+    }
+
+    /// Checks that our session is still valid, letting the aggregator know
+    /// how it's doing.
+    async fn check_session(&mut self) -> Result<(), CollectorError> {
+        let state = match self.source.session_state().await {
+            Ok(state) => state,
+            Err(e) => {
+                log::warn!("error checking steam session: {e}");
+                return Ok(());
+            }
+        };
+        self.report_session(&state).await;
+
+        match state {
+            SessionState::Authenticated {
+                expires_at: Some(at),
+            } => {
+                // EXPIRY_WARNING is well within range
+                let warn_from = at - chrono::Duration::from_std(EXPIRY_WARNING).unwrap();
+                if Utc::now() >= warn_from && !self.refresh_session().await {
+                    log::warn!("steam session expires at {at}, please log in again soon");
+                }
+            }
+            SessionState::Authenticated { expires_at: None } => (),
+            SessionState::NotAuthenticated => self.reauthenticate().await?,
+        }
 
         Ok(())
     }
 
-    async fn send_results(&self, items: &[UnhydratedUnlock]) -> Result<(), ResultsSendError> {
+    /// Renews our session with the source's refresh token, if it has one,
+    /// saving the new credentials. Returns whether this worked.
+    async fn refresh_session(&mut self) -> bool {
+        let creds = match self.source.refresh_session().await {
+            Ok(Some(creds)) => creds,
+            Ok(None) => return false,
+            Err(e) => {
+                log::warn!("error refreshing steam session: {e}");
+                return false;
+            }
+        };
+        if !self.is_authenticated().await {
+            return false;
+        }
+
+        log::info!("refreshed steam session");
+        self.save_credentials(&creds).await;
+        true
+    }
+
+    async fn save_credentials(&self, creds: &SteamCredentials) {
+        if let Some(store) = self.credential_store.as_ref() {
+            if let Err(e) = store.save(creds).await {
+                log::warn!("error saving credentials to file: {}", e);
+            }
+        }
+    }
+
+    /// Logs in to Steam again once our session has expired, by refreshing it
+    /// if we can, then by reloading our credentials file in case it has been
+    /// updated, then by asking the user.
+    async fn reauthenticate(&mut self) -> Result<(), CollectorError> {
+        log::warn!("steam session is no longer valid");
+        self.report_session(&SessionState::NotAuthenticated).await;
+
+        if self.refresh_session().await {
+            return Ok(());
+        }
+
+        if let Some(store) = self.credential_store.as_ref() {
+            match store.load().await {
+                Ok(creds) => {
+                    self.source.set_credentials(creds);
+                    if self.is_authenticated().await {
+                        log::info!("reloaded credentials from {}", store.path().display());
+                        return Ok(());
+                    }
+                }
+                Err(e) => log::warn!("error reloading credentials: {e}"),
+            }
+        }
+
+        loop {
+            let creds = match prompt_for_credentials().await {
+                Ok(creds) => creds,
+                Err(CredentialPromptError::CredentialParse(e)) => {
+                    log::warn!("error parsing cookie: {}", e);
+                    continue;
+                }
+                Err(CredentialPromptError::IO(e)) => {
+                    return Err(CollectorError::PromptingForCredentials(e))
+                }
+            };
+
+            self.source.set_credentials(creds.clone());
+            if !self.is_authenticated().await {
+                log::warn!("authentication unsuccessful");
+                continue;
+            }
+
+            log::info!("authentication successful");
+            self.save_credentials(&creds).await;
+
+            return Ok(());
+        }
+    }
+
+    async fn is_authenticated(&self) -> bool {
+        match self.source.session_state().await {
+            Ok(state) => {
+                self.report_session(&state).await;
+                matches!(state, SessionState::Authenticated { .. })
+            }
+            Err(e) => {
+                log::warn!("error checking steam session: {e}");
+                false
+            }
+        }
+    }
+
+    /// Lets the aggregator know whether we're still logged in. This is only
+    /// informational, so failures are logged and ignored.
+    async fn report_session(&self, state: &SessionState) {
+        let url = match self.collection_url.join("session") {
+            Ok(url) => url,
+            Err(e) => {
+                log::warn!("error building session report url: {e}");
+                return;
+            }
+        };
+
+        let res = self
+            .http_client
+            .post(url)
+            .json(state)
+            .header(AUTHORIZATION, &self.auth_header)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        if let Err(e) = res {
+            log::warn!("error reporting session state: {e}");
+        }
+    }
+
+    async fn report_problems(&self, fetched: &FetchedHistory) {
+        for item in fetched.unresolved.iter() {
+            log::error!(
+                "not able to send {} {} (item {:?}): {}",
+                item.kind,
+                item.history_id,
+                item.item,
+                item.reason
+            );
+        }
+
+        for failure in fetched.failures.iter() {
+            let history_id = failure.history_id.as_deref().unwrap_or("unknown");
+            log::error!(
+                "failed to parse history row {history_id}: {}",
+                failure.error
+            );
+        }
+
+        if let Some(dir) = self.debug_dir.as_ref() {
+            if let Err(e) = save_diagnostics(dir, &fetched.failures).await {
+                log::warn!("error saving diagnostics to {}: {e}", dir.display());
+            }
+        }
+    }
+
+    async fn send_results(&self, items: &HistoryBatch) -> Result<(), ResultsSendError> {
         let data = serde_json::to_vec(items)?;
         log::info!(
             "sending {} new items to {}",
@@ -110,12 +416,59 @@ impl Collector {
     }
 }
 
+impl Collector<SteamClient> {
+    /// Uploads all history between now and the given time, then exits.
+    pub async fn backfill(&self, until: DateTime<Utc>) -> Result<(), CollectorError> {
+        log::info!("backfilling history until {until}");
+        let mut backfill = self.source.backfill(until);
+        let mut pages = 0;
+        let mut total = 0;
+
+        while let Some(fetched) = backfill.next_batch().await? {
+            pages += 1;
+            self.report_problems(&fetched).await;
+            if !fetched.batch.is_empty() {
+                total += fetched.batch.len();
+                self.send_results(&fetched.batch).await?;
+            }
+
+            log::debug!("backfilled {pages} pages of history");
+            sleep(BACKFILL_PAGE_DELAY).await;
+        }
+
+        log::info!("backfilled {total} items from {pages} pages of history");
+        Ok(())
+    }
+}
+
+/// Saves each failed row to its own file, so it can be used to fix the parser.
+async fn save_diagnostics(dir: &Path, failures: &[RowDiagnostic]) -> Result<(), io::Error> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+
+    fs::create_dir_all(dir).await?;
+    let now = Utc::now().timestamp_millis();
+    for (i, failure) in failures.iter().enumerate() {
+        let name = match failure.history_id.as_deref() {
+            Some(id) => format!("row-{id}.html"),
+            None => format!("row-unknown-{now}-{i}.html"),
+        };
+        let contents = format!("<!-- {} -->\n{}\n", failure.error, failure.html);
+        fs::write(dir.join(name), contents).await?;
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Error)]
 pub enum CollectorError {
     #[error("error fetching items: {0}")]
     FetchingItems(#[from] FetchItemsError),
     #[error("error sending results: {0}")]
     SendingResults(#[from] ResultsSendError),
+    #[error("error asking for new credentials: {0}")]
+    PromptingForCredentials(io::Error),
 }
 
 #[derive(Debug, Error)]
@@ -125,3 +478,294 @@ pub enum ResultsSendError {
     #[error("http error: {0}")]
     Transport(#[from] reqwest::Error),
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use axum::body::Bytes;
+    use axum::extract::State;
+    use axum::{routing, Router};
+    use chrono::{DateTime, TimeZone, Utc};
+    use mock_steam::Fixtures;
+    use steam::errors::{FetchItemsError, LocalPrepareError, LoginError};
+    use steam::{
+        EventKind, FetchedHistory, HistoryBatch, HistorySource, Id, InventoryId, RetryPolicy,
+        SteamAuthenticator, SteamClient, SteamCredentials, UnhydratedTransfer, UnresolvedItem,
+    };
+    use tokio::sync::mpsc;
+
+    use super::credentials::CredentialStore;
+    use super::{Collector, MAX_UNRESOLVED_ATTEMPTS};
+
+    /// A source which hands out history it has been given, in order.
+    #[derive(Default)]
+    struct FakeSource {
+        history: Mutex<VecDeque<FetchedHistory>>,
+    }
+
+    impl FakeSource {
+        fn with_history<I: IntoIterator<Item = FetchedHistory>>(history: I) -> Self {
+            Self {
+                history: Mutex::new(history.into_iter().collect()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl HistorySource for FakeSource {
+        async fn fetch_history_for_new_items(
+            &self,
+            _since: Option<&DateTime<Utc>>,
+            _last_item: Option<&InventoryId>,
+        ) -> Result<FetchedHistory, FetchItemsError> {
+            Ok(self.history.lock().unwrap().pop_front().unwrap_or_default())
+        }
+    }
+
+    fn trade(history_id: &str, at: DateTime<Utc>) -> UnhydratedTransfer {
+        UnhydratedTransfer {
+            kind: EventKind::Trade,
+            history_id: history_id.to_string(),
+            description: "You traded with someone".to_string(),
+            lost: Vec::new(),
+            gained: Vec::new(),
+            at,
+            name: "mock".to_string(),
+        }
+    }
+
+    fn unresolved(
+        history_id: &str,
+        reason: LocalPrepareError,
+        at: DateTime<Utc>,
+    ) -> UnresolvedItem {
+        UnresolvedItem {
+            kind: EventKind::Unbox,
+            history_id: history_id.to_string(),
+            item: InventoryId::new(100, 0),
+            reason,
+            at,
+        }
+    }
+
+    /// Looks up the mock user's profile on a mock Steam.
+    async fn mock_id(base: &str) -> Id {
+        Id::try_from_url_with_base(
+            "https://steamcommunity.com/id/mock",
+            base,
+            &RetryPolicy::default(),
+        )
+        .await
+        .unwrap()
+    }
+
+    /// A client for the mock user, with placeholder credentials.
+    async fn mock_client(base: &str) -> SteamClient {
+        let creds = SteamCredentials::new("session".to_string(), "token".to_string());
+        SteamClient::new(mock_id(base).await, creds)
+    }
+
+    /// A collector uploading to `upload_url`, with placeholder settings.
+    async fn collector<S: HistorySource>(upload_url: String, source: S) -> Collector<S> {
+        Collector::new(
+            upload_url,
+            source,
+            "key".to_string(),
+            Duration::from_secs(10),
+            None,
+        )
+        .await
+        .unwrap()
+    }
+
+    /// Serves an upload endpoint which passes on everything sent to it.
+    fn capture_uploads() -> (String, mpsc::UnboundedReceiver<Bytes>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let app = Router::new()
+            .route(
+                "/upload",
+                routing::post(
+                    |State(tx): State<mpsc::UnboundedSender<Bytes>>, body: Bytes| async move {
+                        tx.send(body).unwrap();
+                    },
+                ),
+            )
+            .with_state(tx);
+
+        let addr = ([127, 0, 0, 1], 0).into();
+        let server = axum::Server::bind(&addr).serve(app.into_make_service());
+        let url = format!("http://{}/upload", server.local_addr());
+        tokio::spawn(server);
+
+        (url, rx)
+    }
+
+    #[tokio::test]
+    async fn test_poll_holds_back_unresolved_events() {
+        let (upload_url, mut uploads) = capture_uploads();
+        let at = |minute| Utc.with_ymd_and_hms(2022, 1, 1, 0, minute, 0).unwrap();
+        let fetched = FetchedHistory {
+            batch: HistoryBatch {
+                transfers: vec![trade("1", at(1)), trade("3", at(3))],
+                ..Default::default()
+            },
+            unresolved: vec![unresolved("2", LocalPrepareError::NoAsset, at(2))],
+            failures: Vec::new(),
+        };
+        let mut collector = collector(upload_url, FakeSource::with_history([fetched])).await;
+
+        collector.poll().await.unwrap();
+
+        let uploaded = uploads.recv().await.unwrap();
+        let batch: HistoryBatch = serde_json::from_slice(&uploaded).unwrap();
+        assert_eq!(batch.transfers.len(), 1);
+        assert_eq!(batch.transfers[0].history_id, "1");
+        assert_eq!(collector.last_unboxing, Some(at(1)));
+    }
+
+    #[tokio::test]
+    async fn test_poll_skips_events_which_cannot_resolve() {
+        let (upload_url, mut uploads) = capture_uploads();
+        let at = |minute| Utc.with_ymd_and_hms(2022, 1, 1, 0, minute, 0).unwrap();
+        let fetched = FetchedHistory {
+            batch: HistoryBatch {
+                transfers: vec![trade("1", at(1)), trade("3", at(3))],
+                ..Default::default()
+            },
+            unresolved: vec![unresolved("2", LocalPrepareError::NoInspectLink, at(2))],
+            failures: Vec::new(),
+        };
+        let mut collector = collector(upload_url, FakeSource::with_history([fetched])).await;
+
+        collector.poll().await.unwrap();
+
+        let uploaded = uploads.recv().await.unwrap();
+        let batch: HistoryBatch = serde_json::from_slice(&uploaded).unwrap();
+        assert_eq!(batch.transfers.len(), 2);
+        assert_eq!(collector.last_unboxing, Some(at(3)));
+    }
+
+    #[tokio::test]
+    async fn test_poll_gives_up_on_unresolved_events() {
+        let (upload_url, mut uploads) = capture_uploads();
+        let at = |minute| Utc.with_ymd_and_hms(2022, 1, 1, 0, minute, 0).unwrap();
+        let fetched = || FetchedHistory {
+            batch: HistoryBatch {
+                transfers: vec![trade("3", at(3))],
+                ..Default::default()
+            },
+            unresolved: vec![unresolved("2", LocalPrepareError::NoAsset, at(2))],
+            failures: Vec::new(),
+        };
+        let history = (0..=MAX_UNRESOLVED_ATTEMPTS).map(|_| fetched());
+        let mut collector = collector(upload_url, FakeSource::with_history(history)).await;
+
+        for _ in 0..MAX_UNRESOLVED_ATTEMPTS {
+            collector.poll().await.unwrap();
+            assert_eq!(collector.last_unboxing, None);
+        }
+        collector.poll().await.unwrap();
+
+        let uploaded = uploads.recv().await.unwrap();
+        let batch: HistoryBatch = serde_json::from_slice(&uploaded).unwrap();
+        assert_eq!(batch.transfers.len(), 1);
+        assert_eq!(batch.transfers[0].history_id, "3");
+        assert_eq!(collector.last_unboxing, Some(at(3)));
+    }
+
+    #[tokio::test]
+    async fn test_backfill_against_mock_steam() {
+        let base = mock_steam::spawn(Fixtures::default());
+        let (upload_url, mut uploads) = capture_uploads();
+
+        let collector = collector(upload_url, mock_client(&base).await).await;
+
+        let until = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        collector.backfill(until).await.unwrap();
+
+        let uploaded = uploads.recv().await.unwrap();
+        let batch: HistoryBatch = serde_json::from_slice(&uploaded).unwrap();
+        assert_eq!(batch.unlocks.len(), 1);
+        assert_eq!(
+            batch.unlocks[0].item_market_name,
+            "AK-47 | Redline (Field-Tested)"
+        );
+        assert!(batch.unlocks[0]
+            .item_market_link
+            .contains("S76561198000000001A1000D"));
+
+        let traits = &batch.unlocks[0].item_traits;
+        assert_eq!(traits.rarity.as_deref(), Some("Classified"));
+        assert_eq!(traits.rarity_color.as_deref(), Some("d32ce6"));
+        assert_eq!(traits.exterior.as_deref(), Some("Field-Tested"));
+        assert_eq!(traits.collection.as_deref(), Some("The Phoenix Collection"));
+    }
+
+    #[tokio::test]
+    async fn test_backfill_items_no_longer_in_inventory() {
+        let base = mock_steam::spawn(Fixtures {
+            inventory: r#"{"success": 1, "total_inventory_count": 0}"#.to_string(),
+            ..Default::default()
+        });
+        let (upload_url, mut uploads) = capture_uploads();
+
+        let collector = collector(upload_url, mock_client(&base).await).await;
+
+        let until = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        collector.backfill(until).await.unwrap();
+
+        let uploaded = uploads.recv().await.unwrap();
+        let batch: HistoryBatch = serde_json::from_slice(&uploaded).unwrap();
+        assert_eq!(batch.unlocks.len(), 1);
+        assert_eq!(batch.unlocks[0].name, "mock");
+        assert!(batch.unlocks[0]
+            .item_market_link
+            .contains("S76561198000000001A1000D"));
+        assert_eq!(
+            batch.unlocks[0].item_traits.rarity.as_deref(),
+            Some("Classified")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_login_and_refresh_against_mock_steam() {
+        let base = mock_steam::spawn(Fixtures::default());
+        let (upload_url, _uploads) = capture_uploads();
+        let authenticator = SteamAuthenticator::new().with_base_url(&base);
+
+        let mut session = authenticator.begin("mock", "hunter2").await.unwrap();
+        assert!(matches!(
+            authenticator.submit_code(&session, "WRONG").await,
+            Err(LoginError::Rejected(_))
+        ));
+        authenticator
+            .submit_code(&session, mock_steam::GUARD_CODE)
+            .await
+            .unwrap();
+        let creds = authenticator
+            .wait_for_tokens(&mut session)
+            .await
+            .unwrap()
+            .into_credentials();
+
+        let client =
+            SteamClient::new(mock_id(&base).await, creds).with_authenticator(authenticator);
+        let creds_path = std::env::temp_dir().join(format!("creds-{}.json", std::process::id()));
+        let store = CredentialStore::with_passphrase(creds_path, None);
+        let mut collector = collector(upload_url, client)
+            .await
+            .with_credential_store(Some(store.clone()));
+
+        collector.reauthenticate().await.unwrap();
+
+        let saved = store.load().await;
+        store.wipe().await.unwrap();
+        let saved = saved.unwrap();
+        assert_eq!(saved.refresh_token(), Some("mock-refresh-token"));
+        assert!(saved.expires_at().is_some());
+    }
+}
@@ -1,17 +1,26 @@
 use std::num::ParseIntError;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::time::Duration;
 
-use chrono::Utc;
-use clap::Parser;
-use collector::config::{Config, ConfigLoadError};
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use collector::config::{Config, ConfigLoadError, SourceConfig};
+use collector::credentials::{
+    prompt_for_credentials, prompt_line, prompt_password, CredentialLoadSaveError,
+    CredentialPromptError, CredentialStore,
+};
+use collector::import::{self, CredentialImportError};
 use collector::{Collector, CollectorError, UrlParseError};
 use reqwest::Url;
-use steam::errors::AuthenticationCheckError;
-use steam::{CredentialParseError, Id, IdUrlParseError, SteamClient, SteamCredentials};
+use steam::diagnose::diagnose;
+use steam::errors::{AuthenticationCheckError, LoginError, RequestError};
+use steam::{
+    EventKind, GuardType, Id, IdUrlParseError, Language, RetryPolicy, SteamAuthenticator,
+    SteamClient, SteamCredentials, WebApiClient,
+};
 use thiserror::Error;
 use tokio::fs;
-use tokio::io::{self, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{self, AsyncReadExt};
 
 #[tokio::main]
 async fn main() {
@@ -35,11 +44,102 @@ struct Args {
     /// Path to credentials storage file
     #[arg(short = 'x', long, env, default_value = "./.creds.json")]
     credentials_path: PathBuf,
+    /// Passphrase to encrypt stored credentials with. If not given, a random
+    /// key is kept in a file next to them
+    #[arg(long, env, hide_env_values = true)]
+    credentials_passphrase: Option<String>,
     /// Path to configuration file
     config_path: PathBuf,
     /// Level to log at
     #[arg(short, long, env, default_value = "info")]
     log_level: log::LevelFilter,
+    /// Number of times to retry a request Steam rejects before giving up
+    #[arg(long, env, default_value = "3")]
+    max_retries: u32,
+    /// Longest time to back off for before retrying a request
+    #[arg(long, env, default_value = "60s")]
+    max_backoff: humantime::Duration,
+    /// Interval to check that the Steam session is still valid
+    #[arg(long, env, default_value = "5m")]
+    session_check_interval: humantime::Duration,
+    /// Directory to save history rows which could not be parsed to
+    #[arg(long, env)]
+    debug_dir: Option<PathBuf>,
+    /// Base URL of the Steam Community site
+    #[arg(long, env, default_value = "https://steamcommunity.com")]
+    steam_community_url: String,
+    /// Base URL of the Steam Web API
+    #[arg(long, env, default_value = "https://api.steampowered.com")]
+    steam_api_url: String,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Upload all history since the given time, then exit
+    Backfill {
+        /// Time to backfill to, in RFC 3339 format
+        #[arg(long)]
+        since: DateTime<Utc>,
+    },
+    /// Check how well the Inventory History page can still be parsed
+    Diagnose {
+        /// Saved Inventory History page to check, instead of fetching it
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Import Steam login cookies exported from a browser, then exit
+    Import {
+        #[command(subcommand)]
+        source: ImportSource,
+    },
+    /// Log in to Steam with a username and password, then exit
+    Login {
+        /// Steam account name to log in with
+        #[arg(long, env = "STEAM_USERNAME")]
+        username: String,
+        /// Steam password, asked for if not given
+        #[arg(long, env = "STEAM_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// Manage the stored Steam session, then exit
+    Creds {
+        #[command(subcommand)]
+        action: CredsAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum CredsAction {
+    /// Show what is stored, without revealing the session cookie
+    Show {
+        /// Print the session cookie as well
+        #[arg(long)]
+        reveal: bool,
+    },
+    /// Renew the stored session if possible, and re-encrypt it under a new key
+    Rotate {
+        /// Passphrase to encrypt with from now on, instead of the current one
+        #[arg(long, env, hide_env_values = true)]
+        new_passphrase: Option<String>,
+    },
+    /// Securely delete the stored session and its key file
+    Wipe,
+}
+
+#[derive(Subcommand)]
+enum ImportSource {
+    /// From a Firefox profile directory, or the cookies.sqlite inside it
+    Firefox { path: PathBuf },
+    /// From a Netscape-format cookies.txt
+    CookiesTxt { path: PathBuf },
+    /// From a request copied with "Copy as cURL", read from stdin if no file
+    /// is given
+    Curl { path: Option<PathBuf> },
+    /// From a HAR export of the browser's network requests
+    Har { path: PathBuf },
 }
 
 async fn main_result() -> Result<(), MainError> {
@@ -48,17 +148,117 @@ async fn main_result() -> Result<(), MainError> {
     logging::init(args.log_level);
 
     let cfg = Config::try_from_path(args.config_path).await?;
+    let store = CredentialStore::with_passphrase(
+        &args.credentials_path,
+        args.credentials_passphrase.clone(),
+    );
 
-    let id = Id::try_from_url(&cfg.steam_profile_url).await?;
+    match &args.command {
+        Some(Command::Creds {
+            action: CredsAction::Show { reveal },
+        }) => return show_credentials(&store, *reveal).await,
+        Some(Command::Creds {
+            action: CredsAction::Wipe,
+        }) => {
+            store.wipe().await.map_err(MainError::WipingCredentials)?;
+            log::info!("wiped credentials in {}", store.path().display());
+            return Ok(());
+        }
+        _ => (),
+    }
+
+    if let Some(Command::Diagnose { file: Some(file) }) = &args.command {
+        let page = fs::read_to_string(file).await?;
+        return report_diagnosis(&page, cfg.language);
+    }
 
-    let client = prepare_client(id, AsRef::as_ref(&args.credentials_path)).await?;
+    let retry_policy = RetryPolicy {
+        max_retries: args.max_retries,
+        max_backoff: *args.max_backoff,
+        ..Default::default()
+    };
 
     let now = Utc::now();
     let delta = chrono::Duration::from_std(Duration::from_secs(60 * 10)).unwrap();
     let start = now - delta;
     let st = Some(start);
 
-    Collector::new(
+    if let SourceConfig::WebApi { api_key } = cfg.source {
+        if args.command.is_some() {
+            return Err(MainError::UnsupportedBySource);
+        }
+
+        if !cfg.event_kinds.contains(&EventKind::Trade) {
+            return Err(MainError::NoEventKindsForSource);
+        }
+        if cfg.event_kinds.iter().any(|k| *k != EventKind::Trade) {
+            log::warn!("the web api source only reports trades, ignoring other event kinds");
+        }
+        let source = WebApiClient::new(api_key)
+            .with_base_url(args.steam_api_url)
+            .with_event_kinds([EventKind::Trade])
+            .with_retry_policy(retry_policy);
+        Collector::new(
+            args.collection_url,
+            source,
+            cfg.pre_shared_key,
+            *args.poll_interval,
+            st,
+        )
+        .await?
+        .with_debug_dir(args.debug_dir)
+        .run()
+        .await?;
+
+        return Ok(());
+    }
+
+    let id = Id::try_from_url_with_base(
+        &cfg.steam_profile_url,
+        &args.steam_community_url,
+        &retry_policy,
+    )
+    .await?;
+    let authenticator = SteamAuthenticator::new().with_base_url(&args.steam_api_url);
+
+    let new_creds = match &args.command {
+        Some(Command::Import { source }) => Some(import_credentials(source).await?),
+        Some(Command::Login { username, password }) => {
+            let creds = login(&authenticator, username, password.clone()).await?;
+            Some(creds)
+        }
+        _ => None,
+    };
+    if let Some(creds) = new_creds {
+        let client = SteamClient::new(id, creds.clone()).with_retry_policy(retry_policy);
+        if !client.is_authenticated().await? {
+            return Err(MainError::NewCredentialsNotAuthenticated);
+        }
+
+        store.save(&creds).await?;
+        log::info!("credentials saved to {}", store.path().display());
+        return Ok(());
+    }
+
+    if let Some(Command::Creds {
+        action: CredsAction::Rotate { new_passphrase },
+    }) = args.command
+    {
+        let passphrase = new_passphrase.or(args.credentials_passphrase);
+        return rotate_credentials(id, store, passphrase, authenticator).await;
+    }
+
+    let client = prepare_client(id, &store, retry_policy, authenticator)
+        .await?
+        .with_event_kinds(cfg.event_kinds)
+        .with_language(cfg.language);
+
+    if let Some(Command::Diagnose { file: None }) = &args.command {
+        let page = client.fetch_history_html().await?;
+        return report_diagnosis(&page, cfg.language);
+    }
+
+    let mut collector = Collector::new(
         args.collection_url,
         client,
         cfg.pre_shared_key,
@@ -66,12 +266,34 @@ async fn main_result() -> Result<(), MainError> {
         st,
     )
     .await?
-    .run()
-    .await?;
+    .with_session_check_interval(*args.session_check_interval)
+    .with_credential_store(Some(store))
+    .with_debug_dir(args.debug_dir);
+
+    match args.command {
+        Some(Command::Backfill { since }) => collector.backfill(since).await?,
+        Some(
+            Command::Diagnose { .. }
+            | Command::Import { .. }
+            | Command::Login { .. }
+            | Command::Creds { .. },
+        ) => unreachable!("handled above"),
+        None => collector.run().await?,
+    }
 
     Ok(())
 }
 
+fn report_diagnosis(page: &str, language: Language) -> Result<(), MainError> {
+    let diagnosis = diagnose(page, language);
+    print!("{diagnosis}");
+
+    match diagnosis.is_healthy() {
+        true => Ok(()),
+        false => Err(MainError::Unhealthy),
+    }
+}
+
 #[derive(Debug, Error)]
 enum MainError {
     #[error("{0}")]
@@ -88,6 +310,154 @@ enum MainError {
     InvalidIntervalValue(#[from] ParseIntError),
     #[error("error running main loop: {0}")]
     RunningCollector(#[from] CollectorError),
+    #[error("error reading history page: {0}")]
+    ReadingPage(#[from] io::Error),
+    #[error("error fetching history page: {0}")]
+    FetchingPage(#[from] RequestError),
+    #[error("history page could not be fully parsed")]
+    Unhealthy,
+    #[error("this command is only supported by the community history source")]
+    UnsupportedBySource,
+    #[error("none of the configured event kinds are supported by this source")]
+    NoEventKindsForSource,
+    #[error("error importing credentials: {0}")]
+    ImportingCredentials(#[from] CredentialImportError),
+    #[error("error checking imported credentials: {0}")]
+    CheckingCredentials(#[from] AuthenticationCheckError),
+    #[error("new credentials are not logged in to steam")]
+    NewCredentialsNotAuthenticated,
+    #[error("error logging in to steam: {0}")]
+    LoggingIn(#[from] LoginError),
+    #[error("error prompting for login details: {0}")]
+    PromptingForLogin(io::Error),
+    #[error("error loading or saving credentials: {0}")]
+    StoringCredentials(#[from] CredentialLoadSaveError),
+    #[error("error wiping credentials: {0}")]
+    WipingCredentials(io::Error),
+    #[error("no credentials stored in {0}")]
+    NoStoredCredentials(PathBuf),
+}
+
+async fn import_credentials(
+    source: &ImportSource,
+) -> Result<SteamCredentials, CredentialImportError> {
+    let creds = match source {
+        ImportSource::Firefox { path } => import::from_firefox(path)?,
+        ImportSource::CookiesTxt { path } => {
+            import::from_cookies_txt(&fs::read_to_string(path).await?)?
+        }
+        ImportSource::Curl { path: Some(path) } => {
+            import::from_curl(&fs::read_to_string(path).await?)?
+        }
+        ImportSource::Curl { path: None } => {
+            let mut command = String::new();
+            io::stdin().read_to_string(&mut command).await?;
+            import::from_curl(&command)?
+        }
+        ImportSource::Har { path } => import::from_har(&fs::read_to_string(path).await?)?,
+    };
+
+    Ok(creds)
+}
+
+async fn show_credentials(store: &CredentialStore, reveal: bool) -> Result<(), MainError> {
+    if !store.exists() {
+        return Err(MainError::NoStoredCredentials(store.path().to_path_buf()));
+    }
+    let creds = store.load().await?;
+
+    println!("path: {}", store.path().display());
+    println!("encrypted with: {}", store.key_kind());
+    match creds.expires_at() {
+        Some(at) => println!("session expires: {at}"),
+        None => println!("session expires: unknown"),
+    }
+    let refreshable = if creds.refresh_token().is_some() {
+        "yes"
+    } else {
+        "no"
+    };
+    println!("can be refreshed: {refreshable}");
+    if reveal {
+        println!("cookie: {}", creds.as_string());
+    }
+
+    Ok(())
+}
+
+async fn rotate_credentials(
+    id: Id,
+    store: CredentialStore,
+    passphrase: Option<String>,
+    authenticator: SteamAuthenticator,
+) -> Result<(), MainError> {
+    if !store.exists() {
+        return Err(MainError::NoStoredCredentials(store.path().to_path_buf()));
+    }
+
+    let creds = store.load().await?;
+    let mut client = SteamClient::new(id, creds.clone()).with_authenticator(authenticator);
+    let creds = match client.refresh_session().await? {
+        Some(creds) => {
+            log::info!("renewed steam session");
+            creds
+        }
+        None => {
+            log::info!("stored session can't be renewed, only re-encrypting it");
+            creds
+        }
+    };
+
+    let store = store.rotate_key(&creds, passphrase).await?;
+    log::info!(
+        "credentials in {} re-encrypted with a new {}",
+        store.path().display(),
+        store.key_kind()
+    );
+
+    Ok(())
+}
+
+async fn login(
+    authenticator: &SteamAuthenticator,
+    username: &str,
+    password: Option<String>,
+) -> Result<SteamCredentials, MainError> {
+    let password = match password {
+        Some(password) => password,
+        None => prompt_password("Steam password: ")
+            .await
+            .map_err(MainError::PromptingForLogin)?,
+    };
+    let mut session = authenticator.begin(username, &password).await?;
+
+    match session.code_needed() {
+        Some(guard_type) => loop {
+            let prompt = match guard_type {
+                GuardType::EmailCode => "Steam Guard code sent to your email: ",
+                _ => "Steam Guard code from the mobile app: ",
+            };
+            let code = prompt_line(prompt)
+                .await
+                .map_err(MainError::PromptingForLogin)?;
+            match authenticator.submit_code(&session, &code).await {
+                Ok(()) => break,
+                Err(LoginError::Rejected(eresult)) => {
+                    log::warn!("steam guard code rejected (eresult {eresult})");
+                }
+                Err(e) => return Err(e.into()),
+            }
+        },
+        None if !session.confirmations().is_empty() => {
+            log::info!("waiting for the login to be approved in the steam app or by email");
+        }
+        None => (),
+    }
+
+    let tokens = authenticator.wait_for_tokens(&mut session).await?;
+    log::info!("logged in as {}", tokens.steam_id);
+
+    Ok(tokens.into_credentials())
 }
 
 #[derive(Debug, Error)]
@@ -98,25 +468,46 @@ enum ClientPrepareError {
     Prompt(#[from] CredentialPromptError),
     #[error("error checking for authentication: {0}")]
     AuthCheck(#[from] AuthenticationCheckError),
+    #[error("error loading credentials: {0}")]
+    Load(#[from] CredentialLoadSaveError),
 }
 
-async fn prepare_client(id: Id, creds_path: &Path) -> Result<SteamClient, ClientPrepareError> {
-    if creds_path.exists() {
-        let creds = match load_credentials_from_file(creds_path).await {
+async fn prepare_client(
+    id: Id,
+    store: &CredentialStore,
+    retry_policy: RetryPolicy,
+    authenticator: SteamAuthenticator,
+) -> Result<SteamClient, ClientPrepareError> {
+    if store.exists() {
+        let creds = match store.load().await {
             Ok(creds) => Some(creds),
-            Err(CredentialLoadSaveError::IO(e)) => return Err(e.into()),
             Err(CredentialLoadSaveError::Parse(e)) => {
                 log::warn!("error parsing credentials: {}", e);
-                fs::remove_file(creds_path).await?;
+                store.wipe().await?;
                 None
             }
+            Err(e) => return Err(e.into()),
         };
 
         if let Some(creds) = creds {
-            let client = SteamClient::new(id.clone(), creds);
+            let mut client = SteamClient::new(id.clone(), creds)
+                .with_retry_policy(retry_policy.clone())
+                .with_authenticator(authenticator.clone());
             if client.is_authenticated().await? {
                 return Ok(client);
             }
+
+            match client.refresh_session().await {
+                Ok(Some(creds)) if client.is_authenticated().await? => {
+                    log::info!("refreshed steam session");
+                    if let Err(e) = store.save(&creds).await {
+                        log::warn!("error saving credentials to file: {}", e);
+                    }
+                    return Ok(client);
+                }
+                Ok(_) => (),
+                Err(e) => log::warn!("error refreshing steam session: {e}"),
+            }
         }
     }
 
@@ -130,14 +521,16 @@ async fn prepare_client(id: Id, creds_path: &Path) -> Result<SteamClient, Client
             Err(CredentialPromptError::IO(e)) => return Err(e.into()),
         };
 
-        let client = SteamClient::new(id.clone(), creds.clone());
+        let client = SteamClient::new(id.clone(), creds.clone())
+            .with_retry_policy(retry_policy.clone())
+            .with_authenticator(authenticator.clone());
         if !client.is_authenticated().await? {
             log::warn!("authentication unsuccessful");
             continue;
         }
 
         log::info!("authentication successful");
-        if let Err(e) = save_credentials_to_file(creds_path, &creds).await {
+        if let Err(e) = store.save(&creds).await {
             log::warn!("error saving credentials to file: {}", e);
             log::warn!("continuing without saving, you will need to enter these again next time");
         }
@@ -145,55 +538,3 @@ async fn prepare_client(id: Id, creds_path: &Path) -> Result<SteamClient, Client
         return Ok(client);
     }
 }
-
-#[derive(Debug, Error)]
-enum CredentialPromptError {
-    #[error("error parsing credentials: {0}")]
-    CredentialParse(#[from] CredentialParseError),
-    #[error("io error: {0}")]
-    IO(#[from] io::Error),
-}
-
-async fn prompt_for_credentials() -> Result<SteamCredentials, CredentialPromptError> {
-    let mut stdin = BufReader::new(io::stdin());
-    let mut stdout = io::stdout();
-
-    stdout.flush().await?;
-    stdout
-        .write_all("Please enter steam cookie:\n>".as_bytes())
-        .await?;
-    let mut buf = String::new();
-    stdin.read_line(&mut buf).await?;
-
-    let creds = SteamCredentials::try_from_cookie_str(buf.as_str())?;
-
-    Ok(creds)
-}
-
-#[derive(Debug, Error)]
-enum CredentialLoadSaveError {
-    #[error("error parsing json: {0}")]
-    Parse(#[from] serde_json::Error),
-    #[error("io error: {0}")]
-    IO(#[from] io::Error),
-}
-
-async fn load_credentials_from_file(p: &Path) -> Result<SteamCredentials, CredentialLoadSaveError> {
-    let mut f = fs::File::open(p).await?;
-    let mut buf: Vec<u8> = Vec::new();
-    f.read_to_end(&mut buf).await?;
-    let parsed = serde_json::from_slice(&buf)?;
-
-    Ok(parsed)
-}
-
-async fn save_credentials_to_file(
-    p: &Path,
-    creds: &SteamCredentials,
-) -> Result<(), CredentialLoadSaveError> {
-    let encoded = serde_json::to_vec(creds)?;
-    let mut f = fs::File::create(p).await?;
-    f.write_all(&encoded).await?;
-
-    Ok(())
-}
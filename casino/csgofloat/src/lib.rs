@@ -4,14 +4,55 @@ use std::sync::Arc;
 use bb8_redis::bb8::Pool;
 use bb8_redis::redis::{IntoConnectionInfo, RedisError};
 use bb8_redis::RedisConnectionManager;
+use futures_util::{stream, StreamExt};
 use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
-use reqwest::{Body, Client, StatusCode};
+use reqwest::{Body, Client, RequestBuilder, StatusCode};
 use serde::{Deserialize, Serialize};
+use serde_repr::Deserialize_repr;
 use thiserror::Error;
 
 use cache::Cache;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+mod retry;
+use retry::RetryPolicy;
+
+const CSGOFLOAT_URL: &str = "https://api.csgofloat.com";
+
+/// How we authenticate with an inspect service.
+#[derive(Clone, Debug)]
+pub enum Auth {
+    /// Send nothing, as self-hosted inspect servers usually expect.
+    None,
+    /// Send the key as-is in the Authorization header, as CSGOFloat expects.
+    Key(String),
+    /// Send the token as a bearer token in the Authorization header.
+    Bearer(String),
+}
+
+impl From<String> for Auth {
+    fn from(key: String) -> Self {
+        Auth::Key(key)
+    }
+}
+
+impl From<&str> for Auth {
+    fn from(key: &str) -> Self {
+        Auth::Key(key.to_string())
+    }
+}
+
+impl Auth {
+    fn apply(&self, req: RequestBuilder) -> RequestBuilder {
+        match self {
+            Auth::None => req,
+            Auth::Key(key) => req.header(AUTHORIZATION, key),
+            Auth::Bearer(token) => req.bearer_auth(token),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Sticker {
     #[serde(alias = "stickerId")]
     sticker_id: u32,
@@ -21,12 +62,28 @@ pub struct Sticker {
     name: String,
 }
 
-#[derive(Serialize, Deserialize)]
-struct FloatItemResponse {
-    pub iteminfo: ItemDescription,
+/// Self-hosted inspect servers may return the item on its own, rather than
+/// under `iteminfo` as CSGOFloat does.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FloatItemResponse {
+    Wrapped { iteminfo: ItemDescription },
+    Bare(ItemDescription),
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+impl From<FloatItemResponse> for ItemDescription {
+    fn from(resp: FloatItemResponse) -> Self {
+        match resp {
+            FloatItemResponse::Wrapped { iteminfo } => iteminfo,
+            FloatItemResponse::Bare(item) => item,
+        }
+    }
+}
+
+// Self-hosted inspect servers leave out the fields they can't fill in, such
+// as names when they have no game files.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ItemDescription {
     origin: u32,
     quality: u32,
@@ -34,12 +91,12 @@ pub struct ItemDescription {
     a: String,
     d: String,
     #[serde(alias = "paintseed")]
-    paint_seed: u32,
+    paint_seed: Option<u32>,
     #[serde(alias = "defindex")]
     def_index: u32,
     stickers: Vec<Sticker>,
     #[serde(alias = "floatvalue")]
-    float_value: f32,
+    float_value: Option<f32>,
     s: String,
     m: String,
     #[serde(alias = "imageurl")]
@@ -55,7 +112,34 @@ pub struct ItemDescription {
     full_item_name: String,
 }
 
-#[derive(Debug, Error)]
+impl ItemDescription {
+    /// A description of an item made only from what Steam tells us, for when
+    /// we can't ask CSGOFloat. It has no float, paint seed or stickers.
+    pub fn partial(market_name: &str, rarity_name: Option<&str>, wear_name: Option<&str>) -> Self {
+        // Market names look like "StatTrak™ AK-47 | Redline (Field-Tested)".
+        let (quality_name, name) = ["StatTrak™ ", "Souvenir "]
+            .into_iter()
+            .find_map(|p| Some((p.trim(), market_name.strip_prefix(p)?)))
+            .unwrap_or(("Unique", market_name));
+        let (name, market_wear) = match name.rsplit_once(" (") {
+            Some((n, w)) if w.ends_with(')') => (n, Some(w.trim_end_matches(')'))),
+            _ => (name, None),
+        };
+        let (weapon_type, item_name) = name.split_once(" | ").unwrap_or((name, ""));
+
+        Self {
+            weapon_type: weapon_type.to_string(),
+            item_name: item_name.to_string(),
+            rarity_name: rarity_name.unwrap_or_default().to_string(),
+            quality_name: quality_name.to_string(),
+            wear_name: wear_name.or(market_wear).map(String::from),
+            full_item_name: market_name.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error, Deserialize_repr)]
 #[repr(u8)]
 pub enum CsgoFloatError {
     #[error("Improper parameter structure")]
@@ -76,16 +160,70 @@ pub enum CsgoFloatError {
     BadSecret = 8,
 }
 
+impl CsgoFloatError {
+    /// Whether the same lookup might succeed if we try again later.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::TooManyPendingRequests | Self::ValveServerTimeout | Self::ValveOffline
+        )
+    }
+}
+
+/// The body CSGOFloat responds with when it can't look an item up.
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    code: CsgoFloatError,
+    #[allow(dead_code)]
+    error: String,
+}
+
 #[derive(Debug, Error)]
 pub enum CsgoFloatFetchError {
-    #[error("error from api: {0}")]
-    CsgoFloat(#[from] CsgoFloatError),
+    #[error("error from api ({status}): {error}")]
+    CsgoFloat {
+        status: StatusCode,
+        error: CsgoFloatError,
+    },
+    #[error("unexpected response from api ({status}): {body}")]
+    UnexpectedStatus { status: StatusCode, body: String },
     #[error("http error: {0}")]
     Transport(#[from] reqwest::Error),
     #[error("deserialisation error: {0}")]
     Deserializing(#[from] serde_json::Error),
     #[error("error parsing steam url: {0}")]
     SteamURLFormat(#[from] SteamURLParseError),
+    #[error("error looking up item: {0}")]
+    Lookup(String),
+    #[error("item missing from bulk response")]
+    MissingFromResponse,
+}
+
+impl CsgoFloatFetchError {
+    /// Whether the same lookup might succeed if we try again later.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::CsgoFloat { error, .. } => error.is_transient(),
+            Self::UnexpectedStatus { status, .. } => {
+                *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            Self::Transport(e) => e.is_timeout() || e.is_connect(),
+            Self::MissingFromResponse => true,
+            _ => false,
+        }
+    }
+
+    /// Decodes an error response, which should carry one of CSGOFloat's
+    /// error codes.
+    fn from_response(status: StatusCode, body: String) -> Self {
+        match serde_json::from_str::<ErrorBody>(&body) {
+            Ok(ErrorBody { code, .. }) => Self::CsgoFloat {
+                status,
+                error: code,
+            },
+            Err(_) => Self::UnexpectedStatus { status, body },
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -98,25 +236,30 @@ pub enum SteamURLParseError {
 
 pub async fn get_by_market_url(
     client: &Client,
-    key: &str,
+    base_url: &str,
+    auth: &Auth,
     market_url: &str,
 ) -> Result<ItemDescription, CsgoFloatFetchError> {
-    let url = format!("https://api.csgofloat.com?url={}", market_url);
-    let resp = client.get(&url).header(AUTHORIZATION, key).send().await?;
+    let url = format!("{}?url={}", base_url, market_url);
+    let resp = auth.apply(client.get(&url)).send().await?;
 
     match resp.status() {
         StatusCode::OK => {
             let data = resp.bytes().await?;
             let data: FloatItemResponse = serde_json::from_slice(&data)?;
-            Ok(data.iteminfo)
+            Ok(data.into())
         }
         status => {
-            log::error!("CSGOFloat responded with error status {}", status);
-            resp.json().await.map_err(|e| e.into())
+            let body = resp.text().await?;
+            Err(CsgoFloatFetchError::from_response(status, body))
         }
     }
 }
 
+/// The most links CSGOFloat will look up in a single bulk request.
+pub const BULK_LIMIT: usize = 50;
+const DEFAULT_CONCURRENCY: usize = 4;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct BulkRequestItem {
     pub link: String,
@@ -127,54 +270,97 @@ struct BulkRequest {
     pub links: Vec<BulkRequestItem>,
 }
 
+/// An entry in a bulk response: either the item, or why it couldn't be
+/// looked up.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BulkResponseItem {
+    // Tried first, as every field of an item is optional.
+    Failed(ErrorBody),
+    // Errors with codes we don't know about.
+    FailedOther { error: String },
+    Item(Box<ItemDescription>),
+}
+
+/// CSGOFloat keys bulk responses by the asset id in each inspect link.
+fn asset_id(url: &str) -> Result<&str, SteamURLParseError> {
+    url.split('A')
+        .nth(1)
+        .ok_or(SteamURLParseError::MissingAssetMarker)?
+        .split('D')
+        .next()
+        .ok_or(SteamURLParseError::MissingDMarker)
+}
+
+/// Looks up to [`BULK_LIMIT`] items in one request. CSGOFloat can fail to
+/// look up some items but not others, so each link gets its own result.
 pub async fn get_bulk_by_market_url(
     client: &Client,
-    key: &str,
+    base_url: &str,
+    auth: &Auth,
     urls: &[&str],
-) -> Result<HashMap<String, ItemDescription>, CsgoFloatFetchError> {
-    let url_map: HashMap<String, String> =
-        urls.iter().try_fold(HashMap::new(), |mut acc, url| {
-            let key = url
-                .split('A')
-                .nth(1)
-                .ok_or(SteamURLParseError::MissingAssetMarker)?
-                .split('D')
-                .next()
-                .ok_or(SteamURLParseError::MissingDMarker)?
-                .to_string();
-            acc.insert(url.to_string(), key);
-
-            Ok::<_, SteamURLParseError>(acc)
-        })?;
-
-    let links = urls
-        .iter()
-        .map(|l| BulkRequestItem {
-            link: l.to_string(),
-        })
+) -> Result<HashMap<String, Result<ItemDescription, CsgoFloatFetchError>>, CsgoFloatFetchError> {
+    let mut results = HashMap::with_capacity(urls.len());
+    // The same item can turn up under more than one link, e.g. from its
+    // owner's inventory and from a market listing.
+    let mut urls_by_asset_id: HashMap<String, Vec<String>> = HashMap::with_capacity(urls.len());
+    for url in urls {
+        match asset_id(url) {
+            Ok(id) => {
+                urls_by_asset_id
+                    .entry(id.to_string())
+                    .or_default()
+                    .push(url.to_string());
+            }
+            Err(e) => {
+                results.insert(url.to_string(), Err(e.into()));
+            }
+        }
+    }
+    if urls_by_asset_id.is_empty() {
+        return Ok(results);
+    }
+
+    let links = urls_by_asset_id
+        .values()
+        .map(|l| BulkRequestItem { link: l[0].clone() })
         .collect();
     let bulk_req = BulkRequest { links };
     let req_data = serde_json::to_vec(&bulk_req)?;
 
     let req = client
-        .post("https://api.csgofloat.com/bulk")
+        .post(format!("{}/bulk", base_url))
         .header(CONTENT_TYPE, "application/json")
-        .header(AUTHORIZATION, key)
         .body(Body::from(req_data));
+    let req = auth.apply(req);
 
-    let body = req.send().await?.text().await?;
-    let resp: HashMap<String, ItemDescription> = serde_json::from_str(&body)?;
-
-    let items_by_url = url_map
-        .into_iter()
-        .fold(HashMap::new(), |mut acc, (url, asset_id)| {
-            let item = resp.get(&asset_id).unwrap();
-            acc.insert(url, item.clone());
-
-            acc
-        });
+    let resp = req.send().await?;
+    let status = resp.status();
+    let body = resp.text().await?;
+    if status != StatusCode::OK {
+        return Err(CsgoFloatFetchError::from_response(status, body));
+    }
+    let mut resp: HashMap<String, BulkResponseItem> = serde_json::from_str(&body)?;
+
+    for (asset_id, urls) in urls_by_asset_id {
+        let found = resp.remove(&asset_id);
+        for url in urls {
+            let result = match &found {
+                Some(BulkResponseItem::Item(item)) => Ok(item.as_ref().clone()),
+                Some(BulkResponseItem::Failed(body)) => Err(CsgoFloatFetchError::CsgoFloat {
+                    status: StatusCode::OK,
+                    error: body.code,
+                }),
+                Some(BulkResponseItem::FailedOther { error }) => {
+                    Err(CsgoFloatFetchError::Lookup(error.clone()))
+                }
+                None => Err(CsgoFloatFetchError::MissingFromResponse),
+            };
+            results.insert(url, result);
+        }
+    }
 
-    Ok(items_by_url)
+    Ok(results)
 }
 
 #[derive(Debug, Error)]
@@ -182,14 +368,20 @@ pub async fn get_bulk_by_market_url(
 pub struct CsgoFloatClientCreateError(#[from] RedisError);
 
 pub struct CsgoFloatClient {
-    key: String,
+    auth: Auth,
+    base_url: String,
     cache: Cache<ItemDescription>,
     client: Client,
+    bulk_limit: usize,
+    concurrency: usize,
+    retry_policy: RetryPolicy,
 }
 
 impl CsgoFloatClient {
-    pub async fn new<S: Into<String>, T: IntoConnectionInfo>(
-        key: S,
+    /// Creates a client which authenticates with the given key, or however
+    /// else [`Auth`] says.
+    pub async fn new<A: Into<Auth>, T: IntoConnectionInfo>(
+        auth: A,
         i: T,
     ) -> Result<Self, CsgoFloatClientCreateError> {
         let conn_info = i.into_connection_info()?;
@@ -199,9 +391,40 @@ impl CsgoFloatClient {
         let cache = Cache::new(pool, "floatcache".to_string());
         let client = Client::new();
 
-        let key = key.into();
+        let auth = auth.into();
+        let base_url = CSGOFLOAT_URL.to_string();
+
+        Ok(Self {
+            auth,
+            base_url,
+            cache,
+            client,
+            bulk_limit: BULK_LIMIT,
+            concurrency: DEFAULT_CONCURRENCY,
+            retry_policy: RetryPolicy::default(),
+        })
+    }
 
-        Ok(Self { key, cache, client })
+    /// Sets how many items to look up in each bulk request, instead of
+    /// [`BULK_LIMIT`].
+    pub fn with_bulk_limit(mut self, bulk_limit: usize) -> Self {
+        self.bulk_limit = bulk_limit.clamp(1, BULK_LIMIT);
+        self
+    }
+
+    /// Sets how many items to look up at once when bulk requests fail and
+    /// we fall back to looking them up one at a time.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Points this client at a different host, instead of api.csgofloat.com.
+    /// This can be a self-hosted inspect server that speaks the same
+    /// protocol.
+    pub fn with_base_url<S: Into<String>>(mut self, base_url: S) -> Self {
+        self.base_url = base_url.into();
+        self
     }
 
     pub async fn get(&self, url: &str) -> Result<ItemDescription, CsgoFloatFetchError> {
@@ -211,7 +434,10 @@ impl CsgoFloatClient {
             Err(e) => log::warn!("error fetching from cache: {}", e),
         };
 
-        let res = get_by_market_url(&self.client, &self.key, url).await?;
+        let res = self
+            .retry_policy
+            .run(|| get_by_market_url(&self.client, &self.base_url, &self.auth, url))
+            .await?;
 
         if let Err(e) = self.cache.set(url, &res).await {
             log::warn!("failed to set cache entry: {}", e);
@@ -220,39 +446,238 @@ impl CsgoFloatClient {
         Ok(res)
     }
 
+    /// Looks up many items, in bulk where possible. Each link gets its own
+    /// result, so one bad item doesn't spoil the rest.
     pub async fn get_bulk(
         &self,
         urls: &[&str],
-    ) -> Result<HashMap<String, ItemDescription>, CsgoFloatFetchError> {
-        let res = self.cache.get_bulk(urls).await.unwrap_or_else(|e| {
+    ) -> HashMap<String, Result<ItemDescription, CsgoFloatFetchError>> {
+        let cached = self.cache.get_bulk(urls).await.unwrap_or_else(|e| {
             log::warn!("failed to get items from cache: {}", e);
             HashMap::with_capacity(0)
         });
         let missing: Vec<&str> = urls
             .iter()
-            .filter(|&u| !res.contains_key(*u))
+            .filter(|&u| !cached.contains_key(*u))
             .copied()
             .collect();
 
+        let mut results: HashMap<_, _> = cached.into_iter().map(|(k, v)| (k, Ok(v))).collect();
         if missing.is_empty() {
-            return Ok(res);
+            return results;
         }
 
         let mut fresh = HashMap::with_capacity(missing.len());
-        for item in missing {
-            let desc = get_by_market_url(&self.client, &self.key, item).await?;
-            fresh.insert(item.to_string(), desc);
+        let mut retry = Vec::new();
+        for chunk in missing.chunks(self.bulk_limit) {
+            let found = self
+                .retry_policy
+                .run(|| get_bulk_by_market_url(&self.client, &self.base_url, &self.auth, chunk))
+                .await;
+            match found {
+                Ok(found) => {
+                    for (url, res) in found {
+                        match res {
+                            Ok(item) => {
+                                fresh.insert(url, item);
+                            }
+                            // Looking these up again won't help.
+                            Err(e) if !e.is_transient() => {
+                                results.insert(url, Err(e));
+                            }
+                            Err(e) => {
+                                log::debug!("bulk lookup of {url} failed: {e}");
+                                retry.push(url);
+                            }
+                        }
+                    }
+                }
+                // e.g. a bad key, which would fail every lookup the same way.
+                Err(CsgoFloatFetchError::CsgoFloat { status, error }) if !error.is_transient() => {
+                    for url in chunk {
+                        let e = CsgoFloatFetchError::CsgoFloat { status, error };
+                        results.insert(url.to_string(), Err(e));
+                    }
+                }
+                Err(e) => {
+                    log::warn!(
+                        "bulk lookup of {} items failed, looking them up one by one: {e}",
+                        chunk.len()
+                    );
+                    retry.extend(chunk.iter().map(|u| u.to_string()));
+                }
+            }
+        }
+
+        let singles: Vec<_> = stream::iter(retry)
+            .map(|url| async move {
+                let res = self
+                    .retry_policy
+                    .run(|| get_by_market_url(&self.client, &self.base_url, &self.auth, &url))
+                    .await;
+                (url, res)
+            })
+            .buffer_unordered(self.concurrency)
+            .collect()
+            .await;
+        for (url, res) in singles {
+            match res {
+                Ok(item) => {
+                    fresh.insert(url, item);
+                }
+                Err(e) => {
+                    results.insert(url, Err(e));
+                }
+            }
         }
 
         if let Err(e) = self.cache.set_bulk(&fresh).await {
             log::warn!("failed to set items in cache: {}", e);
         }
+        results.extend(fresh.into_iter().map(|(k, v)| (k, Ok(v))));
 
-        let res = fresh.into_iter().fold(res, |mut acc, (k, v)| {
-            acc.insert(k, v);
-            acc
-        });
+        results
+    }
+}
 
-        Ok(res)
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bb8_redis::bb8::Pool;
+    use bb8_redis::RedisConnectionManager;
+    use cache::Cache;
+    use mock_steam::{Failure, Fixtures};
+    use reqwest::Client;
+
+    use reqwest::StatusCode;
+
+    use super::{
+        get_bulk_by_market_url, Auth, CsgoFloatClient, CsgoFloatError, CsgoFloatFetchError,
+        FloatItemResponse, ItemDescription, RetryPolicy, BULK_LIMIT, DEFAULT_CONCURRENCY,
+    };
+
+    /// A client for the given mock server which retries straight away, with
+    /// a cache it can never reach.
+    fn mock_client(base: &str) -> CsgoFloatClient {
+        let mgr = RedisConnectionManager::new("redis://127.0.0.1:1").unwrap();
+        let pool = Pool::builder()
+            .connection_timeout(Duration::from_millis(10))
+            .build_unchecked(mgr);
+
+        CsgoFloatClient {
+            auth: Auth::None,
+            base_url: base.to_string(),
+            cache: Cache::new(Arc::new(pool), "floatcache".to_string()),
+            client: Client::new(),
+            bulk_limit: BULK_LIMIT,
+            concurrency: DEFAULT_CONCURRENCY,
+            retry_policy: RetryPolicy {
+                max_retries: 1,
+                initial_backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_millis(10),
+            },
+        }
+    }
+
+    #[test]
+    fn test_self_hosted_response_shapes() {
+        let parse = |body: &str| -> ItemDescription {
+            serde_json::from_str::<FloatItemResponse>(body)
+                .unwrap()
+                .into()
+        };
+
+        let wrapped = parse(
+            r#"{"iteminfo": {"paintseed": 123, "floatvalue": 0.25, "full_item_name": "AK-47 | Redline (Field-Tested)"}}"#,
+        );
+        assert_eq!(wrapped.paint_seed, Some(123));
+        assert_eq!(wrapped.full_item_name, "AK-47 | Redline (Field-Tested)");
+
+        // Without game files, self-hosted servers can't name anything.
+        let bare =
+            parse(r#"{"a": "1000", "d": "1234567890", "paintseed": 123, "floatvalue": 0.25}"#);
+        assert_eq!(bare.float_value, Some(0.25));
+        assert!(bare.stickers.is_empty());
+    }
+
+    #[test]
+    fn test_decode_error_response() {
+        let decode = |status, body: &str| CsgoFloatFetchError::from_response(status, body.into());
+
+        let e = decode(
+            StatusCode::UNAUTHORIZED,
+            r#"{"code": 8, "error": "Bad Secret"}"#,
+        );
+        assert!(matches!(
+            e,
+            CsgoFloatFetchError::CsgoFloat {
+                status: StatusCode::UNAUTHORIZED,
+                error: CsgoFloatError::BadSecret,
+            }
+        ));
+        assert!(!e.is_transient());
+
+        let e = decode(
+            StatusCode::SERVICE_UNAVAILABLE,
+            r#"{"code": 5, "error": "Valve's servers appear to be offline"}"#,
+        );
+        assert!(e.is_transient());
+
+        let e = decode(StatusCode::BAD_GATEWAY, "<html>bad gateway</html>");
+        assert!(matches!(e, CsgoFloatFetchError::UnexpectedStatus { .. }));
+        assert!(e.is_transient());
+    }
+
+    #[tokio::test]
+    async fn test_bulk_results_per_link() {
+        let base = mock_steam::spawn(Fixtures::default());
+        let good = [
+            "steam://rungame/730/1/+csgo_econ_action_preview%20S1A1000D1",
+            "steam://rungame/730/1/+csgo_econ_action_preview%20S1A1001D1",
+            // The same item as the first, listed on the market.
+            "steam://rungame/730/1/+csgo_econ_action_preview%20M2A1000D1",
+        ];
+        let bad = "steam://rungame/730/1/+csgo_econ_action_preview%20M1";
+        let urls = [good[0], good[1], good[2], bad];
+
+        let results = get_bulk_by_market_url(&Client::new(), &base, &Auth::None, &urls)
+            .await
+            .unwrap();
+
+        assert_eq!(results.len(), 4);
+        assert!(good.iter().all(|u| results[*u].is_ok()));
+        assert!(matches!(
+            results[bad],
+            Err(CsgoFloatFetchError::SteamURLFormat(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_bulk_falls_back_to_single_lookups() {
+        let (base, log) = mock_steam::spawn_logged(Fixtures {
+            failures: vec![Failure {
+                path: "/bulk".to_string(),
+                status: 500,
+                retry_after: None,
+                times: usize::MAX,
+            }],
+            ..Default::default()
+        });
+        let client = mock_client(&base).with_bulk_limit(1);
+        let urls = [
+            "steam://rungame/730/1/+csgo_econ_action_preview%20S1A1000D1",
+            "steam://rungame/730/1/+csgo_econ_action_preview%20S1A1001D1",
+            "steam://rungame/730/1/+csgo_econ_action_preview%20S1A1002D1",
+        ];
+
+        let results = client.get_bulk(&urls).await;
+
+        assert_eq!(results.len(), 3);
+        assert!(urls.iter().all(|u| results[*u].is_ok()));
+        // One chunk per link, each tried twice, then one lookup per link.
+        assert_eq!(log.count("/bulk"), 6);
+        assert_eq!(log.count("/"), 3);
     }
 }
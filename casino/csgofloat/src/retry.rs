@@ -0,0 +1,56 @@
+use std::future::Future;
+use std::time::Duration;
+
+use crate::CsgoFloatFetchError;
+
+/// How we back off and retry when CSGOFloat, or Valve behind it, has a
+/// transient failure. Each retry waits twice as long as the last, up to
+/// `max_backoff`.
+#[derive(Clone, Debug)]
+pub(crate) struct RetryPolicy {
+    pub(crate) max_retries: u32,
+    pub(crate) initial_backoff: Duration,
+    pub(crate) max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_secs(2),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff)
+    }
+
+    /// Runs a lookup, retrying it for as long as it fails transiently.
+    /// Permanent failures are returned straight away.
+    pub(crate) async fn run<T, F, Fut>(&self, mut lookup: F) -> Result<T, CsgoFloatFetchError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, CsgoFloatFetchError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match lookup().await {
+                Err(e) if e.is_transient() && attempt < self.max_retries => {
+                    let wait = self.backoff(attempt);
+                    log::warn!(
+                        "csgofloat lookup failed ({e}), retrying in {}s",
+                        wait.as_secs()
+                    );
+                    tokio::time::sleep(wait).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
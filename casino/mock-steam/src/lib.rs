@@ -0,0 +1,398 @@
+//! A stand-in for the Steam Community site, the Steam Web API, Steam's
+//! authentication service, Skinport and the CSGOFloat API, serving canned
+//! responses so the collector and aggregator can be run offline.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Form, Query, State};
+use axum::http::header::{CONTENT_TYPE, RETRY_AFTER};
+use axum::http::{Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Response};
+use axum::{routing, Json, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use thiserror::Error;
+use tokio::io;
+
+/// The responses served for each endpoint.
+#[derive(Clone, Debug)]
+pub struct Fixtures {
+    /// Profile page, with the user's id and logged-in state.
+    pub profile: String,
+    /// Inventory History page.
+    pub history: String,
+    /// First page of the user's inventory, as JSON.
+    pub inventory: String,
+    /// Later pages of the user's inventory, as JSON, by the asset id they
+    /// start from.
+    pub more_inventory: HashMap<String, String>,
+    /// The user's trades, as the Web API's GetTradeHistory returns them.
+    pub trade_history: String,
+    /// Later pages of the user's trades, by the trade id they start after.
+    pub more_trade_history: HashMap<String, String>,
+    /// Steam Market price overview, as JSON.
+    pub price_overview: String,
+    /// Skinport's item listing, as JSON.
+    pub skinport: String,
+    /// CSGOFloat item info, as JSON. Used for every item asked for.
+    pub float: String,
+    /// Error responses to serve instead of the fixtures.
+    pub failures: Vec<Failure>,
+}
+
+/// An error response, served instead of the fixture for the first `times`
+/// requests to `path`.
+#[derive(Clone, Debug)]
+pub struct Failure {
+    pub path: String,
+    pub status: u16,
+    /// Sent as the Retry-After header, if set.
+    pub retry_after: Option<String>,
+    pub times: usize,
+}
+
+/// The path of every request the mock has been sent, in order.
+#[derive(Clone, Debug, Default)]
+pub struct RequestLog(Arc<Mutex<Vec<String>>>);
+
+impl RequestLog {
+    /// How many requests were made to `path`.
+    pub fn count(&self, path: &str) -> usize {
+        self.0.lock().unwrap().iter().filter(|p| *p == path).count()
+    }
+}
+
+impl Default for Fixtures {
+    fn default() -> Self {
+        Self {
+            profile: include_str!("../fixtures/profile.html").to_string(),
+            history: include_str!("../fixtures/inventoryhistory.html").to_string(),
+            inventory: include_str!("../fixtures/inventory.json").to_string(),
+            more_inventory: HashMap::new(),
+            trade_history: include_str!("../fixtures/tradehistory.json").to_string(),
+            more_trade_history: HashMap::new(),
+            price_overview: include_str!("../fixtures/priceoverview.json").to_string(),
+            skinport: include_str!("../fixtures/skinport.json").to_string(),
+            float: include_str!("../fixtures/float.json").to_string(),
+            failures: Vec::new(),
+        }
+    }
+}
+
+impl Fixtures {
+    /// Loads fixtures from a directory laid out like `fixtures/` in this
+    /// crate, falling back to the built-in ones for any missing files.
+    pub async fn from_dir<P: AsRef<Path>>(dir: P) -> Result<Self, io::Error> {
+        let dir = dir.as_ref();
+        let defaults = Self::default();
+        let load = |name: &'static str, default: String| async move {
+            match tokio::fs::read_to_string(dir.join(name)).await {
+                Ok(s) => Ok(s),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(default),
+                Err(e) => Err(e),
+            }
+        };
+
+        Ok(Self {
+            profile: load("profile.html", defaults.profile).await?,
+            history: load("inventoryhistory.html", defaults.history).await?,
+            inventory: load("inventory.json", defaults.inventory).await?,
+            more_inventory: defaults.more_inventory,
+            trade_history: load("tradehistory.json", defaults.trade_history).await?,
+            more_trade_history: defaults.more_trade_history,
+            price_overview: load("priceoverview.json", defaults.price_overview).await?,
+            skinport: load("skinport.json", defaults.skinport).await?,
+            float: load("float.json", defaults.float).await?,
+            failures: defaults.failures,
+        })
+    }
+}
+
+/// The Steam Guard code the mock authentication service accepts.
+pub const GUARD_CODE: &str = "MOCK1";
+/// The account every mock login is for.
+pub const STEAM_ID: &str = "76561198000000001";
+/// The access token handed out on login.
+// {"iss":"steam","sub":"76561198000000001","aud":["web","mobile"],"exp":4102444800}
+pub const ACCESS_TOKEN: &str = "eyJhbGciOiJFZERTQSJ9.eyJpc3MiOiJzdGVhbSIsInN1YiI6Ijc2NTYxMTk4MDAwMDAwMDAxIiwiYXVkIjpbIndlYiIsIm1vYmlsZSJdLCJleHAiOjQxMDI0NDQ4MDB9.c2ln";
+/// The refresh token handed out on login.
+pub const REFRESH_TOKEN: &str = "mock-refresh-token";
+
+// A real 1024-bit key, as Steam's are, so passwords can be encrypted with it.
+const RSA_MODULUS: &str = "F08691DD133E13B674560AF1C0C97A76D483F43957BC9BD6BA345B56AC1805A5AEBCDA28F015BF25C4E3773A36086812BBEBC419BA8CA42716F708E50E1B8809FBC47E2837533CE5D59465531F1B1C8734F28EBCC6BD573082D3165B7057DBC50400CC20B7079BBC8F81FC47D37BD6BB95D907A11AC367060273A4DCBCD943F9";
+// k_EResultInvalidLoginAuthCode
+const ERESULT_INVALID_CODE: &str = "65";
+
+#[derive(Debug, Error)]
+#[error("failed to serve http: {0}")]
+pub struct ServingError(#[from] hyper::Error);
+
+type SharedFixtures = Arc<Fixtures>;
+
+struct Script {
+    failures: Mutex<Vec<Failure>>,
+    log: RequestLog,
+}
+
+/// Records each request, and serves any scripted failure for it.
+async fn serve_scripted<B>(
+    State(script): State<Arc<Script>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let path = req.uri().path().to_string();
+    script.log.0.lock().unwrap().push(path.clone());
+
+    let failure = {
+        let mut failures = script.failures.lock().unwrap();
+        failures
+            .iter_mut()
+            .find(|f| f.path == path && f.times > 0)
+            .map(|f| {
+                f.times -= 1;
+                f.clone()
+            })
+    };
+
+    match failure {
+        Some(f) => {
+            let status =
+                StatusCode::from_u16(f.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            let mut resp = status.into_response();
+            if let Some(retry_after) = f.retry_after.and_then(|r| r.parse().ok()) {
+                resp.headers_mut().insert(RETRY_AFTER, retry_after);
+            }
+
+            resp
+        }
+        None => next.run(req).await,
+    }
+}
+
+fn json_response(body: &str) -> Response {
+    ([(CONTENT_TYPE, "application/json")], body.to_string()).into_response()
+}
+
+async fn handle_profile(State(f): State<SharedFixtures>) -> Html<String> {
+    Html(f.profile.clone())
+}
+
+async fn handle_history(
+    State(f): State<SharedFixtures>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    // Later pages are requested over ajax, but the fixture only has one.
+    if params.contains_key("ajax") {
+        return Json(json!({"success": true, "html": "", "cursor": null})).into_response();
+    }
+
+    Html(f.history.clone()).into_response()
+}
+
+async fn handle_inventory(
+    State(f): State<SharedFixtures>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    match params.get("start_assetid") {
+        Some(start) => match f.more_inventory.get(start) {
+            Some(page) => json_response(page),
+            None => StatusCode::NOT_FOUND.into_response(),
+        },
+        None => json_response(&f.inventory),
+    }
+}
+
+async fn handle_trade_history(
+    State(f): State<SharedFixtures>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    match params.get("start_after_tradeid") {
+        Some(start) => match f.more_trade_history.get(start) {
+            Some(page) => json_response(page),
+            None => StatusCode::NOT_FOUND.into_response(),
+        },
+        None => json_response(&f.trade_history),
+    }
+}
+
+async fn handle_price_overview(State(f): State<SharedFixtures>) -> Response {
+    json_response(&f.price_overview)
+}
+
+async fn handle_skinport_items(State(f): State<SharedFixtures>) -> Response {
+    json_response(&f.skinport)
+}
+
+fn invalid_float_fixture(e: serde_json::Error) -> Response {
+    let msg = format!("invalid float fixture: {e}");
+    (StatusCode::INTERNAL_SERVER_ERROR, msg).into_response()
+}
+
+async fn handle_float(State(f): State<SharedFixtures>) -> Response {
+    match serde_json::from_str::<Value>(&f.float) {
+        Ok(item) => Json(json!({ "iteminfo": item })).into_response(),
+        Err(e) => invalid_float_fixture(e),
+    }
+}
+
+#[derive(Deserialize)]
+struct BulkRequestItem {
+    link: String,
+}
+
+#[derive(Deserialize)]
+struct BulkRequest {
+    links: Vec<BulkRequestItem>,
+}
+
+async fn handle_float_bulk(
+    State(f): State<SharedFixtures>,
+    Json(req): Json<BulkRequest>,
+) -> Response {
+    let item: Value = match serde_json::from_str(&f.float) {
+        Ok(item) => item,
+        Err(e) => return invalid_float_fixture(e),
+    };
+
+    // CSGOFloat keys bulk responses by the asset id in each inspect link.
+    let items: HashMap<&str, &Value> = req
+        .links
+        .iter()
+        .filter_map(|l| l.link.split('A').nth(1)?.split('D').next())
+        .map(|asset_id| (asset_id, &item))
+        .collect();
+
+    Json(items).into_response()
+}
+
+async fn handle_rsa_key() -> Json<Value> {
+    Json(json!({"response": {
+        "publickey_mod": RSA_MODULUS,
+        "publickey_exp": "010001",
+        "timestamp": "1700000000000",
+    }}))
+}
+
+async fn handle_begin_auth() -> Json<Value> {
+    Json(json!({"response": {
+        "client_id": "1",
+        "request_id": "bW9jaw==",
+        "interval": 1,
+        "steamid": STEAM_ID,
+        "allowed_confirmations": [{"confirmation_type": 2, "associated_message": "mock.example"}],
+    }}))
+}
+
+async fn handle_guard_code(Form(params): Form<HashMap<String, String>>) -> Response {
+    match params.get("code").map(String::as_str) {
+        Some(GUARD_CODE) => Json(json!({"response": {}})).into_response(),
+        _ => (
+            [("x-eresult", ERESULT_INVALID_CODE)],
+            Json(json!({"response": {}})),
+        )
+            .into_response(),
+    }
+}
+
+async fn handle_poll_auth() -> Json<Value> {
+    Json(json!({"response": {
+        "refresh_token": REFRESH_TOKEN,
+        "access_token": ACCESS_TOKEN,
+        "account_name": "mock",
+    }}))
+}
+
+async fn handle_refresh() -> Json<Value> {
+    Json(json!({"response": {"access_token": ACCESS_TOKEN}}))
+}
+
+/// Builds the routes for every endpoint we mock.
+pub fn router(fixtures: Fixtures) -> Router {
+    logged_router(fixtures, RequestLog::default())
+}
+
+fn logged_router(fixtures: Fixtures, log: RequestLog) -> Router {
+    let script = Arc::new(Script {
+        failures: Mutex::new(fixtures.failures.clone()),
+        log,
+    });
+
+    Router::new()
+        .route("/profiles/:id", routing::get(handle_profile))
+        .route("/id/:vanity", routing::get(handle_profile))
+        .route(
+            "/profiles/:id/inventoryhistory/",
+            routing::get(handle_history),
+        )
+        .route(
+            "/id/:vanity/inventoryhistory/",
+            routing::get(handle_history),
+        )
+        .route("/inventory/:id/730/2", routing::get(handle_inventory))
+        .route(
+            "/market/priceoverview/",
+            routing::get(handle_price_overview),
+        )
+        .route(
+            "/IEconService/GetTradeHistory/v1/",
+            routing::get(handle_trade_history),
+        )
+        .route("/v1/items", routing::get(handle_skinport_items))
+        .route(
+            "/IAuthenticationService/GetPasswordRSAPublicKey/v1/",
+            routing::get(handle_rsa_key),
+        )
+        .route(
+            "/IAuthenticationService/BeginAuthSessionViaCredentials/v1/",
+            routing::post(handle_begin_auth),
+        )
+        .route(
+            "/IAuthenticationService/UpdateAuthSessionWithSteamGuardCode/v1/",
+            routing::post(handle_guard_code),
+        )
+        .route(
+            "/IAuthenticationService/PollAuthSessionStatus/v1/",
+            routing::post(handle_poll_auth),
+        )
+        .route(
+            "/IAuthenticationService/GenerateAccessTokenForApp/v1/",
+            routing::post(handle_refresh),
+        )
+        .route("/", routing::get(handle_float))
+        .route("/bulk", routing::post(handle_float_bulk))
+        .layer(middleware::from_fn_with_state(script, serve_scripted))
+        .with_state(Arc::new(fixtures))
+}
+
+pub async fn serve(bind_addr: &SocketAddr, fixtures: Fixtures) -> Result<(), ServingError> {
+    axum::Server::bind(bind_addr)
+        .serve(router(fixtures).into_make_service())
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c().await.unwrap();
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Serves the given fixtures on a free local port in the background,
+/// returning the base URL to point clients at.
+pub fn spawn(fixtures: Fixtures) -> String {
+    spawn_logged(fixtures).0
+}
+
+/// Like [`spawn`], but also returns a log of the requests the mock is sent.
+pub fn spawn_logged(fixtures: Fixtures) -> (String, RequestLog) {
+    let log = RequestLog::default();
+    let router = logged_router(fixtures, log.clone());
+    let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+    let server = axum::Server::bind(&addr).serve(router.into_make_service());
+    let base = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+
+    (base, log)
+}
@@ -0,0 +1,59 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use clap::Parser;
+use thiserror::Error;
+use tokio::io;
+
+use mock_steam::{serve, Fixtures, ServingError};
+
+#[tokio::main]
+async fn main() {
+    match real_main().await {
+        Err(MockError::ParsingCommandLineArgs(e)) => eprintln!("{e}"),
+        Err(e) => eprintln!("fatal error: {e}"),
+        _ => return,
+    }
+
+    std::process::exit(1);
+}
+
+#[derive(Debug, Error)]
+enum MockError {
+    #[error("{0}")]
+    ParsingCommandLineArgs(#[from] clap::Error),
+    #[error("error loading fixtures: {0}")]
+    LoadingFixtures(#[from] io::Error),
+    #[error("error serving http: {0}")]
+    ServingHTTP(#[from] ServingError),
+}
+
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Address to bind server to
+    #[arg(short, long, env, default_value = "127.0.0.1:7001")]
+    bind_addr: SocketAddr,
+    /// Directory of fixtures to serve instead of the built-in ones
+    #[arg(short, long, env)]
+    fixtures_dir: Option<PathBuf>,
+    /// Level to log at
+    #[arg(short, long, env, default_value = "info")]
+    log_level: log::LevelFilter,
+}
+
+async fn real_main() -> Result<(), MockError> {
+    let args = Args::try_parse()?;
+
+    logging::init(args.log_level);
+
+    let fixtures = match args.fixtures_dir {
+        Some(dir) => Fixtures::from_dir(dir).await?,
+        None => Fixtures::default(),
+    };
+
+    log::info!("serving mock steam on http://{}", args.bind_addr);
+    serve(&args.bind_addr, fixtures).await?;
+
+    Ok(())
+}
@@ -24,8 +24,15 @@ in
         };
 
         csgoFloatKeyFile = mkOption {
-          type = path;
-          description = "Path to file containing CSGOFloat Key";
+          type = nullOr path;
+          default = null;
+          description = "Path to file containing CSGOFloat Key. Items have no float information without one";
+        };
+
+        inspectUrl = mkOption {
+          type = nullOr str;
+          default = null;
+          description = "Base URL of a self-hosted inspect server to use instead of CSGOFloat";
         };
 
         keystorePath = mkOption {
@@ -66,12 +73,18 @@ in
         BIND_ADDR = cfg.bindAddr;
         KEYSTORE_PATH = cfg.keystorePath;
         LOG_LEVEL = cfg.logLevel;
+      } // lib.optionalAttrs (cfg.inspectUrl != null) {
+        INSPECT_BACKEND = "self-hosted";
+        INSPECT_URL = cfg.inspectUrl;
       };
 
       serviceConfig = {
-        ExecStart = ''
-          ${pkgs.bash}/bin/bash -c "${cfg.package}/bin/aggregator --csgofloat-key $(cat ${cfg.csgoFloatKeyFile})"
-        '';
+        ExecStart =
+          if cfg.csgoFloatKeyFile == null
+          then "${cfg.package}/bin/aggregator"
+          else ''
+            ${pkgs.bash}/bin/bash -c "${cfg.package}/bin/aggregator --csgofloat-key $(cat ${cfg.csgoFloatKeyFile})"
+          '';
       };
     };
   };
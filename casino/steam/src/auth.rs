@@ -0,0 +1,402 @@
+//! Logging in to Steam with a username and password, through the same
+//! authentication service the Steam mobile app uses.
+
+use std::time::Duration;
+
+use rand::RngCore;
+use reqwest::{Client, Response};
+use rsa::{BigUint, Pkcs1v15Encrypt, RsaPublicKey};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_aux::field_attributes::deserialize_number_from_string;
+
+use crate::errors::LoginError;
+use crate::{SteamCredentials, SteamId};
+
+const WEB_API_URL: &str = "https://api.steampowered.com";
+const SERVICE: &str = "IAuthenticationService";
+// k_EAuthTokenPlatformType_MobileApp, whose access tokens can be used as
+// steamLoginSecure cookies as they are.
+const PLATFORM_TYPE_MOBILE_APP: &str = "3";
+// k_ESessionPersistence_Persistent
+const PERSISTENCE_PERSISTENT: &str = "1";
+const DEVICE_NAME: &str = "casino collector";
+// k_EResultOK
+const ERESULT_OK: u32 = 1;
+// Steam gives up on a login it hasn't heard back about after a few minutes.
+const MAX_WAIT: Duration = Duration::from_secs(5 * 60);
+
+/// A way Steam wants a login confirmed with Steam Guard.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GuardType {
+    /// A code sent to the account's email address.
+    EmailCode,
+    /// A code from the Steam Guard mobile authenticator.
+    DeviceCode,
+    /// Approving the login in the Steam mobile app.
+    DeviceConfirmation,
+    /// Following a link sent to the account's email address.
+    EmailConfirmation,
+}
+
+impl GuardType {
+    fn from_confirmation_type(t: u32) -> Option<Self> {
+        match t {
+            2 => Some(Self::EmailCode),
+            3 => Some(Self::DeviceCode),
+            4 => Some(Self::DeviceConfirmation),
+            5 => Some(Self::EmailConfirmation),
+            _ => None,
+        }
+    }
+
+    fn code_type(&self) -> &'static str {
+        match self {
+            Self::EmailCode => "2",
+            Self::DeviceCode => "3",
+            Self::DeviceConfirmation => "4",
+            Self::EmailConfirmation => "5",
+        }
+    }
+
+    /// Whether the user has to give us a code for this confirmation.
+    pub fn needs_code(&self) -> bool {
+        matches!(self, Self::EmailCode | Self::DeviceCode)
+    }
+}
+
+/// A login which Steam has accepted the password for, but which may still
+/// need confirming with Steam Guard.
+#[derive(Debug)]
+pub struct AuthSession {
+    client_id: String,
+    request_id: String,
+    steam_id: SteamId,
+    interval: Duration,
+    confirmations: Vec<GuardType>,
+}
+
+impl AuthSession {
+    pub fn steam_id(&self) -> SteamId {
+        self.steam_id
+    }
+
+    /// The ways Steam will accept this login being confirmed. Empty if it
+    /// doesn't need confirming.
+    pub fn confirmations(&self) -> &[GuardType] {
+        &self.confirmations
+    }
+
+    /// The kind of Steam Guard code to ask the user for, if the login can't
+    /// go ahead without one.
+    pub fn code_needed(&self) -> Option<GuardType> {
+        self.confirmations
+            .iter()
+            .copied()
+            .find(GuardType::needs_code)
+    }
+}
+
+/// The tokens Steam gives us once a login has gone through.
+#[derive(Clone, Debug)]
+pub struct LoginTokens {
+    pub steam_id: SteamId,
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+impl LoginTokens {
+    /// Turns these into cookies for the Steam Community site, keeping the
+    /// refresh token so they can be renewed later.
+    pub fn into_credentials(self) -> SteamCredentials {
+        SteamCredentials::from_access_token(
+            random_session_id(),
+            self.steam_id,
+            &self.access_token,
+            Some(self.refresh_token),
+        )
+    }
+}
+
+// Steam doesn't issue sessionid itself, it's only a CSRF token, so any
+// random 12 bytes will do.
+fn random_session_id() -> String {
+    let mut bytes = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut bytes);
+
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[derive(Deserialize)]
+struct ServiceResponse<T> {
+    response: T,
+}
+
+#[derive(Deserialize)]
+struct RsaKey {
+    publickey_mod: String,
+    publickey_exp: String,
+    timestamp: String,
+}
+
+#[derive(Deserialize)]
+struct Confirmation {
+    confirmation_type: u32,
+}
+
+#[derive(Deserialize)]
+struct BeginSession {
+    client_id: String,
+    request_id: String,
+    #[serde(default)]
+    interval: f32,
+    #[serde(default)]
+    allowed_confirmations: Vec<Confirmation>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    steamid: u64,
+}
+
+#[derive(Deserialize)]
+struct PollStatus {
+    new_client_id: Option<String>,
+    refresh_token: Option<String>,
+    access_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct Empty {}
+
+/// Logs in to Steam and renews sessions using a refresh token.
+#[derive(Clone, Debug)]
+pub struct SteamAuthenticator {
+    http_client: Client,
+    base_url: String,
+}
+
+impl Default for SteamAuthenticator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SteamAuthenticator {
+    pub fn new() -> Self {
+        Self {
+            http_client: Client::new(),
+            base_url: WEB_API_URL.to_string(),
+        }
+    }
+
+    /// Points this authenticator at a different host, instead of
+    /// api.steampowered.com.
+    pub fn with_base_url<S: Into<String>>(mut self, base_url: S) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    fn url(&self, method: &str) -> String {
+        format!("{}/{SERVICE}/{method}/v1/", self.base_url)
+    }
+
+    /// Reads a service response, which Steam leaves empty on failure with
+    /// the reason in a header.
+    async fn parse<T: DeserializeOwned>(resp: Response) -> Result<T, LoginError> {
+        let eresult = resp
+            .headers()
+            .get("x-eresult")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(ERESULT_OK);
+        if eresult != ERESULT_OK {
+            return Err(LoginError::Rejected(eresult));
+        }
+
+        let body = resp.error_for_status()?.text().await?;
+        let parsed: ServiceResponse<T> = serde_json::from_str(&body)?;
+
+        Ok(parsed.response)
+    }
+
+    async fn post<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        form: &[(&str, &str)],
+    ) -> Result<T, LoginError> {
+        let resp = self
+            .http_client
+            .post(self.url(method))
+            .form(form)
+            .send()
+            .await?;
+
+        Self::parse(resp).await
+    }
+
+    async fn encrypt_password(
+        &self,
+        account_name: &str,
+        password: &str,
+    ) -> Result<(String, String), LoginError> {
+        let resp = self
+            .http_client
+            .get(self.url("GetPasswordRSAPublicKey"))
+            .query(&[("account_name", account_name)])
+            .send()
+            .await?;
+        let key: RsaKey = Self::parse(resp).await?;
+
+        let n = BigUint::parse_bytes(key.publickey_mod.as_bytes(), 16)
+            .ok_or(LoginError::MalformedKey)?;
+        let e = BigUint::parse_bytes(key.publickey_exp.as_bytes(), 16)
+            .ok_or(LoginError::MalformedKey)?;
+        let encrypted = RsaPublicKey::new(n, e)?.encrypt(
+            &mut rand::thread_rng(),
+            Pkcs1v15Encrypt,
+            password.as_bytes(),
+        )?;
+
+        Ok((base64::encode(encrypted), key.timestamp))
+    }
+
+    /// Starts logging in with the given username and password.
+    pub async fn begin(
+        &self,
+        account_name: &str,
+        password: &str,
+    ) -> Result<AuthSession, LoginError> {
+        let (encrypted_password, timestamp) = self.encrypt_password(account_name, password).await?;
+        let begun: BeginSession = self
+            .post(
+                "BeginAuthSessionViaCredentials",
+                &[
+                    ("account_name", account_name),
+                    ("encrypted_password", &encrypted_password),
+                    ("encryption_timestamp", &timestamp),
+                    ("remember_login", "true"),
+                    ("persistence", PERSISTENCE_PERSISTENT),
+                    ("platform_type", PLATFORM_TYPE_MOBILE_APP),
+                    ("device_friendly_name", DEVICE_NAME),
+                ],
+            )
+            .await?;
+
+        Ok(AuthSession {
+            client_id: begun.client_id,
+            request_id: begun.request_id,
+            steam_id: SteamId::try_from(begun.steamid)?,
+            interval: Duration::from_secs_f32(begun.interval.max(1.0)),
+            confirmations: begun
+                .allowed_confirmations
+                .iter()
+                .filter_map(|c| GuardType::from_confirmation_type(c.confirmation_type))
+                .collect(),
+        })
+    }
+
+    /// Confirms a login with a Steam Guard code.
+    pub async fn submit_code(&self, session: &AuthSession, code: &str) -> Result<(), LoginError> {
+        let guard_type = session.code_needed().ok_or(LoginError::NoCodeNeeded)?;
+        let _: Empty = self
+            .post(
+                "UpdateAuthSessionWithSteamGuardCode",
+                &[
+                    ("client_id", &session.client_id),
+                    ("steamid", &session.steam_id.to_string()),
+                    ("code", code.trim()),
+                    ("code_type", guard_type.code_type()),
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Waits for Steam to finish a login, once it has been confirmed.
+    pub async fn wait_for_tokens(
+        &self,
+        session: &mut AuthSession,
+    ) -> Result<LoginTokens, LoginError> {
+        let started = tokio::time::Instant::now();
+        loop {
+            let status: PollStatus = self
+                .post(
+                    "PollAuthSessionStatus",
+                    &[
+                        ("client_id", &session.client_id),
+                        ("request_id", &session.request_id),
+                    ],
+                )
+                .await?;
+            if let Some(client_id) = status.new_client_id {
+                session.client_id = client_id;
+            }
+
+            if let (Some(refresh_token), Some(access_token)) =
+                (status.refresh_token, status.access_token)
+            {
+                return Ok(LoginTokens {
+                    steam_id: session.steam_id,
+                    access_token,
+                    refresh_token,
+                });
+            }
+
+            if started.elapsed() >= MAX_WAIT {
+                return Err(LoginError::TimedOut);
+            }
+            tokio::time::sleep(session.interval).await;
+        }
+    }
+
+    /// Mints a new access token for an account we have logged in to.
+    pub async fn refresh(
+        &self,
+        steam_id: SteamId,
+        refresh_token: &str,
+    ) -> Result<String, LoginError> {
+        let token: AccessToken = self
+            .post(
+                "GenerateAccessTokenForApp",
+                &[
+                    ("refresh_token", refresh_token),
+                    ("steamid", &steam_id.to_string()),
+                ],
+            )
+            .await?;
+
+        Ok(token.access_token)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mock_steam::{Fixtures, ACCESS_TOKEN, GUARD_CODE, REFRESH_TOKEN, STEAM_ID};
+
+    use super::{GuardType, SteamAuthenticator};
+
+    #[tokio::test]
+    async fn test_login() {
+        let base = mock_steam::spawn(Fixtures::default());
+        let auth = SteamAuthenticator::new().with_base_url(base);
+
+        let mut session = auth.begin("mock", "hunter2").await.unwrap();
+        assert_eq!(session.code_needed(), Some(GuardType::EmailCode));
+        auth.submit_code(&session, GUARD_CODE).await.unwrap();
+
+        let tokens = auth.wait_for_tokens(&mut session).await.unwrap();
+        let steam_id = tokens.steam_id;
+        let creds = tokens.into_credentials();
+        assert!(creds
+            .as_string()
+            .ends_with(&format!("steamLoginSecure={STEAM_ID}%7C%7C{ACCESS_TOKEN}")));
+        assert_eq!(creds.refresh_token(), Some(REFRESH_TOKEN));
+
+        let renewed = auth.refresh(steam_id, REFRESH_TOKEN).await.unwrap();
+        assert_eq!(renewed, ACCESS_TOKEN);
+    }
+}
@@ -0,0 +1,75 @@
+use chrono::{DateTime, Utc};
+
+use crate::errors::FetchItemsError;
+use crate::parsing::{HistoryCursor, HistoryFilter};
+use crate::{FetchedHistory, InventoryIndex, SteamClient};
+
+/// Walks backwards through a user's Inventory History, one page at a time.
+pub struct HistoryBackfill<'a> {
+    client: &'a SteamClient,
+    until: DateTime<Utc>,
+
+    cursor: Option<HistoryCursor>,
+    done: bool,
+    // Shared between pages, so we read the inventory at most once.
+    index: InventoryIndex,
+}
+
+impl<'a> HistoryBackfill<'a> {
+    pub(crate) fn new(client: &'a SteamClient, until: DateTime<Utc>) -> Self {
+        Self {
+            client,
+            until,
+
+            cursor: None,
+            done: false,
+            index: InventoryIndex::default(),
+        }
+    }
+
+    /// Fetches and prepares the next page of history.
+    ///
+    /// Returns `None` once we have reached the time we were asked to backfill
+    /// to, or there is no more history to fetch. Pages containing nothing of
+    /// interest are returned as empty batches.
+    pub async fn next_batch(&mut self) -> Result<Option<FetchedHistory>, FetchItemsError> {
+        if self.done {
+            return Ok(None);
+        }
+
+        let page = self.client.fetch_history_page(self.cursor.as_ref()).await?;
+        let filter = HistoryFilter {
+            since: Some(&self.until),
+            last_seen_inventory_id: None,
+            kinds: &self.client.event_kinds,
+            language: self.client.language,
+        };
+        let parsed = page.parse(&filter);
+        // Items gained long ago have often been sold or traded away since,
+        // so we rely on the page's own descriptions of them where we can.
+        self.index.add_history_items(page.items);
+
+        self.done = parsed.reached_end || page.next.is_none();
+        self.cursor = page.next;
+
+        log::debug!(
+            "backfill page had {} rows, {} of interest",
+            parsed.rows,
+            parsed.events.len()
+        );
+        if parsed.events.is_empty() {
+            return Ok(Some(FetchedHistory {
+                failures: parsed.failures,
+                ..Default::default()
+            }));
+        }
+
+        let mut fetched = self
+            .client
+            .prepare_unlocks(&mut self.index, parsed.events)
+            .await?;
+        fetched.failures = parsed.failures;
+
+        Ok(Some(fetched))
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+#[error("unknown currency: {0}")]
+pub struct UnknownCurrency(String);
+
+macro_rules! currencies {
+    ($($variant:ident = $id:literal, $decimal_sep:literal;)*) => {
+        /// A currency the Steam Market can show prices in, numbered as Steam
+        /// does (`ECurrencyCode`).
+        #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+        pub enum Currency {
+            #[default]
+            $($variant = $id,)*
+        }
+
+        impl Currency {
+            pub const ALL: &'static [Currency] = &[$(Currency::$variant,)*];
+
+            /// The ISO 4217 code for this currency.
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $(Currency::$variant => stringify!($variant),)*
+                }
+            }
+
+            /// The character Steam separates whole and fractional amounts
+            /// with when formatting prices in this currency.
+            fn decimal_separator(&self) -> char {
+                match self {
+                    $(Currency::$variant => $decimal_sep,)*
+                }
+            }
+        }
+    };
+}
+
+currencies! {
+    USD = 1, '.';
+    GBP = 2, '.';
+    EUR = 3, ',';
+    CHF = 4, '.';
+    RUB = 5, ',';
+    PLN = 6, ',';
+    BRL = 7, ',';
+    JPY = 8, '.';
+    NOK = 9, ',';
+    IDR = 10, ',';
+    MYR = 11, '.';
+    PHP = 12, '.';
+    SGD = 13, '.';
+    THB = 14, '.';
+    VND = 15, ',';
+    KRW = 16, '.';
+    TRY = 17, ',';
+    UAH = 18, ',';
+    MXN = 19, '.';
+    CAD = 20, '.';
+    AUD = 21, '.';
+    NZD = 22, '.';
+    CNY = 23, '.';
+    INR = 24, '.';
+    CLP = 25, ',';
+    PEN = 26, '.';
+    COP = 27, ',';
+    ZAR = 28, '.';
+    HKD = 29, '.';
+    TWD = 30, '.';
+    SAR = 31, '.';
+    AED = 32, '.';
+    ARS = 34, ',';
+    ILS = 35, '.';
+    KZT = 37, ',';
+    KWD = 38, '.';
+    QAR = 39, '.';
+    CRC = 40, ',';
+    UYU = 41, ',';
+}
+
+impl Currency {
+    /// The id Steam uses for this currency in requests.
+    pub fn steam_id(&self) -> u32 {
+        *self as u32
+    }
+
+    /// Parses a price as Steam formats it in this currency, e.g. "1,23€",
+    /// "R$ 4,50", "CDN$ 2.10" or "12,34 pуб.".
+    pub fn parse_price(&self, price: &str) -> Option<Decimal> {
+        // Skip the currency symbol on either side. Some symbols end in a dot
+        // ("pуб.") or start with one ("S/."), so trim to the digits first.
+        let start = price.find(|c: char| c.is_ascii_digit())?;
+        let end = price.rfind(|c: char| c.is_ascii_digit() || c == '-')? + 1;
+        if end <= start {
+            return None;
+        }
+
+        // Whole amounts are shown as "5,--€" in some currencies.
+        let decimal_sep = self.decimal_separator();
+        let amount: String = price[start..end]
+            .replace("--", "00")
+            .chars()
+            .filter_map(|c| match c {
+                c if c.is_ascii_digit() => Some(c),
+                c if c == decimal_sep => Some('.'),
+                // Thousands separators: commas, dots and (non-breaking)
+                // spaces.
+                _ => None,
+            })
+            .collect();
+
+        Decimal::from_str(&amount).ok()
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Currency {
+    type Err = UnknownCurrency;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Currency::ALL
+            .iter()
+            .find(|c| c.as_str().eq_ignore_ascii_case(s.trim()))
+            .copied()
+            .ok_or_else(|| UnknownCurrency(s.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal::Decimal;
+
+    use super::Currency;
+
+    #[test]
+    fn test_parse_price() {
+        let cases = [
+            (Currency::USD, "$1,234.56", Decimal::new(123456, 2)),
+            (Currency::EUR, "1,23€", Decimal::new(123, 2)),
+            (Currency::EUR, "5,--€", Decimal::new(500, 2)),
+            (Currency::EUR, "1.234,56€", Decimal::new(123456, 2)),
+            (Currency::BRL, "R$ 4,50", Decimal::new(450, 2)),
+            (Currency::CAD, "CDN$ 2.10", Decimal::new(210, 2)),
+            (Currency::RUB, "12,34 pуб.", Decimal::new(1234, 2)),
+            (Currency::PEN, "S/.1.23", Decimal::new(123, 2)),
+            (Currency::IDR, "Rp 12 345", Decimal::new(12345, 0)),
+            (Currency::KZT, "1\u{a0}234,50₸", Decimal::new(123450, 2)),
+            (Currency::JPY, "¥ 1,234", Decimal::new(1234, 0)),
+        ];
+
+        for (currency, price, expected) in cases {
+            assert_eq!(currency.parse_price(price), Some(expected), "{price}");
+        }
+        assert_eq!(Currency::USD.parse_price("$"), None);
+    }
+}
@@ -0,0 +1,291 @@
+use std::fmt;
+
+use scraper::{Html, Selector};
+
+use crate::event::{EventKind, EventKinds};
+use crate::language::Language;
+use crate::parsing::{
+    history_id_from_row, is_authenticated, parse_history_cursor, parse_history_row,
+    AuthenticationParseError, HistoryFilter, ParseFailure, ParseSuccess, DESCRIPTION_SELECTOR,
+    INFO_SELECTOR, LOGGED_IN_ACTION_SELECTOR, LOGGED_OUT_ACTION_SELECTOR, LOGIN_AREA_SELECTOR,
+    PLUS_MINUS_SELECTOR, TRADE_DATE_SELECTOR, TRADE_ITEM_IMG_SELECTOR, TRADE_ITEM_NAME_SELECTOR,
+    TRADE_ITEM_SELECTOR, TRADE_SELECTOR, USER_ID_SELECTOR,
+};
+
+/// Whether a selector should match anything on the page we were given.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Expectation {
+    Present,
+    Absent,
+    /// We can't tell, because we don't know whether the page is logged in.
+    Unknown,
+}
+
+/// How many nodes a single selector matched on a page.
+#[derive(Debug)]
+pub struct SelectorReport {
+    pub name: &'static str,
+    pub matches: usize,
+    pub expected: Expectation,
+}
+
+impl SelectorReport {
+    /// Whether the selector matched (or didn't) as it should have.
+    pub fn is_as_expected(&self) -> bool {
+        match self.expected {
+            Expectation::Present => self.matches > 0,
+            Expectation::Absent => self.matches == 0,
+            Expectation::Unknown => true,
+        }
+    }
+}
+
+/// What happened when we tried to parse a single history row.
+#[derive(Debug)]
+pub enum RowOutcome {
+    Parsed(EventKind),
+    Ignored,
+    Failed(ParseFailure),
+}
+
+#[derive(Debug)]
+pub struct RowReport {
+    pub history_id: Option<String>,
+    pub outcome: RowOutcome,
+}
+
+/// Results of running an Inventory History page through our parser.
+#[derive(Debug)]
+pub struct Diagnosis {
+    pub authenticated: Result<bool, AuthenticationParseError>,
+    pub has_cursor: bool,
+    pub selectors: Vec<SelectorReport>,
+    pub rows: Vec<RowReport>,
+}
+
+impl Diagnosis {
+    /// Whether we found any rows on the page, and could parse all of them.
+    pub fn is_healthy(&self) -> bool {
+        !self.rows.is_empty()
+            && self
+                .rows
+                .iter()
+                .all(|r| !matches!(r.outcome, RowOutcome::Failed(_)))
+    }
+}
+
+/// Every selector we use, and whether it should match given the page's login
+/// state, where that's known.
+fn selectors(authenticated: Option<bool>) -> [(&'static str, &'static Selector, Expectation); 12] {
+    let (logged_in, logged_out) = match authenticated {
+        Some(true) => (Expectation::Present, Expectation::Absent),
+        Some(false) => (Expectation::Absent, Expectation::Present),
+        None => (Expectation::Unknown, Expectation::Unknown),
+    };
+
+    [
+        (
+            "LOGIN_AREA_SELECTOR",
+            &LOGIN_AREA_SELECTOR,
+            Expectation::Present,
+        ),
+        (
+            "LOGGED_IN_ACTION_SELECTOR",
+            &LOGGED_IN_ACTION_SELECTOR,
+            logged_in,
+        ),
+        (
+            "LOGGED_OUT_ACTION_SELECTOR",
+            &LOGGED_OUT_ACTION_SELECTOR,
+            logged_out,
+        ),
+        ("TRADE_SELECTOR", &TRADE_SELECTOR, logged_in),
+        ("TRADE_DATE_SELECTOR", &TRADE_DATE_SELECTOR, logged_in),
+        ("DESCRIPTION_SELECTOR", &DESCRIPTION_SELECTOR, logged_in),
+        ("INFO_SELECTOR", &INFO_SELECTOR, logged_in),
+        ("PLUS_MINUS_SELECTOR", &PLUS_MINUS_SELECTOR, logged_in),
+        ("TRADE_ITEM_SELECTOR", &TRADE_ITEM_SELECTOR, logged_in),
+        (
+            "TRADE_ITEM_IMG_SELECTOR",
+            &TRADE_ITEM_IMG_SELECTOR,
+            logged_in,
+        ),
+        (
+            "TRADE_ITEM_NAME_SELECTOR",
+            &TRADE_ITEM_NAME_SELECTOR,
+            logged_in,
+        ),
+        // This is only on the profile page, so says nothing about history.
+        ("USER_ID_SELECTOR", &USER_ID_SELECTOR, Expectation::Unknown),
+    ]
+}
+
+/// Runs a saved or freshly-fetched Inventory History page through every
+/// selector and parser, to find out what has changed when parsing breaks.
+pub fn diagnose(page: &str, language: Language) -> Diagnosis {
+    let has_cursor = parse_history_cursor(page).is_some();
+    let page = Html::parse_document(page);
+    let authenticated = is_authenticated(&page);
+
+    let selectors = selectors(authenticated.as_ref().ok().copied())
+        .into_iter()
+        .map(|(name, selector, expected)| SelectorReport {
+            name,
+            matches: page.select(selector).count(),
+            expected,
+        })
+        .collect();
+
+    let kinds: EventKinds = EventKind::ALL.into_iter().collect();
+    let filter = HistoryFilter {
+        since: None,
+        last_seen_inventory_id: None,
+        kinds: &kinds,
+        language,
+    };
+    let rows = page
+        .select(&TRADE_SELECTOR)
+        .map(|row| {
+            let outcome = match parse_history_row(row, &filter) {
+                Ok(ParseSuccess::Event(e)) => RowOutcome::Parsed(e.kind()),
+                Ok(_) => RowOutcome::Ignored,
+                Err(e) => RowOutcome::Failed(e),
+            };
+
+            RowReport {
+                history_id: history_id_from_row(row),
+                outcome,
+            }
+        })
+        .collect();
+
+    Diagnosis {
+        authenticated,
+        has_cursor,
+        selectors,
+        rows,
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.authenticated {
+            Ok(true) => writeln!(f, "login state: logged in")?,
+            Ok(false) => writeln!(f, "login state: logged out")?,
+            Err(e) => writeln!(f, "login state: unknown ({e})")?,
+        }
+        writeln!(f, "history cursor: {}", self.has_cursor)?;
+
+        writeln!(f, "selectors:")?;
+        for s in self.selectors.iter() {
+            let status = match (s.is_as_expected(), s.expected) {
+                (true, _) => "ok",
+                (false, Expectation::Absent) => "UNEXPECTED MATCH",
+                (false, _) => "NO MATCH",
+            };
+            writeln!(f, "  {:<28} {:>4} {status}", s.name, s.matches)?;
+        }
+
+        writeln!(f, "rows:")?;
+        for (i, row) in self.rows.iter().enumerate() {
+            let history_id = row.history_id.as_deref().unwrap_or("unknown");
+            match &row.outcome {
+                RowOutcome::Parsed(kind) => writeln!(f, "  {i:>3} {history_id}: {kind}")?,
+                RowOutcome::Ignored => writeln!(f, "  {i:>3} {history_id}: ignored")?,
+                RowOutcome::Failed(e) => writeln!(f, "  {i:>3} {history_id}: FAILED: {e}")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{diagnose, Expectation, RowOutcome};
+    use crate::language::Language;
+
+    #[test]
+    fn test_diagnose_reports_failures() {
+        let page = r#"<html><body>
+            <div id="global_actions"><a id="account_pulldown">me</a></div>
+            <div class="tradehistoryrow">
+                <div class="tradehistory_date">Oct 31, 2021<div class="tradehistory_timestamp">1:50pm</div></div>
+                <div class="tradehistory_content">
+                    <div class="tradehistory_event_description">Unlocked a container</div>
+                </div>
+            </div>
+        </body></html>"#;
+
+        let diagnosis = diagnose(page, Language::English);
+
+        assert!(matches!(diagnosis.authenticated, Ok(true)));
+        assert!(!diagnosis.has_cursor);
+        let trades = diagnosis
+            .selectors
+            .iter()
+            .find(|s| s.name == "TRADE_SELECTOR")
+            .unwrap();
+        assert_eq!(trades.matches, 1);
+        assert_eq!(diagnosis.rows.len(), 1);
+        assert!(matches!(diagnosis.rows[0].outcome, RowOutcome::Failed(_)));
+        assert!(!diagnosis.is_healthy());
+    }
+
+    #[test]
+    fn test_diagnose_only_flags_unexpected_selectors() {
+        let logged_out = r#"<html><body>
+            <div id="global_actions"><a id="language_pulldown">language</a></div>
+        </body></html>"#;
+
+        let diagnosis = diagnose(logged_out, Language::English);
+
+        assert!(matches!(diagnosis.authenticated, Ok(false)));
+        let user_id = diagnosis
+            .selectors
+            .iter()
+            .find(|s| s.name == "USER_ID_SELECTOR")
+            .unwrap();
+        assert_eq!(user_id.expected, Expectation::Unknown);
+        assert!(diagnosis.selectors.iter().all(|s| s.is_as_expected()));
+
+        let logged_in = r#"<html><body>
+            <div id="global_actions">
+                <a id="account_pulldown">me</a>
+                <a id="language_pulldown">language</a>
+            </div>
+        </body></html>"#;
+
+        let diagnosis = diagnose(logged_in, Language::English);
+
+        let unexpected: Vec<_> = diagnosis
+            .selectors
+            .iter()
+            .filter(|s| !s.is_as_expected())
+            .map(|s| s.name)
+            .collect();
+        assert!(unexpected.contains(&"LOGGED_OUT_ACTION_SELECTOR"));
+        assert!(unexpected.contains(&"TRADE_SELECTOR"));
+        assert!(!unexpected.contains(&"LOGGED_IN_ACTION_SELECTOR"));
+    }
+
+    #[test]
+    fn test_diagnose_healthy_page_has_no_unexpected_selectors() {
+        let page = mock_steam::Fixtures::default().history;
+
+        let diagnosis = diagnose(&page, Language::English);
+
+        assert!(matches!(diagnosis.authenticated, Ok(true)));
+        assert!(diagnosis.is_healthy());
+        let unexpected: Vec<_> = diagnosis
+            .selectors
+            .iter()
+            .filter(|s| !s.is_as_expected())
+            .map(|s| s.name)
+            .collect();
+        assert!(
+            unexpected.is_empty(),
+            "unexpected selectors: {unexpected:?}"
+        );
+    }
+}
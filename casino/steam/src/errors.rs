@@ -1,14 +1,35 @@
+use std::time::Duration;
+
 use reqwest::StatusCode;
 use thiserror::Error;
 
-use super::parsing::{AuthenticationParseError, ParseFailure};
+use super::parsing::AuthenticationParseError;
+use super::SteamIdParseError;
 
-// TODO: Handle rate limiting errors as its' own special category??
+/// Steam told us to slow down, and we couldn't wait long enough to retry.
+#[derive(Debug, Error)]
+#[error("rate limited by steam (retry after {retry_after:?})")]
+pub struct RateLimited {
+    /// How long we were asked to wait, if we were told.
+    pub retry_after: Option<Duration>,
+}
+
+#[derive(Debug, Error)]
+pub enum RequestError {
+    #[error("HTTP error: {0}")]
+    Transport(#[from] reqwest::Error),
+    #[error(transparent)]
+    RateLimited(#[from] RateLimited),
+    #[error("request has a streaming body, so can't be retried")]
+    Unretryable,
+}
 
 #[derive(Debug, Error)]
 pub enum FetchInventoryError {
     #[error("HTTP error: {0}")]
     Transport(#[from] reqwest::Error),
+    #[error(transparent)]
+    Request(#[from] RequestError),
     #[error("error deserlializing inventory: {0}")]
     Deserializing(#[from] serde_json::Error),
 }
@@ -17,14 +38,18 @@ pub enum FetchInventoryError {
 pub enum FetchNewUnpreparedItemsError {
     #[error("HTTP error: {0}")]
     Transport(#[from] reqwest::Error),
+    #[error(transparent)]
+    Request(#[from] RequestError),
     #[error("Authentication failure")]
     Authentication,
     #[error("unhandled status code: {0}")]
     UnhandledStatusCode(StatusCode),
     #[error("failed to parse any history from steam site")]
     NoHistoryFound,
-    #[error("failed to parse inventory history: {0}")]
-    PageParse(#[from] ParseFailure),
+    #[error("steam did not return a page of history")]
+    Unsuccessful,
+    #[error("error deserialising history page: {0}")]
+    Deserializing(#[from] serde_json::Error),
     #[error("error finding login state: {0}")]
     AuthenticationParse(#[from] AuthenticationParseError),
     #[error("not logged in")]
@@ -43,18 +68,52 @@ pub enum FetchItemsError {
     PreparingItems(#[from] PrepareItemsError),
 }
 
+impl FetchItemsError {
+    /// Whether we were rate-limited by Steam, and for how long.
+    pub fn rate_limited(&self) -> Option<&RateLimited> {
+        use FetchInventoryError as Inv;
+        use RequestError::RateLimited as Limited;
+
+        match self {
+            FetchItemsError::FetchInventory(Inv::Request(Limited(e)))
+            | FetchItemsError::PreparingItems(PrepareItemsError::FetchInventory(Inv::Request(
+                Limited(e),
+            )))
+            | FetchItemsError::FetchUnpreparedItems(FetchNewUnpreparedItemsError::Request(
+                Limited(e),
+            )) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Whether this failed because our Steam session is no longer valid.
+    pub fn is_not_authenticated(&self) -> bool {
+        matches!(
+            self,
+            FetchItemsError::FetchUnpreparedItems(
+                FetchNewUnpreparedItemsError::NotAuthenticated
+                    | FetchNewUnpreparedItemsError::Authentication
+            )
+        )
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum PrepareItemsError {
     #[error("http error fetching inventory: {0}")]
     Transport(#[from] reqwest::Error),
     #[error("error deserialising inventory: {0}")]
     Deserializing(#[from] serde_json::Error),
+    #[error("error fetching more of the inventory: {0}")]
+    FetchInventory(#[from] FetchInventoryError),
 }
 
 #[derive(Debug, Error)]
 pub enum MarketPriceFetchError {
     #[error("http error: {0}")]
     Transport(#[from] reqwest::Error),
+    #[error(transparent)]
+    Request(#[from] RequestError),
     #[error("error deserialising market prices: {0}")]
     Deserializing(#[from] serde_json::Error),
 }
@@ -63,10 +122,32 @@ pub enum MarketPriceFetchError {
 pub enum AuthenticationCheckError {
     #[error("http error: {0}")]
     Transport(#[from] reqwest::Error),
+    #[error(transparent)]
+    Request(#[from] RequestError),
     #[error("error parsing authentication data: {0}")]
     Parse(#[from] AuthenticationParseError),
 }
 
+#[derive(Debug, Error)]
+pub enum LoginError {
+    #[error("http error: {0}")]
+    Transport(#[from] reqwest::Error),
+    #[error("error deserialising login response: {0}")]
+    Deserializing(#[from] serde_json::Error),
+    #[error("steam sent a malformed rsa key")]
+    MalformedKey,
+    #[error("error encrypting password: {0}")]
+    Encrypting(#[from] rsa::Error),
+    #[error("steam rejected the login (eresult {0})")]
+    Rejected(u32),
+    #[error("steam sent an invalid steam id: {0}")]
+    InvalidSteamId(#[from] SteamIdParseError),
+    #[error("this login does not need a steam guard code")]
+    NoCodeNeeded,
+    #[error("timed out waiting for the login to be confirmed")]
+    TimedOut,
+}
+
 #[derive(Debug, Error)]
 pub enum LocalPrepareError {
     #[error("could not find item description in inventory")]
@@ -76,3 +157,12 @@ pub enum LocalPrepareError {
     #[error("could not find in-game inspect link")]
     NoInspectLink,
 }
+
+impl LocalPrepareError {
+    /// Whether this may clear up by itself, e.g. because Steam hasn't added
+    /// the item to our inventory yet. Items without an inspect link, like
+    /// graffiti or music kits, will never have one.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::NoDescription | Self::NoAsset)
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::parsing::{InventoryId, RawTradeUp, RawUnlock, TrivialItem};
+
+/// The kinds of Inventory History event we know how to recognise.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Unbox,
+    TradeUp,
+    MarketPurchase,
+    MarketSale,
+    Trade,
+    CaseDrop,
+    RankDrop,
+    StorageUnitMove,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 8] = [
+        EventKind::Unbox,
+        EventKind::TradeUp,
+        EventKind::MarketPurchase,
+        EventKind::MarketSale,
+        EventKind::Trade,
+        EventKind::CaseDrop,
+        EventKind::RankDrop,
+        EventKind::StorageUnitMove,
+    ];
+
+    /// The kinds of event we collect if not told otherwise.
+    pub const DEFAULT: [EventKind; 2] = [EventKind::Unbox, EventKind::TradeUp];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Unbox => "unbox",
+            EventKind::TradeUp => "trade_up",
+            EventKind::MarketPurchase => "market_purchase",
+            EventKind::MarketSale => "market_sale",
+            EventKind::Trade => "trade",
+            EventKind::CaseDrop => "case_drop",
+            EventKind::RankDrop => "rank_drop",
+            EventKind::StorageUnitMove => "storage_unit_move",
+        }
+    }
+
+    /// Whether every event of this kind adds a new item to the inventory.
+    ///
+    /// If we're only interested in these kinds, we can skip fetching history
+    /// whenever the newest item in the inventory hasn't changed.
+    pub fn always_gains_items(&self) -> bool {
+        matches!(
+            self,
+            EventKind::Unbox
+                | EventKind::TradeUp
+                | EventKind::MarketPurchase
+                | EventKind::CaseDrop
+                | EventKind::RankDrop
+        )
+    }
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("unknown event kind: {0}")]
+pub struct UnknownEventKind(String);
+
+impl FromStr for EventKind {
+    type Err = UnknownEventKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        EventKind::ALL
+            .into_iter()
+            .find(|k| k.as_str() == s)
+            .ok_or_else(|| UnknownEventKind(s.to_string()))
+    }
+}
+
+pub type EventKinds = HashSet<EventKind>;
+
+/// An item gained in a [`RawTransfer`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GainedItem {
+    pub item: TrivialItem,
+    pub id: InventoryId,
+}
+
+/// Represents a generic transaction fetched from the Inventory History page,
+/// where some items were lost and others gained.
+#[derive(Debug)]
+pub struct RawTransfer {
+    pub history_id: String,
+    pub description: String,
+
+    pub lost: Vec<TrivialItem>,
+    pub gained: Vec<GainedItem>,
+
+    pub at: DateTime<Utc>,
+}
+
+/// A typed event from the Inventory History page.
+#[derive(Debug)]
+pub enum InventoryEvent {
+    Unbox(RawUnlock),
+    TradeUp(RawTradeUp),
+    MarketPurchase(RawTransfer),
+    MarketSale(RawTransfer),
+    Trade(RawTransfer),
+    CaseDrop(RawTransfer),
+    RankDrop(RawTransfer),
+    StorageUnitMove(RawTransfer),
+}
+
+impl InventoryEvent {
+    /// Wraps a generic transfer in the variant for the given kind.
+    ///
+    /// Returns `None` for kinds which aren't represented as transfers.
+    pub fn from_transfer(kind: EventKind, transfer: RawTransfer) -> Option<Self> {
+        let event = match kind {
+            EventKind::MarketPurchase => InventoryEvent::MarketPurchase(transfer),
+            EventKind::MarketSale => InventoryEvent::MarketSale(transfer),
+            EventKind::Trade => InventoryEvent::Trade(transfer),
+            EventKind::CaseDrop => InventoryEvent::CaseDrop(transfer),
+            EventKind::RankDrop => InventoryEvent::RankDrop(transfer),
+            EventKind::StorageUnitMove => InventoryEvent::StorageUnitMove(transfer),
+            EventKind::Unbox | EventKind::TradeUp => return None,
+        };
+
+        Some(event)
+    }
+
+    pub fn kind(&self) -> EventKind {
+        match self {
+            InventoryEvent::Unbox(_) => EventKind::Unbox,
+            InventoryEvent::TradeUp(_) => EventKind::TradeUp,
+            InventoryEvent::MarketPurchase(_) => EventKind::MarketPurchase,
+            InventoryEvent::MarketSale(_) => EventKind::MarketSale,
+            InventoryEvent::Trade(_) => EventKind::Trade,
+            InventoryEvent::CaseDrop(_) => EventKind::CaseDrop,
+            InventoryEvent::RankDrop(_) => EventKind::RankDrop,
+            InventoryEvent::StorageUnitMove(_) => EventKind::StorageUnitMove,
+        }
+    }
+
+    pub fn history_id(&self) -> &str {
+        match self {
+            InventoryEvent::Unbox(u) => &u.history_id,
+            InventoryEvent::TradeUp(t) => &t.history_id,
+            InventoryEvent::MarketPurchase(t)
+            | InventoryEvent::MarketSale(t)
+            | InventoryEvent::Trade(t)
+            | InventoryEvent::CaseDrop(t)
+            | InventoryEvent::RankDrop(t)
+            | InventoryEvent::StorageUnitMove(t) => &t.history_id,
+        }
+    }
+
+    pub fn at(&self) -> &DateTime<Utc> {
+        match self {
+            InventoryEvent::Unbox(u) => &u.at,
+            InventoryEvent::TradeUp(t) => &t.at,
+            InventoryEvent::MarketPurchase(t)
+            | InventoryEvent::MarketSale(t)
+            | InventoryEvent::Trade(t)
+            | InventoryEvent::CaseDrop(t)
+            | InventoryEvent::RankDrop(t)
+            | InventoryEvent::StorageUnitMove(t) => &t.at,
+        }
+    }
+
+    /// The first item this event added to the inventory, if any.
+    pub fn gained_id(&self) -> Option<&InventoryId> {
+        match self {
+            InventoryEvent::Unbox(u) => Some(&u.item),
+            InventoryEvent::TradeUp(t) => Some(&t.item),
+            InventoryEvent::MarketPurchase(t)
+            | InventoryEvent::MarketSale(t)
+            | InventoryEvent::Trade(t)
+            | InventoryEvent::CaseDrop(t)
+            | InventoryEvent::RankDrop(t)
+            | InventoryEvent::StorageUnitMove(t) => t.gained.first().map(|g| &g.id),
+        }
+    }
+}
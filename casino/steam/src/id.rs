@@ -1,17 +1,22 @@
 use regex::Regex;
-use reqwest::Url;
+use reqwest::{Client, Url};
 use scraper::Html;
 use thiserror::Error;
 
+use super::errors::RequestError;
 use super::parsing::{get_userid, UserIdParseError};
+use super::retry::RetryPolicy;
+use super::steam_id::SteamId;
+
+const COMMUNITY_URL: &str = "https://steamcommunity.com";
 
 lazy_static::lazy_static! {
-    static ref PROFILE_URL_REGEX: Regex = Regex::new(r#"steamcommunity\.com/(?:id/([a-zA-Z0-9-_]+)|profiles/([0-9]+))"#).unwrap();
+    static ref PROFILE_URL_REGEX: Regex = Regex::new(r#"steamcommunity\.com/id/([a-zA-Z0-9-_]+)"#).unwrap();
 }
 
 #[derive(Clone)]
 pub struct Id {
-    id: u64,
+    id: SteamId,
     vanity: Option<String>,
 
     profile_url: Url,
@@ -20,13 +25,19 @@ pub struct Id {
 }
 
 impl Id {
-    pub fn new(id: u64, vanity: Option<String>) -> Self {
+    pub fn new(id: SteamId, vanity: Option<String>) -> Self {
+        Self::with_base_url(id, vanity, COMMUNITY_URL)
+    }
+
+    /// Creates an Id whose URLs point at the given Steam Community host,
+    /// instead of steamcommunity.com.
+    pub fn with_base_url(id: SteamId, vanity: Option<String>, base: &str) -> Self {
         let profile_url = match vanity.as_deref() {
-            Some(v) => format_profile_url_vanity(v),
-            None => format_profile_url_id(id),
+            Some(v) => format_profile_url_vanity(base, v),
+            None => format_profile_url_id(base, id),
         };
 
-        let inventory_url = format_inventory_url(id);
+        let inventory_url = format_inventory_url(base, id);
         let inventory_history_url = format_inventory_history_url(profile_url.as_str());
 
         Self {
@@ -40,12 +51,26 @@ impl Id {
     }
 
     pub async fn try_from_url(url_ish: &str) -> Result<Self, IdUrlParseError> {
+        Self::try_from_url_with_base(url_ish, COMMUNITY_URL, &RetryPolicy::default()).await
+    }
+
+    /// Looks up the given Steam profile on another Steam Community host,
+    /// instead of steamcommunity.com, backing off as the given policy says
+    /// if Steam rate-limits us.
+    pub async fn try_from_url_with_base(
+        url_ish: &str,
+        base: &str,
+        retry_policy: &RetryPolicy,
+    ) -> Result<Self, IdUrlParseError> {
         let url_match = parse_profile_url(url_ish).ok_or(IdUrlParseError::InvalidProfileUrl)?;
         let url = match &url_match {
-            ProfileUrlMatch::SteamId(id) => format_profile_url_id(*id),
-            ProfileUrlMatch::VanityUrl(v) => format_profile_url_vanity(v),
+            ProfileUrlMatch::SteamId(id) => format_profile_url_id(base, *id),
+            ProfileUrlMatch::VanityUrl(v) => format_profile_url_vanity(base, v),
         };
-        let resp = reqwest::get(url.as_ref()).await?;
+        let client = Client::new();
+        let resp = retry_policy
+            .execute(&client, client.get(url.as_ref()).build()?)
+            .await?;
         let profile_url = resp.url().to_owned();
         if profile_url != url {
             // Should we do something different if we're given a by-id-only
@@ -63,7 +88,7 @@ impl Id {
             ProfileUrlMatch::SteamId(_) => None,
             ProfileUrlMatch::VanityUrl(v) => Some(v),
         };
-        let inventory_url = format_inventory_url(id);
+        let inventory_url = format_inventory_url(base, id);
         let inventory_history_url = format_inventory_history_url(profile_url.as_str());
 
         Ok(Self {
@@ -76,10 +101,14 @@ impl Id {
         })
     }
 
-    pub fn user_id(&self) -> u64 {
+    pub fn user_id(&self) -> SteamId {
         self.id
     }
 
+    pub fn vanity(&self) -> Option<&str> {
+        self.vanity.as_deref()
+    }
+
     // NOTE: Should we accept a different count, etc here?
     pub fn inventory_url(&self) -> &str {
         self.inventory_url.as_ref()
@@ -100,6 +129,8 @@ pub enum IdUrlParseError {
     InvalidProfileUrl,
     #[error("http error: {0}")]
     TransportError(#[from] reqwest::Error),
+    #[error(transparent)]
+    Request(#[from] RequestError),
     #[error("error parsing user information: {0}")]
     ValidationError(#[from] UserIdParseError),
 }
@@ -107,39 +138,33 @@ pub enum IdUrlParseError {
 #[derive(Debug, PartialEq, Eq)]
 enum ProfileUrlMatch {
     VanityUrl(String),
-    SteamId(u64),
+    SteamId(SteamId),
 }
 
+/// Accepts either a vanity profile URL, or anything [`SteamId`] can parse.
 fn parse_profile_url(url: &str) -> Option<ProfileUrlMatch> {
-    let matches = PROFILE_URL_REGEX.captures(url)?;
-
-    if let Some(m) = matches.get(1) {
-        Some(ProfileUrlMatch::VanityUrl(m.as_str().to_string()))
-    } else if let Some(m) = matches.get(2) {
-        // validity should be guaranteed by regex
-        let steam_id: u64 = m.as_str().parse().unwrap();
-        Some(ProfileUrlMatch::SteamId(steam_id))
-    } else {
-        None
+    if let Some(matches) = PROFILE_URL_REGEX.captures(url) {
+        return Some(ProfileUrlMatch::VanityUrl(matches[1].to_string()));
     }
+
+    url.parse().ok().map(ProfileUrlMatch::SteamId)
 }
 
-fn format_profile_url_id(id: u64) -> Url {
-    format!("https://steamcommunity.com/profiles/{}", id)
-        .parse()
-        .unwrap()
+fn format_profile_url_id(base: &str, id: SteamId) -> Url {
+    format!("{}/profiles/{}", base, id).parse().unwrap()
 }
 
-fn format_profile_url_vanity(vanity: &str) -> Url {
-    format!("https://steamcommunity.com/id/{}", vanity)
-        .parse()
-        .unwrap()
+fn format_profile_url_vanity(base: &str, vanity: &str) -> Url {
+    format!("{}/id/{}", base, vanity).parse().unwrap()
 }
 
-fn format_inventory_url(id: u64) -> Url {
+// The most items Steam will return in one page of an inventory.
+const INVENTORY_PAGE_SIZE: u32 = 2000;
+
+fn format_inventory_url(base: &str, id: SteamId) -> Url {
     format!(
-        "https://steamcommunity.com/inventory/{}/730/2?l=english&count=25",
-        id
+        "{}/inventory/{}/730/2?l=english&count={}",
+        base, id, INVENTORY_PAGE_SIZE
     )
     .parse()
     .unwrap()
@@ -154,6 +179,7 @@ fn format_inventory_history_url(base: &str) -> Url {
 #[cfg(test)]
 mod test {
     use super::{parse_profile_url, ProfileUrlMatch};
+    use crate::steam_id::SteamId;
 
     #[test]
     fn test_profile_url_parsing_vanity() {
@@ -168,7 +194,7 @@ mod test {
     fn test_profile_url_parsing_steamid() {
         let by_steamid = "https://steamcommunity.com/profiles/76561198000494793";
         let parsed = parse_profile_url(by_steamid).unwrap();
-        let expected = ProfileUrlMatch::SteamId(76561198000494793);
+        let expected = ProfileUrlMatch::SteamId(SteamId::try_from(76561198000494793).unwrap());
 
         assert_eq!(parsed, expected);
     }
@@ -0,0 +1,41 @@
+use chrono::{DateTime, TimeZone, Utc};
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Claims {
+    exp: i64,
+}
+
+/// Finds when a `steamLoginSecure` token expires.
+///
+/// These are `<steamid>||<jwt>`, usually percent-encoded. We only read the
+/// expiry out of the token, we don't (and can't) verify it.
+pub(crate) fn expiry(login_token: &str) -> Option<DateTime<Utc>> {
+    let token = percent_decode_str(login_token).decode_utf8().ok()?;
+    let jwt = token.rsplit("||").next()?;
+    let payload = jwt.split('.').nth(1)?;
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+    let claims: Claims = serde_json::from_slice(&payload).ok()?;
+
+    Utc.timestamp_opt(claims.exp, 0).single()
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+
+    use super::expiry;
+
+    #[test]
+    fn test_expiry() {
+        // {"iss":"steam","sub":"76561198000000001","exp":1700000000}
+        let token = "76561198000000001%7C%7CeyJhbGciOiJFZERTQSJ9.eyJpc3MiOiJzdGVhbSIsInN1YiI6Ijc2NTYxMTk4MDAwMDAwMDAxIiwiZXhwIjoxNzAwMDAwMDAwfQ.c2ln";
+
+        assert_eq!(
+            expiry(token),
+            Some(Utc.timestamp_opt(1700000000, 0).unwrap())
+        );
+        assert_eq!(expiry("notajwt"), None);
+    }
+}
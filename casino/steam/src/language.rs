@@ -0,0 +1,390 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::event::EventKind;
+
+lazy_static::lazy_static! {
+    // Matches both 12-hour ("1:50pm") and 24-hour ("13:50") times.
+    static ref TIME_REGEX: Regex = Regex::new(r"(?i)(\d{1,2}):(\d{2})\s*([ap]m)?").unwrap();
+}
+
+/// Languages we know how to read the Inventory History page in.
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Language {
+    #[default]
+    English,
+    German,
+    Portuguese,
+    Brazilian,
+    Russian,
+}
+
+impl Language {
+    pub const ALL: [Language; 5] = [
+        Language::English,
+        Language::German,
+        Language::Portuguese,
+        Language::Brazilian,
+        Language::Russian,
+    ];
+
+    /// The name Steam uses for this language in the `l=` query parameter.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Language::English => "english",
+            Language::German => "german",
+            Language::Portuguese => "portuguese",
+            Language::Brazilian => "brazilian",
+            Language::Russian => "russian",
+        }
+    }
+
+    /// Names for each month, in order, including any alternate forms Steam
+    /// uses. Abbreviations are matched against the start of these.
+    fn months(&self) -> &'static [&'static [&'static str]; 12] {
+        match self {
+            Language::English => &[
+                &["january"],
+                &["february"],
+                &["march"],
+                &["april"],
+                &["may"],
+                &["june"],
+                &["july"],
+                &["august"],
+                &["september"],
+                &["october"],
+                &["november"],
+                &["december"],
+            ],
+            Language::German => &[
+                &["januar", "jänner"],
+                &["februar"],
+                &["märz", "mrz"],
+                &["april"],
+                &["mai"],
+                &["juni"],
+                &["juli"],
+                &["august"],
+                &["september"],
+                &["oktober"],
+                &["november"],
+                &["dezember"],
+            ],
+            Language::Portuguese | Language::Brazilian => &[
+                &["janeiro"],
+                &["fevereiro"],
+                &["março"],
+                &["abril"],
+                &["maio"],
+                &["junho"],
+                &["julho"],
+                &["agosto"],
+                &["setembro"],
+                &["outubro"],
+                &["novembro"],
+                &["dezembro"],
+            ],
+            Language::Russian => &[
+                &["января", "январь"],
+                &["февраля", "февраль"],
+                &["марта", "март"],
+                &["апреля", "апрель"],
+                &["мая", "май"],
+                &["июня", "июнь"],
+                &["июля", "июль"],
+                &["августа", "август"],
+                &["сентября", "сентябрь"],
+                &["октября", "октябрь"],
+                &["ноября", "ноябрь"],
+                &["декабря", "декабрь"],
+            ],
+        }
+    }
+
+    /// Prefixes of the Inventory History descriptions for each kind of event.
+    fn event_descriptions(&self) -> &'static [(&'static str, EventKind)] {
+        match self {
+            Language::English => &[
+                ("Unlocked a container", EventKind::Unbox),
+                ("Completed a trade-up contract", EventKind::TradeUp),
+                (
+                    "Purchased on the Community Market",
+                    EventKind::MarketPurchase,
+                ),
+                ("Sold on the Community Market", EventKind::MarketSale),
+                ("You traded with", EventKind::Trade),
+                ("Got an item drop", EventKind::CaseDrop),
+                ("Earned a new rank and got a drop", EventKind::RankDrop),
+                ("Moved to Storage Unit", EventKind::StorageUnitMove),
+                ("Removed from Storage Unit", EventKind::StorageUnitMove),
+            ],
+            Language::German => &[
+                ("Behälter geöffnet", EventKind::Unbox),
+                ("Austauschvertrag abgeschlossen", EventKind::TradeUp),
+                ("Auf dem Communitymarkt gekauft", EventKind::MarketPurchase),
+                ("Auf dem Communitymarkt verkauft", EventKind::MarketSale),
+                ("Sie haben mit", EventKind::Trade),
+                ("Gegenstandsfund erhalten", EventKind::CaseDrop),
+                ("Neuen Rang erreicht und Fund erhalten", EventKind::RankDrop),
+                (
+                    "In Aufbewahrungseinheit verschoben",
+                    EventKind::StorageUnitMove,
+                ),
+                (
+                    "Aus Aufbewahrungseinheit entfernt",
+                    EventKind::StorageUnitMove,
+                ),
+            ],
+            Language::Portuguese | Language::Brazilian => &[
+                ("Abriu um recipiente", EventKind::Unbox),
+                ("Contrato de troca concluído", EventKind::TradeUp),
+                (
+                    "Comprado no Mercado da Comunidade",
+                    EventKind::MarketPurchase,
+                ),
+                ("Vendido no Mercado da Comunidade", EventKind::MarketSale),
+                ("Você trocou com", EventKind::Trade),
+                ("Recebeu um item", EventKind::CaseDrop),
+                ("Subiu de patente e recebeu um item", EventKind::RankDrop),
+                (
+                    "Movido para a Unidade de Armazenamento",
+                    EventKind::StorageUnitMove,
+                ),
+                (
+                    "Removido da Unidade de Armazenamento",
+                    EventKind::StorageUnitMove,
+                ),
+            ],
+            Language::Russian => &[
+                ("Открыт контейнер", EventKind::Unbox),
+                ("Выполнен контракт обмена", EventKind::TradeUp),
+                ("Куплено на Торговой площадке", EventKind::MarketPurchase),
+                ("Продано на Торговой площадке", EventKind::MarketSale),
+                ("Вы обменялись с", EventKind::Trade),
+                ("Получен предмет", EventKind::CaseDrop),
+                ("Получено новое звание и предмет", EventKind::RankDrop),
+                ("Перемещено в хранилище", EventKind::StorageUnitMove),
+                ("Извлечено из хранилища", EventKind::StorageUnitMove),
+            ],
+        }
+    }
+
+    pub(crate) fn event_kind_for(&self, description: &str) -> Option<EventKind> {
+        self.event_descriptions()
+            .iter()
+            .find(|(prefix, _)| description.starts_with(prefix))
+            .map(|(_, kind)| *kind)
+    }
+
+    fn month_for(&self, word: &str) -> Option<u32> {
+        let word = word.to_lowercase();
+        // Too short to tell apart from filler like "de".
+        if word.chars().count() < 3 {
+            return None;
+        }
+
+        self.months()
+            .iter()
+            .position(|names| names.iter().any(|n| n.starts_with(&word)))
+            .map(|i| i as u32 + 1)
+    }
+
+    /// Parses the date and time shown on an Inventory History row.
+    ///
+    /// Steam renders these in UTC as long as we send `timezoneOffset=0,0`.
+    pub(crate) fn parse_datetime(&self, date: &str, time: &str) -> Option<DateTime<Utc>> {
+        let mut day = None;
+        let mut month = None;
+        let mut year = None;
+        for word in date.split(|c: char| !c.is_alphanumeric()) {
+            match word.parse::<u32>() {
+                Ok(n) if word.len() == 4 => year = Some(n as i32),
+                Ok(n) => day = Some(n),
+                Err(_) if month.is_none() => month = self.month_for(word),
+                Err(_) => (),
+            }
+        }
+
+        let captures = TIME_REGEX.captures(time)?;
+        let mut hour: u32 = captures.get(1)?.as_str().parse().ok()?;
+        let minute: u32 = captures.get(2)?.as_str().parse().ok()?;
+        match captures
+            .get(3)
+            .map(|m| m.as_str().to_lowercase())
+            .as_deref()
+        {
+            Some("pm") if hour < 12 => hour += 12,
+            Some("am") if hour == 12 => hour = 0,
+            _ => (),
+        }
+
+        let datetime =
+            NaiveDate::from_ymd_opt(year?, month?, day?)?.and_hms_opt(hour, minute, 0)?;
+
+        Some(Utc.from_utc_datetime(&datetime))
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("unknown language: {0}")]
+pub struct UnknownLanguage(String);
+
+impl FromStr for Language {
+    type Err = UnknownLanguage;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Language::ALL
+            .into_iter()
+            .find(|l| l.as_str() == s)
+            .ok_or_else(|| UnknownLanguage(s.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+    use scraper::Html;
+
+    use super::Language;
+    use crate::event::{EventKind, EventKinds, InventoryEvent};
+    use crate::parsing::{parse_history_rows, HistoryFilter, ParsedHistory};
+
+    const FIXTURES: [(Language, &str); 3] = [
+        (
+            Language::German,
+            include_str!("../fixtures/inventoryhistory_german.html"),
+        ),
+        (
+            Language::Russian,
+            include_str!("../fixtures/inventoryhistory_russian.html"),
+        ),
+        (
+            Language::Brazilian,
+            include_str!("../fixtures/inventoryhistory_brazilian.html"),
+        ),
+    ];
+
+    fn parse_page(html: &str, language: Language) -> ParsedHistory {
+        let kinds: EventKinds = EventKind::ALL.into_iter().collect();
+        let filter = HistoryFilter {
+            since: None,
+            last_seen_inventory_id: None,
+            kinds: &kinds,
+            language,
+        };
+        parse_history_rows(&Html::parse_document(html), &filter)
+    }
+
+    #[test]
+    fn test_parse_history_in_each_language() {
+        // Times are shown in 24-hour clocks, in UTC as we ask Steam for.
+        let unboxed_at = Utc.with_ymd_and_hms(2021, 10, 31, 13, 50, 0).unwrap();
+        let traded_at = Utc.with_ymd_and_hms(2021, 11, 1, 9, 5, 0).unwrap();
+
+        for (language, html) in FIXTURES {
+            let parsed = parse_page(html, language);
+            assert_eq!(parsed.rows, 3, "{language}");
+            assert!(
+                parsed.failures.is_empty(),
+                "{language}: {:?}",
+                parsed.failures
+            );
+
+            // The listing isn't something we track, so is skipped.
+            match &parsed.events[..] {
+                [InventoryEvent::Trade(trade), InventoryEvent::Unbox(unbox)] => {
+                    assert_eq!(trade.at, traded_at, "{language}");
+                    assert_eq!(unbox.at, unboxed_at, "{language}");
+                    assert_eq!(unbox.case.get_name(), "Clutch Case");
+                }
+                events => panic!("{language}: unexpected events {events:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_history_in_wrong_language() {
+        let (_, russian) = FIXTURES[1];
+        let parsed = parse_page(russian, Language::English);
+
+        assert!(parsed.events.is_empty());
+        assert_eq!(parsed.failures.len(), 3);
+        assert_eq!(
+            parsed.failures[1].error,
+            "history is in russian, not english"
+        );
+        // Events we don't track are recognised by their dates instead.
+        assert_eq!(
+            parsed.failures[0].error,
+            "history does not appear to be in english"
+        );
+    }
+
+    #[test]
+    fn test_parse_datetime() {
+        let expected = Utc.with_ymd_and_hms(2021, 10, 31, 13, 50, 0).unwrap();
+        let cases = [
+            (Language::English, "Oct 31, 2021", "1:50pm"),
+            (Language::German, "31. Okt. 2021", "13:50"),
+            (Language::Brazilian, "31 de out. de 2021", "13:50"),
+            (Language::Russian, "31 окт. 2021 г.", "13:50"),
+        ];
+
+        for (language, date, time) in cases {
+            assert_eq!(
+                language.parse_datetime(date, time),
+                Some(expected),
+                "{language}"
+            );
+        }
+
+        let midnight = Utc.with_ymd_and_hms(2022, 5, 2, 0, 5, 0).unwrap();
+        assert_eq!(
+            Language::English.parse_datetime("May 2, 2022", "12:05am"),
+            Some(midnight)
+        );
+        assert_eq!(
+            Language::Russian.parse_datetime("2 мая 2022 г.", "0:05"),
+            Some(midnight)
+        );
+    }
+
+    #[test]
+    fn test_parse_datetime_wrong_language() {
+        assert!(Language::German
+            .parse_datetime("Oct 31, 2021", "1:50pm")
+            .is_none());
+        assert!(Language::English
+            .parse_datetime("31 de out. de 2021", "13:50")
+            .is_none());
+    }
+
+    #[test]
+    fn test_event_kind_for() {
+        assert_eq!(
+            Language::English.event_kind_for("Unlocked a container"),
+            Some(EventKind::Unbox)
+        );
+        assert_eq!(
+            Language::Russian.event_kind_for("Открыт контейнер"),
+            Some(EventKind::Unbox)
+        );
+        assert_eq!(
+            Language::English.event_kind_for("Listed on the Community Market"),
+            None
+        );
+    }
+}
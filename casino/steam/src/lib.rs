@@ -1,42 +1,77 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use chrono::{DateTime, Utc};
 use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use regex::Regex;
 use reqwest::header::COOKIE;
-use reqwest::{Client, Request, StatusCode};
+use reqwest::{Client, Request, Response, StatusCode};
 use scraper::Html;
 use serde::{Deserialize, Serialize};
+use serde_aux::field_attributes::deserialize_bool_from_anything;
 use thiserror::Error;
 
+pub use crate::currency::{Currency, UnknownCurrency};
 use crate::errors::{
     AuthenticationCheckError, FetchInventoryError, FetchItemsError, FetchNewUnpreparedItemsError,
-    LocalPrepareError, PrepareItemsError,
+    LocalPrepareError, LoginError, PrepareItemsError, RequestError,
+};
+pub use crate::event::{
+    EventKind, EventKinds, GainedItem, InventoryEvent, RawTransfer, UnknownEventKind,
 };
 pub use crate::id::{Id, IdUrlParseError};
+pub use crate::language::{Language, UnknownLanguage};
 use crate::parsing::{
-    is_authenticated, parse_raw_unlock, Asset, ParseSuccess, RawUnlock, TrivialItem, TRADE_SELECTOR,
+    is_authenticated, parse_history_cursor, parse_history_inventory, parse_history_rows, Asset,
+    HistoryFilter, HistoryItem, ParsedHistory,
+};
+pub use crate::parsing::{
+    DescriptionLine, HistoryCursor, InventoryDescription, InventoryId, ItemTag, ItemTags,
+    ItemTraits, RawTradeUp, RawUnlock, RowDiagnostic, TrivialItem,
 };
-pub use crate::parsing::{InventoryDescription, InventoryId};
+pub use crate::steam_id::{AccountType, SteamId, SteamIdParseError, Universe};
 
+mod auth;
+pub use auth::{AuthSession, GuardType, LoginTokens, SteamAuthenticator};
+mod backfill;
+pub use backfill::HistoryBackfill;
+pub mod diagnose;
 pub mod errors;
+mod retry;
+pub use retry::RetryPolicy;
+mod source;
+pub use source::{HistorySource, SessionState};
+mod web_api;
+pub use web_api::WebApiClient;
+mod currency;
+mod event;
 mod id;
+mod jwt;
+mod language;
 #[cfg(feature = "backend")]
 mod redis;
+mod steam_id;
 #[cfg(feature = "backend")]
 pub use self::redis::*;
 #[cfg(feature = "backend")]
 mod price_client;
 #[cfg(feature = "backend")]
 pub use price_client::*;
+#[cfg(feature = "backend")]
+mod price_source;
+#[cfg(feature = "backend")]
+pub use price_source::{PriceSheet, PriceSheetLoadError, PriceSource, Skinport, SteamMarket};
+#[cfg(feature = "backend")]
+mod price_history;
+#[cfg(feature = "backend")]
+pub use price_history::{PriceHistory, PriceHistoryError, PriceSnapshot};
 mod parsing;
+#[cfg(test)]
+mod test_util;
 
 lazy_static::lazy_static! {
-    static ref COOKIE_REGEX: Regex = Regex::new(r"[^\s=;]+=[^\s=;]+").unwrap();
+    static ref COOKIE_REGEX: Regex = Regex::new(r"[^\s=;]+=[^\s;]+").unwrap();
 }
 
-type LocalPrepareResult = Result<UnhydratedUnlock, LocalPrepareError>;
-
 /// A minimal inventory transaction, suitable for sending to our backend.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct UnhydratedUnlock {
@@ -47,11 +82,143 @@ pub struct UnhydratedUnlock {
     pub case: TrivialItem,
     pub item_market_link: String,
     pub item_market_name: String,
+    #[serde(flatten)]
+    pub item_traits: ItemTraits,
+
+    pub at: DateTime<Utc>,
+    pub name: String,
+}
+
+/// A minimal trade-up contract, suitable for sending to our backend.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UnhydratedTradeUp {
+    pub history_id: String,
+    pub inventory_id: InventoryId,
+
+    pub inputs: Vec<TrivialItem>,
+    pub item_market_link: String,
+    pub item_market_name: String,
+    #[serde(flatten)]
+    pub item_traits: ItemTraits,
+
+    pub at: DateTime<Utc>,
+    pub name: String,
+}
+
+/// A minimal generic inventory transaction, suitable for sending to our
+/// backend.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UnhydratedTransfer {
+    pub kind: EventKind,
+    pub history_id: String,
+    pub description: String,
+
+    pub lost: Vec<TrivialItem>,
+    pub gained: Vec<GainedItem>,
 
     pub at: DateTime<Utc>,
     pub name: String,
 }
 
+/// A set of new inventory history entries, as uploaded to our backend.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HistoryBatch {
+    #[serde(default)]
+    pub unlocks: Vec<UnhydratedUnlock>,
+    #[serde(default)]
+    pub trade_ups: Vec<UnhydratedTradeUp>,
+    #[serde(default)]
+    pub transfers: Vec<UnhydratedTransfer>,
+}
+
+impl HistoryBatch {
+    pub fn is_empty(&self) -> bool {
+        self.unlocks.is_empty() && self.trade_ups.is_empty() && self.transfers.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.unlocks.len() + self.trade_ups.len() + self.transfers.len()
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (&str, &DateTime<Utc>, Option<&InventoryId>)> {
+        let unlocks = self
+            .unlocks
+            .iter()
+            .map(|u| (u.history_id.as_str(), &u.at, Some(&u.inventory_id)));
+        let trade_ups = self
+            .trade_ups
+            .iter()
+            .map(|t| (t.history_id.as_str(), &t.at, Some(&t.inventory_id)));
+        let transfers = self.transfers.iter().map(|t| {
+            (
+                t.history_id.as_str(),
+                &t.at,
+                t.gained.first().map(|g| &g.id),
+            )
+        });
+
+        unlocks.chain(trade_ups).chain(transfers)
+    }
+
+    /// Timestamp of the most recent entry in this batch.
+    pub fn latest(&self) -> Option<DateTime<Utc>> {
+        self.entries().map(|(_, at, _)| *at).max()
+    }
+
+    /// Inventory id of the most recently gained item in this batch.
+    pub fn latest_inventory_id(&self) -> Option<InventoryId> {
+        self.entries()
+            .filter_map(|(_, at, id)| id.map(|id| (at, id)))
+            .max_by_key(|(at, _)| *at)
+            .map(|(_, id)| *id)
+    }
+
+    /// History ids of all entries in this batch at the given time.
+    pub fn history_ids_at(&self, at: &DateTime<Utc>) -> Vec<String> {
+        self.entries()
+            .filter(|(_, a, _)| *a == at)
+            .map(|(id, _, _)| id.to_string())
+            .collect()
+    }
+
+    /// Removes any entries whose history id is in the given set.
+    pub fn remove_seen(&mut self, seen: &HashSet<String>) {
+        self.unlocks.retain(|u| !seen.contains(&u.history_id));
+        self.trade_ups.retain(|t| !seen.contains(&t.history_id));
+        self.transfers.retain(|t| !seen.contains(&t.history_id));
+    }
+
+    /// Removes any entries from the given time onwards.
+    pub fn retain_before(&mut self, at: &DateTime<Utc>) {
+        self.unlocks.retain(|u| &u.at < at);
+        self.trade_ups.retain(|t| &t.at < at);
+        self.transfers.retain(|t| &t.at < at);
+    }
+
+    /// Removes any entries whose kind is not in the given set, returning how
+    /// many of each kind were removed.
+    pub fn retain_kinds(&mut self, kinds: &EventKinds) -> HashMap<EventKind, usize> {
+        let mut dropped = HashMap::new();
+        if !kinds.contains(&EventKind::Unbox) && !self.unlocks.is_empty() {
+            dropped.insert(EventKind::Unbox, self.unlocks.len());
+            self.unlocks.clear();
+        }
+        if !kinds.contains(&EventKind::TradeUp) && !self.trade_ups.is_empty() {
+            dropped.insert(EventKind::TradeUp, self.trade_ups.len());
+            self.trade_ups.clear();
+        }
+        self.transfers.retain(|t| {
+            let keep = kinds.contains(&t.kind);
+            if !keep {
+                *dropped.entry(t.kind).or_default() += 1;
+            }
+            keep
+        });
+
+        dropped
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum CredentialParseError {
     #[error("could not parse session id, ensure you are passing a valid sessionid parameter")]
@@ -65,6 +232,9 @@ pub struct SteamCredentials {
     session_id: String,
     // NOTE: Unsure if this is required on accounts without steam guard
     login_token: Option<String>,
+    // Only present if we logged in ourselves, rather than being given cookies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
 }
 
 fn maybe_url_encode(s: String) -> String {
@@ -72,7 +242,7 @@ fn maybe_url_encode(s: String) -> String {
         return s;
     }
 
-    return utf8_percent_encode(s.as_str(), NON_ALPHANUMERIC).to_string();
+    utf8_percent_encode(s.as_str(), NON_ALPHANUMERIC).to_string()
 }
 
 impl SteamCredentials {
@@ -81,23 +251,51 @@ impl SteamCredentials {
         Self {
             session_id,
             login_token,
+            refresh_token: None,
+        }
+    }
+
+    /// Builds cookies from an access token minted by [`SteamAuthenticator`].
+    fn from_access_token(
+        session_id: String,
+        steam_id: SteamId,
+        access_token: &str,
+        refresh_token: Option<String>,
+    ) -> Self {
+        Self {
+            session_id,
+            login_token: Some(format!("{steam_id}%7C%7C{access_token}")),
+            refresh_token,
         }
     }
 
     pub fn try_from_cookie_str<S: AsRef<str>>(cookie_str: S) -> Result<Self, CredentialParseError> {
-        let mut session_id: Option<String> = None;
-        let mut login_token: Option<String> = None;
         let mut cookies = COOKIE_REGEX.find_iter(cookie_str.as_ref()).peekable();
         if cookies.peek().is_none() {
             return Err(CredentialParseError::DoesNotResembleCookie);
         }
 
-        for cookie in cookies {
-            // unwrap should be safe - we are guaranteed exactly one = from
-            // our regex matching.
-            match cookie.as_str().split_once('=').unwrap() {
-                ("sessionid", v) => session_id = Some(v.to_string()),
-                ("steamLoginSecure", v) => login_token = Some(maybe_url_encode(v.to_string())),
+        // unwrap should be safe - we are guaranteed at least one = from our
+        // regex matching. Values may contain more.
+        Self::try_from_cookies(cookies.map(|c| c.as_str().split_once('=').unwrap()))
+    }
+
+    /// Picks our credentials out of a set of (name, value) cookie pairs,
+    /// e.g. from a browser's cookie store.
+    pub fn try_from_cookies<I, S1, S2>(cookies: I) -> Result<Self, CredentialParseError>
+    where
+        I: IntoIterator<Item = (S1, S2)>,
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        let mut session_id: Option<String> = None;
+        let mut login_token: Option<String> = None;
+
+        for (name, value) in cookies {
+            let value = value.as_ref().to_string();
+            match name.as_ref() {
+                "sessionid" => session_id = Some(value),
+                "steamLoginSecure" => login_token = Some(maybe_url_encode(value)),
                 _ => (),
             };
         }
@@ -106,11 +304,26 @@ impl SteamCredentials {
             Some(session_id) => Ok(Self {
                 session_id,
                 login_token,
+                refresh_token: None,
             }),
             None => Err(CredentialParseError::NoSessionId),
         }
     }
 
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// The token to renew these credentials with, if we logged in ourselves.
+    pub fn refresh_token(&self) -> Option<&str> {
+        self.refresh_token.as_deref()
+    }
+
+    /// When the login token in these credentials expires, if we can tell.
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        self.login_token.as_deref().and_then(jwt::expiry)
+    }
+
     pub fn as_string(&self) -> String {
         match self.login_token.as_deref() {
             Some(t) => format!("sessionid={}; steamLoginSecure={}", self.session_id, t),
@@ -121,98 +334,406 @@ impl SteamCredentials {
 
 #[derive(Debug, Deserialize)]
 pub struct Inventory {
+    #[serde(default)]
     assets: Vec<Asset>,
+    #[serde(default)]
     descriptions: Vec<InventoryDescription>,
+    #[serde(default, deserialize_with = "deserialize_bool_from_anything")]
+    more_items: bool,
+    last_assetid: Option<String>,
+}
+
+impl Inventory {
+    /// The asset id to start the next page of the inventory from, if any.
+    fn next_page(&self) -> Option<&str> {
+        match self.more_items {
+            true => self.last_assetid.as_deref(),
+            false => None,
+        }
+    }
+}
+
+/// An event we couldn't find the resulting item for in the inventory, even
+/// after reading all of it.
+#[derive(Debug)]
+pub struct UnresolvedItem {
+    pub kind: EventKind,
+    pub history_id: String,
+    pub item: InventoryId,
+    pub reason: LocalPrepareError,
+    pub at: DateTime<Utc>,
+}
+
+/// History fetched from Steam, ready for upload.
+#[derive(Debug, Default)]
+pub struct FetchedHistory {
+    pub batch: HistoryBatch,
+    pub unresolved: Vec<UnresolvedItem>,
+    /// History rows we failed to parse.
+    pub failures: Vec<RowDiagnostic>,
+}
+
+/// A single page of Inventory History.
+struct HistoryPage {
+    html: String,
+    // The first page is a full document, later pages are only fragments.
+    is_fragment: bool,
+    next: Option<HistoryCursor>,
+    /// The items involved in the events on this page.
+    items: Vec<HistoryItem>,
+}
+
+impl HistoryPage {
+    fn parse(&self, filter: &HistoryFilter<'_>) -> ParsedHistory {
+        let page = match self.is_fragment {
+            true => Html::parse_fragment(&self.html),
+            false => Html::parse_document(&self.html),
+        };
+
+        parse_history_rows(&page, filter)
+    }
+}
+
+/// New events from the first page of Inventory History, along with the items
+/// involved in them.
+struct UnpreparedHistory {
+    events: Vec<InventoryEvent>,
+    failures: Vec<RowDiagnostic>,
+    items: Vec<HistoryItem>,
+}
+
+/// Response to a request for a later page of Inventory History.
+#[derive(Debug, Deserialize)]
+struct AjaxHistoryResponse {
+    success: bool,
+    #[serde(default)]
+    html: String,
+    cursor: Option<HistoryCursor>,
+    #[serde(default)]
+    descriptions: serde_json::Value,
+}
+
+/// Inventory data indexed by [`InventoryId`], for combining with history data.
+#[derive(Default)]
+struct InventoryIndex {
+    descriptions: HashMap<InventoryId, InventoryDescription>,
+    assets: HashMap<InventoryId, Asset>,
+    read_first_page: bool,
+    next_page: Option<String>,
+}
+
+impl From<Inventory> for InventoryIndex {
+    fn from(inv: Inventory) -> Self {
+        let mut index = Self::default();
+        index.extend(inv);
+
+        index
+    }
+}
+
+impl InventoryIndex {
+    /// Adds a further page of the inventory to the index.
+    fn extend(&mut self, inv: Inventory) {
+        self.read_first_page = true;
+        self.next_page = inv.next_page().map(String::from);
+
+        self.descriptions.extend(
+            inv.descriptions
+                .into_iter()
+                .map(|i| (InventoryId::from(&i), i)),
+        );
+        self.assets
+            .extend(inv.assets.into_iter().map(|i| (InventoryId::from(&i), i)));
+    }
+
+    /// Adds the items described alongside a page of history, which may have
+    /// since left the inventory.
+    fn add_history_items(&mut self, items: Vec<HistoryItem>) {
+        for item in items {
+            let id = InventoryId::from(&item.description);
+            self.assets
+                .entry(id)
+                .or_insert_with(|| Asset::new(item.app_id, item.asset_id, id));
+            self.descriptions.entry(id).or_insert(item.description);
+        }
+    }
+
+    /// Where to read the next page of the inventory from, or `None` once
+    /// we've read all of it.
+    fn next_start(&mut self) -> Option<Option<String>> {
+        match self.read_first_page {
+            false => Some(None),
+            true => self.next_page.take().map(Some),
+        }
+    }
+
+    fn contains(&self, id: &InventoryId) -> bool {
+        self.descriptions.contains_key(id) && self.assets.contains_key(id)
+    }
+
+    /// Finds the market name and in-game inspect link for the given item.
+    fn inspect_info(
+        &self,
+        id: &InventoryId,
+        owner: SteamId,
+    ) -> Result<(String, String), LocalPrepareError> {
+        let item_data = self
+            .descriptions
+            .get(id)
+            .ok_or(LocalPrepareError::NoDescription)?;
+        let item_asset = self.assets.get(id).ok_or(LocalPrepareError::NoAsset)?;
+
+        let market_name = item_data.name.clone();
+        let actions = item_data
+            .actions
+            .as_ref()
+            .ok_or(LocalPrepareError::NoInspectLink)?;
+        let link_tpl = actions
+            .iter()
+            .find(|a| a.is_csgo_inspect_link())
+            .ok_or(LocalPrepareError::NoInspectLink)?;
+
+        let market_link = link_tpl
+            .link
+            .replacen("%assetid%", &item_asset.asset_id().to_string(), 1)
+            .replacen("%owner_steamid%", &owner.to_string(), 1);
+
+        Ok((market_name, market_link))
+    }
+
+    /// What the inventory tells us about the given item, if anything.
+    fn traits(&self, id: &InventoryId) -> ItemTraits {
+        self.descriptions
+            .get(id)
+            .map(ItemTraits::from)
+            .unwrap_or_default()
+    }
 }
 
 pub struct SteamClient {
     id: Id,
     http_client: Client,
     username: String,
+    event_kinds: EventKinds,
+    language: Language,
+    retry_policy: RetryPolicy,
 
+    session_id: String,
     cookie_str: String,
+    expires_at: Option<DateTime<Utc>>,
+    refresh_token: Option<String>,
+    authenticator: SteamAuthenticator,
 }
 
 impl SteamClient {
     pub fn new(id: Id, creds: SteamCredentials) -> Self {
         let http_client = Client::builder().build().unwrap();
-        let cookie_str = format!("{}; timezoneOffset=0,0", creds.as_string());
-        let username = String::from("");
+        let username = match id.vanity() {
+            Some(vanity) => vanity.to_string(),
+            None => id.user_id().to_string(),
+        };
+        let event_kinds = EventKind::DEFAULT.into_iter().collect();
+        let language = Language::default();
+        let retry_policy = RetryPolicy::default();
 
-        Self {
+        let mut client = Self {
             id,
             http_client,
             username,
-            cookie_str,
+            event_kinds,
+            language,
+            retry_policy,
+            session_id: String::new(),
+            cookie_str: String::new(),
+            expires_at: None,
+            refresh_token: None,
+            authenticator: SteamAuthenticator::new(),
+        };
+        client.set_credentials(creds);
+
+        client
+    }
+
+    /// Replaces the credentials this client uses, e.g. once the session they
+    /// were for has expired.
+    pub fn set_credentials(&mut self, creds: SteamCredentials) {
+        // Have Steam render history timestamps in UTC.
+        self.cookie_str = format!("{}; timezoneOffset=0,0", creds.as_string());
+        self.expires_at = creds.expires_at();
+        self.refresh_token = creds.refresh_token;
+        self.session_id = creds.session_id;
+    }
+
+    /// Mints fresh credentials from our refresh token, if we have one, and
+    /// switches to them.
+    pub async fn refresh_session(&mut self) -> Result<Option<SteamCredentials>, LoginError> {
+        let refresh_token = match self.refresh_token.clone() {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+
+        let steam_id = self.id.user_id();
+        let access_token = self.authenticator.refresh(steam_id, &refresh_token).await?;
+        let creds = SteamCredentials::from_access_token(
+            self.session_id.clone(),
+            steam_id,
+            &access_token,
+            Some(refresh_token),
+        );
+        self.set_credentials(creds.clone());
+
+        Ok(Some(creds))
+    }
+
+    /// When the session this client is using expires, if we can tell.
+    pub fn session_expires_at(&self) -> Option<DateTime<Utc>> {
+        self.expires_at
+    }
+
+    /// Sets the kinds of Inventory History event this client will collect.
+    pub fn with_event_kinds<I: IntoIterator<Item = EventKind>>(mut self, kinds: I) -> Self {
+        self.event_kinds = kinds.into_iter().collect();
+        self
+    }
+
+    /// Sets the language this client will request Inventory History in.
+    pub fn with_language(mut self, language: Language) -> Self {
+        self.language = language;
+        self
+    }
+
+    /// Sets how this client backs off and retries requests that Steam
+    /// rejects.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Sets the authenticator used to renew sessions we logged in to
+    /// ourselves.
+    pub fn with_authenticator(mut self, authenticator: SteamAuthenticator) -> Self {
+        self.authenticator = authenticator;
+        self
+    }
+
+    /// Points this client at a different Steam Community host, instead of
+    /// the one its [`Id`] was created with.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        let vanity = self.id.vanity().map(String::from);
+        self.id = Id::with_base_url(self.id.user_id(), vanity, base_url);
+        self
+    }
+
+    async fn execute(&self, req: Request) -> Result<Response, RequestError> {
+        self.retry_policy.execute(&self.http_client, req).await
+    }
+
+    fn inv_req(&self, start_assetid: Option<&str>) -> Request {
+        let mut req = self.http_client.get(self.id.inventory_url());
+        if let Some(start) = start_assetid {
+            req = req.query(&[("start_assetid", start)]);
         }
+
+        // NOTE: Desired to avoid exceeding rate limits (anon inventory
+        // requests are aggressively rate-limited)
+        req.header("Cookie", &self.cookie_str).build().unwrap()
     }
 
-    fn inv_req(&self) -> Request {
+    fn inv_history_req(&self) -> Request {
         self.http_client
-            .get(self.id.inventory_url())
-            // NOTE: Desired to avoid exceeding rate limits (anon inventory
-            // requests are aggressively rate-limited)
+            .get(self.id.inventory_history_url())
+            .query(&[("l", self.language.as_str())])
             .header("Cookie", &self.cookie_str)
             .build()
             .unwrap()
     }
 
-    fn inv_history_req(&self) -> Request {
+    fn inv_history_page_req(&self, cursor: &HistoryCursor) -> Request {
         self.http_client
             .get(self.id.inventory_history_url())
+            .query(&[
+                ("ajax", "1"),
+                ("cursor[time]", &cursor.time.to_string()),
+                ("cursor[time_frac]", &cursor.time_frac.to_string()),
+                ("cursor[s]", &cursor.s),
+                ("sessionid", &self.session_id),
+                ("l", self.language.as_str()),
+            ])
             .header("Cookie", &self.cookie_str)
             .build()
             .unwrap()
     }
 
+    /// Fetches the raw Inventory History page, without checking that it can
+    /// be parsed.
+    pub async fn fetch_history_html(&self) -> Result<String, RequestError> {
+        let html = self
+            .execute(self.inv_history_req())
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        Ok(html)
+    }
+
+    /// Walks back through this user's Inventory History until the given time,
+    /// a page at a time.
+    pub fn backfill(&self, until: DateTime<Utc>) -> HistoryBackfill<'_> {
+        HistoryBackfill::new(self, until)
+    }
+
     pub async fn fetch_history_for_new_items(
         &self,
         since: Option<&DateTime<Utc>>,
         last_item: Option<&InventoryId>,
-    ) -> Result<Vec<UnhydratedUnlock>, FetchItemsError> {
-        // TODO: Need to check what exactly start_assetid does (but we should
-        // have it handy by our stored InventoryId if needed)
-        let inv = self.fetch_inventory().await?;
+    ) -> Result<FetchedHistory, FetchItemsError> {
+        let inv = self.fetch_inventory(None).await?;
         log::debug!("inventory count: {}", inv.assets.len());
+        // If we're interested in events that don't add new items, we can't
+        // tell from the inventory alone if there's anything new.
+        let only_gains = self.event_kinds.iter().all(EventKind::always_gains_items);
         match (inv.descriptions.first(), last_item) {
             // Return early if we have made a successful call and it shows we
             // have no new items in our inventory.
-            (Some(new), Some(old)) => {
+            (Some(new), Some(old)) if only_gains => {
                 log::debug!("new item: {new:?}");
                 let new_inv_id = InventoryId::from(new);
                 if &new_inv_id == old {
                     // No new items to process
-                    return Ok(vec![]);
+                    return Ok(FetchedHistory::default());
                 }
             }
             // Return early if steam tells us there are no items in our inventory.
-            (None, _) => return Ok(vec![]),
+            (None, _) if only_gains => return Ok(FetchedHistory::default()),
             _ => (),
         };
 
         // TODO: Give this a better name
         let unhydrated = self.fetch_new_unprepared_items(since, last_item).await?;
-        if unhydrated.is_empty() {
-            return Ok(vec![]);
+        if unhydrated.events.is_empty() {
+            return Ok(FetchedHistory {
+                failures: unhydrated.failures,
+                ..Default::default()
+            });
         }
 
-        log::debug!("{} unhydrated items", unhydrated.len());
-        let prepared = self
-            .prepare_unlocks(inv, unhydrated, self.username.clone())
-            .await?;
+        log::debug!("{} unhydrated items", unhydrated.events.len());
+        let mut index = InventoryIndex::from(inv);
+        index.add_history_items(unhydrated.items);
+        let mut prepared = self.prepare_unlocks(&mut index, unhydrated.events).await?;
+        prepared.failures = unhydrated.failures;
 
         Ok(prepared)
     }
 
     pub async fn is_authenticated(&self) -> Result<bool, AuthenticationCheckError> {
-        let data = self
+        let req = self
             .http_client
             .get(self.id.profile_url())
             .header(COOKIE, &self.cookie_str)
-            .send()
-            .await?
-            .text()
-            .await?;
+            .build()?;
+        let data = self.execute(req).await?.text().await?;
 
         let parsed = Html::parse_document(&data);
         let authenticated = is_authenticated(&parsed)?;
@@ -224,9 +745,46 @@ impl SteamClient {
         &self,
         since: Option<&DateTime<Utc>>,
         last_item: Option<&InventoryId>,
-    ) -> Result<Vec<RawUnlock>, FetchNewUnpreparedItemsError> {
+    ) -> Result<UnpreparedHistory, FetchNewUnpreparedItemsError> {
         log::debug!("checking since: {since:?}");
-        let resp = self.http_client.execute(self.inv_history_req()).await?;
+        let page = self.fetch_history_page(None).await?;
+
+        let filter = HistoryFilter {
+            since,
+            last_seen_inventory_id: last_item,
+            kinds: &self.event_kinds,
+            language: self.language,
+        };
+        let parsed = page.parse(&filter);
+
+        log::debug!(
+            "returning {} items, {} failed rows",
+            parsed.events.len(),
+            parsed.failures.len()
+        );
+
+        if parsed.rows == 0 {
+            return Err(FetchNewUnpreparedItemsError::NoHistoryFound);
+        }
+
+        Ok(UnpreparedHistory {
+            events: parsed.events,
+            failures: parsed.failures,
+            items: page.items,
+        })
+    }
+
+    /// Fetches the page of Inventory History at the given cursor, or the most
+    /// recent page if none is given.
+    async fn fetch_history_page(
+        &self,
+        cursor: Option<&HistoryCursor>,
+    ) -> Result<HistoryPage, FetchNewUnpreparedItemsError> {
+        let req = match cursor {
+            Some(c) => self.inv_history_page_req(c),
+            None => self.inv_history_req(),
+        };
+        let resp = self.execute(req).await?;
 
         match resp.status() {
             StatusCode::OK => (),
@@ -237,41 +795,43 @@ impl SteamClient {
         }
 
         let data = resp.text().await?;
-        let parsed_data = Html::parse_document(&data);
-
-        if !is_authenticated(&parsed_data)? {
-            return Err(FetchNewUnpreparedItemsError::NotAuthenticated);
-        }
 
-        let trades = parsed_data.select(&TRADE_SELECTOR);
-        let mut seen_any = false;
-
-        let mut unlocks: Vec<RawUnlock> = Vec::new();
-
-        for trade in trades {
-            match parse_raw_unlock(trade, since, last_item)? {
-                ParseSuccess::ValidItem(v) => unlocks.push(v),
-                ParseSuccess::TooOld => return Ok(unlocks),
-                ParseSuccess::WrongTransactionType => {
-                    seen_any = true;
-                    continue;
-                }
+        if cursor.is_some() {
+            let resp: AjaxHistoryResponse = serde_json::from_str(&data)?;
+            if !resp.success {
+                return Err(FetchNewUnpreparedItemsError::Unsuccessful);
             }
-        }
 
-        log::debug!("returning {} items", unlocks.len());
+            return Ok(HistoryPage {
+                html: resp.html,
+                is_fragment: true,
+                next: resp.cursor,
+                items: HistoryItem::from_map(resp.descriptions),
+            });
+        }
 
-        if !seen_any {
-            return Err(FetchNewUnpreparedItemsError::NoHistoryFound);
+        if !is_authenticated(&Html::parse_document(&data))? {
+            return Err(FetchNewUnpreparedItemsError::NotAuthenticated);
         }
 
-        Ok(unlocks)
+        let next = parse_history_cursor(&data);
+        let items = parse_history_inventory(&data);
+        Ok(HistoryPage {
+            html: data,
+            is_fragment: false,
+            next,
+            items,
+        })
     }
 
-    async fn fetch_inventory(&self) -> Result<Inventory, FetchInventoryError> {
+    /// Fetches a page of the inventory, starting after the given asset id, or
+    /// the most recent page if none is given.
+    async fn fetch_inventory(
+        &self,
+        start_assetid: Option<&str>,
+    ) -> Result<Inventory, FetchInventoryError> {
         let resp = self
-            .http_client
-            .execute(self.inv_req())
+            .execute(self.inv_req(start_assetid))
             .await?
             .error_for_status()?
             .text()
@@ -280,82 +840,217 @@ impl SteamClient {
         serde_json::from_str(&resp).map_err(FetchInventoryError::from)
     }
 
+    /// Finds the items gained in each event, reading more of the inventory
+    /// into the index if it doesn't have them all.
     async fn prepare_unlocks(
         &self,
-        inv: Inventory,
-        items: Vec<RawUnlock>,
-        name: String,
-    ) -> Result<Vec<UnhydratedUnlock>, PrepareItemsError> {
-        let data_map: HashMap<InventoryId, InventoryDescription> = inv
-            .descriptions
-            .into_iter()
-            .map(|i| (InventoryId::from(&i), i))
-            .collect();
+        index: &mut InventoryIndex,
+        history: Vec<InventoryEvent>,
+    ) -> Result<FetchedHistory, PrepareItemsError> {
+        let name = &self.username;
+        let owner = self.id.user_id();
+        let mut batch = HistoryBatch::default();
+        let mut unresolved = Vec::new();
 
-        let asset_map: HashMap<InventoryId, Asset> = inv
-            .assets
-            .into_iter()
-            .map(|i| (InventoryId::from(&i), i))
+        // Items gained long enough ago may not be on the first page of the
+        // inventory, so keep reading until we've found everything we need.
+        let wanted: Vec<&InventoryId> = history
+            .iter()
+            .filter_map(|e| match e {
+                InventoryEvent::Unbox(u) => Some(&u.item),
+                InventoryEvent::TradeUp(t) => Some(&t.item),
+                _ => None,
+            })
             .collect();
+        while !wanted.iter().all(|id| index.contains(id)) {
+            let start = match index.next_start() {
+                Some(start) => start,
+                None => break,
+            };
 
-        // NOTE: This fairly ugly block of code exists because we need to
-        // combine results from both the user's inventory _and_ inventory
-        // history data to provide our full set of data to the backend, which
-        // are not guaranteed to be sorted in order.
-        // Moving this mapper to another function would also require us to pass
-        // in both data maps and the user's identifier, which feels like more
-        // trouble than it's worth.
-        let (results, errs): (Vec<_>, Vec<_>) = items
-            .into_iter()
-            .map(|i| {
-                let case = i.case;
-                let key = i.key;
-                let item_data = data_map
-                    .get(&i.item)
-                    .ok_or(LocalPrepareError::NoDescription)?;
-                let item_asset = asset_map.get(&i.item).ok_or(LocalPrepareError::NoAsset)?;
-
-                let item_market_name = item_data.name.clone();
-                let actions = item_data
-                    .actions
-                    .as_ref()
-                    .ok_or(LocalPrepareError::NoInspectLink)?;
-                let link_tpl = actions
-                    .iter()
-                    .find(|a| a.is_csgo_inspect_link())
-                    .ok_or(LocalPrepareError::NoInspectLink)?;
-
-                let item_market_link = link_tpl
-                    .link
-                    .replacen("%assetid%", &item_asset.asset_id().to_string(), 1)
-                    .replacen("%owner_steamid%", &self.id.user_id().to_string(), 1);
-
-                let inventory_id = i.item;
-                // TODO: Do we need this anymore?
-                let history_id = i.history_id;
-                let at = i.at;
-                let name = name.clone();
-
-                Ok(UnhydratedUnlock {
-                    history_id,
-                    inventory_id,
-
-                    key,
-                    case,
-                    item_market_link,
-                    item_market_name,
-                    at,
-                    name,
-                })
-            })
-            .partition(|r: &LocalPrepareResult| r.is_ok());
+            log::debug!("fetching more of the inventory from asset {start:?}");
+            let page = self.fetch_inventory(start.as_deref()).await?;
+            index.extend(page);
+        }
+
+        for event in history {
+            let kind = event.kind();
+            match event {
+                InventoryEvent::Unbox(i) => {
+                    let (item_market_name, item_market_link) =
+                        match index.inspect_info(&i.item, owner) {
+                            Ok(info) => info,
+                            Err(reason) => {
+                                unresolved.push(UnresolvedItem {
+                                    kind,
+                                    history_id: i.history_id,
+                                    item: i.item,
+                                    reason,
+                                    at: i.at,
+                                });
+                                continue;
+                            }
+                        };
 
-        let results = results.into_iter().map(|r| r.unwrap()).collect();
+                    batch.unlocks.push(UnhydratedUnlock {
+                        // TODO: Do we need this anymore?
+                        history_id: i.history_id,
+                        inventory_id: i.item,
 
-        for err in errs {
-            log::error!("not able to send item: {}", err.unwrap_err());
+                        key: i.key,
+                        case: i.case,
+                        item_market_link,
+                        item_market_name,
+                        item_traits: index.traits(&i.item),
+                        at: i.at,
+                        name: name.clone(),
+                    });
+                }
+                InventoryEvent::TradeUp(t) => {
+                    let (item_market_name, item_market_link) =
+                        match index.inspect_info(&t.item, owner) {
+                            Ok(info) => info,
+                            Err(reason) => {
+                                unresolved.push(UnresolvedItem {
+                                    kind,
+                                    history_id: t.history_id,
+                                    item: t.item,
+                                    reason,
+                                    at: t.at,
+                                });
+                                continue;
+                            }
+                        };
+
+                    batch.trade_ups.push(UnhydratedTradeUp {
+                        history_id: t.history_id,
+                        inventory_id: t.item,
+
+                        inputs: t.inputs,
+                        item_market_link,
+                        item_market_name,
+                        item_traits: index.traits(&t.item),
+                        at: t.at,
+                        name: name.clone(),
+                    });
+                }
+                InventoryEvent::MarketPurchase(t)
+                | InventoryEvent::MarketSale(t)
+                | InventoryEvent::Trade(t)
+                | InventoryEvent::CaseDrop(t)
+                | InventoryEvent::RankDrop(t)
+                | InventoryEvent::StorageUnitMove(t) => {
+                    batch.transfers.push(UnhydratedTransfer {
+                        kind,
+                        history_id: t.history_id,
+                        description: t.description,
+
+                        lost: t.lost,
+                        gained: t.gained,
+
+                        at: t.at,
+                        name: name.clone(),
+                    });
+                }
+            }
         }
 
-        Ok(results)
+        Ok(FetchedHistory {
+            batch,
+            unresolved,
+            failures: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+    use mock_steam::Fixtures;
+    use reqwest::header::COOKIE;
+
+    use super::{Inventory, InventoryIndex};
+    use crate::test_util::mock_client;
+    use crate::{HistoryCursor, InventoryEvent, InventoryId, RawUnlock, TrivialItem};
+
+    // A page of inventory with one item in it, a Clutch Case.
+    const CASE_PAGE: &str = r#"{
+        "assets": [{"appid": 730, "contextid": "2", "assetid": "5000", "classid": "200", "instanceid": "0", "amount": "1"}],
+        "descriptions": [{"classid": "200", "instanceid": "0", "icon_url": "clutch", "market_hash_name": "Clutch Case", "type": "Base Grade Container"}],
+        "more_items": 1,
+        "last_assetid": "5000",
+        "success": 1
+    }"#;
+
+    #[test]
+    fn test_history_requests_ask_for_utc() {
+        let client = mock_client("http://mock");
+        let cursor = HistoryCursor {
+            time: 1635688200,
+            time_frac: 0,
+            s: "0".to_string(),
+        };
+
+        // Otherwise Steam shows times in whatever timezone it guesses we're in,
+        // and we'd parse them as UTC anyway.
+        for req in [
+            client.inv_history_req(),
+            client.inv_history_page_req(&cursor),
+        ] {
+            let cookies = req.headers()[COOKIE].to_str().unwrap();
+            assert!(cookies.split("; ").any(|c| c == "timezoneOffset=0,0"));
+        }
+    }
+
+    #[test]
+    fn test_inventory_index_extend() {
+        let mut index = InventoryIndex::default();
+        assert_eq!(index.next_start(), Some(None));
+
+        let first: Inventory = serde_json::from_str(CASE_PAGE).unwrap();
+        index.extend(first);
+        assert!(index.contains(&InventoryId::new(200, 0)));
+        assert_eq!(index.next_start(), Some(Some("5000".to_string())));
+
+        let last: Inventory = serde_json::from_str(&Fixtures::default().inventory).unwrap();
+        index.extend(last);
+        assert!(index.contains(&InventoryId::new(200, 0)));
+        assert!(index.contains(&InventoryId::new(100, 0)));
+        assert_eq!(index.next_start(), None);
+    }
+
+    #[tokio::test]
+    async fn test_prepare_reads_later_inventory_pages() {
+        let defaults = Fixtures::default();
+        let base = mock_steam::spawn(Fixtures {
+            inventory: CASE_PAGE.to_string(),
+            more_inventory: [("5000".to_string(), defaults.inventory.clone())].into(),
+            ..defaults
+        });
+        let client = mock_client(&base);
+
+        let unbox = |history_id: &str, item| {
+            InventoryEvent::Unbox(RawUnlock {
+                history_id: history_id.to_string(),
+                case: TrivialItem::new("Clutch Case", "clutch", None),
+                key: None,
+                item,
+                at: Utc.with_ymd_and_hms(2021, 10, 31, 13, 50, 0).unwrap(),
+            })
+        };
+        let events = vec![
+            unbox("found", InventoryId::new(100, 0)),
+            unbox("missing", InventoryId::new(999, 0)),
+        ];
+
+        let mut index = InventoryIndex::default();
+        let fetched = client.prepare_unlocks(&mut index, events).await.unwrap();
+
+        assert_eq!(fetched.batch.unlocks.len(), 1);
+        assert!(fetched.batch.unlocks[0]
+            .item_market_link
+            .contains("S76561198000000001A1000D"));
+        assert_eq!(fetched.unresolved.len(), 1);
+        assert_eq!(fetched.unresolved[0].history_id, "missing");
     }
 }
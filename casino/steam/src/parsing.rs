@@ -1,12 +1,16 @@
 use std::num::ParseIntError;
 
-use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use chrono::{DateTime, Utc};
 use regex::Regex;
 use scraper::{ElementRef, Html, Selector};
 use serde::{Deserialize, Serialize};
 use serde_aux::field_attributes::deserialize_number_from_string;
 use thiserror::Error;
 
+use crate::event::{EventKind, EventKinds, GainedItem, InventoryEvent, RawTransfer};
+use crate::language::Language;
+use crate::steam_id::{SteamId, SteamIdParseError};
+
 lazy_static::lazy_static! {
     pub static ref LOGIN_AREA_SELECTOR: Selector = Selector::parse("#global_actions").unwrap();
     pub static ref LOGGED_IN_ACTION_SELECTOR: Selector = Selector::parse("#account_pulldown").unwrap();
@@ -18,12 +22,16 @@ lazy_static::lazy_static! {
     pub static ref TRADE_DATE_SELECTOR: Selector = Selector::parse("div.tradehistory_date").unwrap();
     pub static ref DESCRIPTION_SELECTOR: Selector = Selector::parse("div.tradehistory_event_description").unwrap();
     pub static ref INFO_SELECTOR: Selector = Selector::parse("div.tradehistory_items").unwrap();
+    pub static ref PLUS_MINUS_SELECTOR: Selector = Selector::parse("div.tradehistory_items_plusminus").unwrap();
     pub static ref TRADE_ITEM_SELECTOR: Selector = Selector::parse(".history_item").unwrap();
     pub static ref TRADE_ITEM_IMG_SELECTOR: Selector = Selector::parse("img.tradehistory_received_item_img").unwrap();
     pub static ref TRADE_ITEM_NAME_SELECTOR: Selector = Selector::parse("span.history_item_name").unwrap();
 
+    pub static ref HISTORY_CURSOR_REGEX: Regex = Regex::new(r"g_historyCursor\s*=\s*(\{[^}]*\})").unwrap();
+    pub static ref HISTORY_INVENTORY_REGEX: Regex = Regex::new(r"(?m)g_rgHistoryInventory\s*=\s*(.*);\s*$").unwrap();
     pub static ref HISTORY_ID_REGEX: Regex = Regex::new(r"^history([0-9a-f]{40})_.+").unwrap();
     pub static ref USER_ID_REGEX: Regex = Regex::new("commentthread_Profile_([0-9]+)_.*").unwrap();
+    pub static ref NAME_COLOR_REGEX: Regex = Regex::new(r"color:\s*#([0-9A-Fa-f]{6})").unwrap();
 }
 
 /// Represents some non-unique item on the Steam Market (keys, cases, etc)
@@ -51,6 +59,10 @@ impl TrivialItem {
     pub fn get_name(&self) -> &str {
         self.name.as_str()
     }
+
+    pub fn get_color(&self) -> Option<&str> {
+        self.color.as_deref()
+    }
 }
 
 /// Minimal representation of a unique item in a user's inventory
@@ -100,10 +112,106 @@ pub struct InventoryDescription {
     pub name: String,
     #[serde(rename = "type")]
     pub variant: String,
+    /// Hex colour Steam shows the item's name in, without a leading `#`.
+    pub name_color: Option<String>,
+    #[serde(default)]
+    pub tags: ItemTags,
+    #[serde(default)]
+    pub descriptions: Vec<DescriptionLine>,
 
     pub actions: Option<Vec<Action>>,
 }
 
+/// One of the tags Steam classifies an item with.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ItemTag {
+    pub internal_name: String,
+    #[serde(rename(deserialize = "localized_tag_name"))]
+    pub name: String,
+    pub color: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawItemTag {
+    category: String,
+    #[serde(flatten)]
+    tag: ItemTag,
+}
+
+/// The tags on an item that we care about, by category.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(from = "Vec<RawItemTag>")]
+pub struct ItemTags {
+    /// e.g. "Classified", coloured as the game shows it.
+    pub rarity: Option<ItemTag>,
+    /// e.g. "Field-Tested".
+    pub exterior: Option<ItemTag>,
+    /// e.g. "StatTrak™".
+    pub quality: Option<ItemTag>,
+    /// e.g. "AK-47".
+    pub weapon: Option<ItemTag>,
+    /// e.g. "The Phoenix Collection".
+    pub collection: Option<ItemTag>,
+}
+
+impl From<Vec<RawItemTag>> for ItemTags {
+    fn from(raw: Vec<RawItemTag>) -> Self {
+        let mut tags = Self::default();
+        for RawItemTag { category, tag } in raw {
+            let slot = match category.as_str() {
+                "Rarity" => &mut tags.rarity,
+                "Exterior" => &mut tags.exterior,
+                "Quality" => &mut tags.quality,
+                "Weapon" => &mut tags.weapon,
+                "ItemSet" => &mut tags.collection,
+                _ => continue,
+            };
+            *slot = Some(tag);
+        }
+
+        tags
+    }
+}
+
+/// A line of an item's description, as shown under it in the inventory.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DescriptionLine {
+    /// How to display the value, either "text" or "html".
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub value: String,
+    pub color: Option<String>,
+}
+
+/// What Steam tells us about an item in the inventory, so we know it
+/// without asking CSGOFloat.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ItemTraits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rarity: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rarity_color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exterior: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collection: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name_color: Option<String>,
+}
+
+impl From<&InventoryDescription> for ItemTraits {
+    fn from(d: &InventoryDescription) -> Self {
+        let tags = &d.tags;
+        Self {
+            rarity: tags.rarity.as_ref().map(|t| t.name.clone()),
+            rarity_color: tags.rarity.as_ref().and_then(|t| t.color.clone()),
+            exterior: tags.exterior.as_ref().map(|t| t.name.clone()),
+            collection: tags.collection.as_ref().map(|t| t.name.clone()),
+            name_color: d.name_color.clone(),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Action {
     pub link: String,
@@ -133,11 +241,64 @@ pub struct Asset {
 }
 
 impl Asset {
+    pub(crate) fn new(app_id: u32, asset_id: u64, id: InventoryId) -> Self {
+        Self {
+            app_id,
+            asset_id,
+            class_id: id.class_id,
+            instance_id: id.instance_id,
+        }
+    }
+
     pub fn asset_id(&self) -> &u64 {
         &self.asset_id
     }
 }
 
+/// An item as described alongside a page of Inventory History. This is the
+/// item as it was at the time, so we have it even once it has left the
+/// inventory.
+#[derive(Debug, Deserialize)]
+pub struct HistoryItem {
+    #[serde(skip)]
+    pub app_id: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    #[serde(rename(deserialize = "id"))]
+    pub asset_id: u64,
+    #[serde(flatten)]
+    pub description: InventoryDescription,
+}
+
+impl HistoryItem {
+    /// Reads items from Steam's map of app id -> context id -> asset id ->
+    /// item. Steam writes empty maps as `[]`, and items we can't read are
+    /// skipped, as we can still look them up in the inventory.
+    pub fn from_map(map: serde_json::Value) -> Vec<Self> {
+        let mut items = Vec::new();
+        let apps = match map {
+            serde_json::Value::Object(apps) => apps,
+            _ => return items,
+        };
+        for (app_id, contexts) in apps {
+            let app_id = match app_id.parse() {
+                Ok(id) => id,
+                Err(_) => continue,
+            };
+            let contexts = contexts.as_object().into_iter().flat_map(|c| c.values());
+            for assets in contexts {
+                for raw in assets.as_object().into_iter().flat_map(|a| a.values()) {
+                    match serde_json::from_value::<HistoryItem>(raw.clone()) {
+                        Ok(item) => items.push(HistoryItem { app_id, ..item }),
+                        Err(e) => log::debug!("skipping unreadable history item: {e}"),
+                    }
+                }
+            }
+        }
+
+        items
+    }
+}
+
 /// Represents a transaction fetched from the Inventory History page.
 #[derive(Debug)]
 pub struct RawUnlock {
@@ -150,14 +311,68 @@ pub struct RawUnlock {
     pub at: DateTime<Utc>,
 }
 
+/// Represents a trade-up contract fetched from the Inventory History page.
+#[derive(Debug)]
+pub struct RawTradeUp {
+    pub history_id: String,
+
+    pub inputs: Vec<TrivialItem>,
+    pub item: InventoryId,
+
+    pub at: DateTime<Utc>,
+}
+
 pub type ParseResult = Result<ParseSuccess, ParseFailure>;
 
 pub enum ParseSuccess {
-    ValidItem(RawUnlock),
+    Event(InventoryEvent),
     TooOld,
     WrongTransactionType,
 }
 
+/// Position in a user's Inventory History, used to request older pages.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HistoryCursor {
+    pub time: i64,
+    pub time_frac: u32,
+    pub s: String,
+}
+
+/// Events parsed from a single page of Inventory History.
+#[derive(Debug, Default)]
+pub struct ParsedHistory {
+    pub events: Vec<InventoryEvent>,
+    /// Number of rows found on the page, including ones we weren't
+    /// interested in.
+    pub rows: usize,
+    /// Whether we stopped parsing early because we found a row that was too
+    /// old.
+    pub reached_end: bool,
+    /// Rows which we failed to parse, and skipped.
+    pub failures: Vec<RowDiagnostic>,
+}
+
+/// Determines which Inventory History rows we are interested in.
+pub struct HistoryFilter<'a> {
+    pub since: Option<&'a DateTime<Utc>>,
+    pub last_seen_inventory_id: Option<&'a InventoryId>,
+    pub kinds: &'a EventKinds,
+    /// Language the page was rendered in.
+    pub language: Language,
+}
+
+impl HistoryFilter<'_> {
+    fn is_too_old(&self, at: &DateTime<Utc>) -> bool {
+        self.since.map(|s| at < s).unwrap_or(false)
+    }
+
+    fn is_last_seen(&self, id: &InventoryId) -> bool {
+        self.last_seen_inventory_id
+            .map(|l| l == id)
+            .unwrap_or(false)
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ParseFailure {
     #[error("could not find trade description")]
@@ -168,8 +383,12 @@ pub enum ParseFailure {
     MissingDate,
     #[error("could not find trade time")]
     MissingTime,
-    #[error("could not parse date from existing format")]
-    DateFormattingChanged,
+    #[error("could not parse {language} date: {date}")]
+    UnrecognisedDate { language: Language, date: String },
+    #[error("history is in {found}, not {expected}")]
+    WrongLanguage { expected: Language, found: Language },
+    #[error("history does not appear to be in {0}")]
+    UnrecognisedLanguage(Language),
     #[error("could not find lost items from unboxing")]
     MissingLostItems,
     #[error("could not used container from unboxing")]
@@ -178,14 +397,34 @@ pub enum ParseFailure {
     MissingGainedItems,
     #[error("could not find item gained from unboxing")]
     MissingGainedItem,
+    #[error("could not find items consumed by trade-up")]
+    MissingTradeUpInputs,
+    #[error("could not find item gained from trade-up")]
+    MissingTradeUpOutput,
+    #[error("could not find any items involved in transaction")]
+    MissingTransferItems,
+    #[error("could not tell if items were gained or lost in transaction")]
+    MissingTransferDirection,
     #[error("could not find id associated with trade")]
     MissingTradeId,
     #[error("could not parse trade id from element")]
     TradeIdFormattingChanged,
+    #[error("could not find inventory id of item")]
+    MissingInventoryId,
+    #[error("could not parse inventory id of item: {0}")]
+    InventoryIdParsing(#[from] ParseIntError),
     #[error("could not parse trivial item: {0}")]
     TrivialItemParsing(#[from] TrivialItemParseError),
 }
 
+/// A row of Inventory History which we failed to parse.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RowDiagnostic {
+    pub history_id: Option<String>,
+    pub error: String,
+    pub html: String,
+}
+
 #[derive(Debug, Error)]
 pub enum TrivialItemParseError {
     #[error("could not find item name node")]
@@ -208,9 +447,11 @@ pub enum UserIdParseError {
     BadUserId(#[from] ParseIntError),
     #[error("error parsing steam user id")]
     UserIdElementParseError,
+    #[error("invalid steam user id: {0}")]
+    InvalidUserId(#[from] SteamIdParseError),
 }
 
-pub fn get_userid(page: &Html) -> Result<u64, UserIdParseError> {
+pub fn get_userid(page: &Html) -> Result<SteamId, UserIdParseError> {
     let user_id_element = page
         .select(&USER_ID_SELECTOR)
         .next()
@@ -222,7 +463,10 @@ pub fn get_userid(page: &Html) -> Result<u64, UserIdParseError> {
         .ok_or(UserIdParseError::MissingUserIdElement)?;
 
     match USER_ID_REGEX.captures(element_id) {
-        Some(g) => Ok(g.get(1).unwrap().as_str().parse()?),
+        Some(g) => {
+            let raw: u64 = g.get(1).unwrap().as_str().parse()?;
+            Ok(SteamId::try_from(raw)?)
+        }
         None => Err(UserIdParseError::UserIdElementParseError),
     }
 }
@@ -259,51 +503,117 @@ pub fn is_authenticated(page: &Html) -> Result<bool, AuthenticationParseError> {
     Err(AuthenticationParseError::MissingLoginOrUserInfo)
 }
 
-// TODO: This probably shouldn't concern itself with parsing _and_ filtering.
-pub fn parse_raw_unlock(
-    trade: ElementRef<'_>,
-    since: Option<&DateTime<Utc>>,
-    last_seen_inventory_id: Option<&InventoryId>,
-) -> ParseResult {
+/// Finds the cursor for the next page of history embedded in the first page.
+pub fn parse_history_cursor(page: &str) -> Option<HistoryCursor> {
+    let raw = HISTORY_CURSOR_REGEX.captures(page)?.get(1)?.as_str();
+
+    serde_json::from_str(raw).ok()
+}
+
+/// Finds the descriptions of the items in the first page of history, which
+/// are embedded in it.
+pub fn parse_history_inventory(page: &str) -> Vec<HistoryItem> {
+    let raw = match HISTORY_INVENTORY_REGEX
+        .captures(page)
+        .and_then(|c| c.get(1))
+    {
+        Some(raw) => raw.as_str(),
+        None => return Vec::new(),
+    };
+
+    match serde_json::from_str(raw) {
+        Ok(map) => HistoryItem::from_map(map),
+        Err(e) => {
+            log::debug!("failed to read history inventory: {e}");
+            Vec::new()
+        }
+    }
+}
+
+/// Parses all rows in the given page, stopping at the first row which is too
+/// old.
+///
+/// Rows which fail to parse are skipped, and recorded in
+/// [`ParsedHistory::failures`].
+pub fn parse_history_rows(page: &Html, filter: &HistoryFilter<'_>) -> ParsedHistory {
+    let mut parsed = ParsedHistory::default();
+
+    for trade in page.select(&TRADE_SELECTOR) {
+        parsed.rows += 1;
+        match parse_history_row(trade, filter) {
+            Ok(ParseSuccess::Event(e)) => parsed.events.push(e),
+            Ok(ParseSuccess::TooOld) => {
+                parsed.reached_end = true;
+                break;
+            }
+            Ok(ParseSuccess::WrongTransactionType) => continue,
+            Err(e) => parsed.failures.push(RowDiagnostic {
+                history_id: history_id_from_row(trade),
+                error: e.to_string(),
+                html: trade.html(),
+            }),
+        }
+    }
+
+    parsed
+}
+
+pub fn parse_history_row(trade: ElementRef<'_>, filter: &HistoryFilter<'_>) -> ParseResult {
     let desc = trade
         .select(&DESCRIPTION_SELECTOR)
         .next()
         .ok_or(ParseFailure::MissingDescription)?;
     let desc_text = desc
         .text()
-        .next()
-        .ok_or(ParseFailure::MissingDescriptionText)?
-        .trim();
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    if desc_text.is_empty() {
+        return Err(ParseFailure::MissingDescriptionText);
+    }
 
-    // NOTE: We could easily(?) change this to handle trade-ups, too.
-    if desc_text != "Unlocked a container" {
-        // This transaction was not a container unboxing
-        return Ok(ParseSuccess::WrongTransactionType);
+    let kind = match filter.language.event_kind_for(&desc_text) {
+        Some(kind) if filter.kinds.contains(&kind) => kind,
+        // This transaction was not something we are interested in
+        Some(_) => return Ok(ParseSuccess::WrongTransactionType),
+        None => return check_unrecognised_row(trade, &desc_text, filter),
+    };
+
+    match kind {
+        EventKind::Unbox => parse_raw_unlock(trade, filter),
+        EventKind::TradeUp => parse_raw_trade_up(trade, filter),
+        kind => parse_raw_transfer(trade, kind, desc_text, filter),
     }
+}
 
-    let mut date_nodes = trade
-        .select(&TRADE_DATE_SELECTOR)
-        .next()
-        .ok_or(ParseFailure::MissingDate)?
-        .text();
+/// Rows we don't recognise are usually events we don't track, but may also
+/// be in a different language to the one we expected, in which case we'd
+/// otherwise skip every row without saying why.
+fn check_unrecognised_row(
+    trade: ElementRef<'_>,
+    desc_text: &str,
+    filter: &HistoryFilter<'_>,
+) -> ParseResult {
+    let expected = filter.language;
+    if let Some(found) = Language::ALL
+        .into_iter()
+        .find(|l| l.event_kind_for(desc_text).is_some())
+    {
+        return Err(ParseFailure::WrongLanguage { expected, found });
+    }
 
-    let date = date_nodes
-        .next()
-        .map(|i| i.trim())
-        .ok_or(ParseFailure::MissingDate)?;
-    let time = date_nodes
-        .next()
-        .map(|i| i.trim())
-        .ok_or(ParseFailure::MissingTime)?;
-    let datetime = NaiveDateTime::parse_from_str(
-        // Oct 31, 2021 1:50pm
-        format!("{} {}", date, time).as_ref(),
-        "%b %e, %Y %l:%M%P",
-    )
-    .map_err(|_| ParseFailure::DateFormattingChanged)?;
-    let datetime = Utc.from_local_datetime(&datetime).unwrap();
-
-    if since.map(|s| &datetime < s).unwrap_or(false) {
+    match parse_row_datetime(trade, expected) {
+        Err(ParseFailure::UnrecognisedDate { .. }) => {
+            Err(ParseFailure::UnrecognisedLanguage(expected))
+        }
+        _ => Ok(ParseSuccess::WrongTransactionType),
+    }
+}
+
+fn parse_raw_unlock(trade: ElementRef<'_>, filter: &HistoryFilter<'_>) -> ParseResult {
+    let datetime = parse_row_datetime(trade, filter.language)?;
+    if filter.is_too_old(&datetime) {
         // We have successfully started parsing a trade that is older than our threshold, return
         // early.
         return Ok(ParseSuccess::TooOld);
@@ -323,41 +633,195 @@ pub fn parse_raw_unlock(
         .next()
         .ok_or(ParseFailure::MissingGainedItem)?;
 
-    let inv_id = inv_id_from_node(gained_item);
-
-    let history_id_attr = case_node.value().id().unwrap();
-    let history_id = HISTORY_ID_REGEX
-        .captures(history_id_attr)
-        .ok_or(ParseFailure::MissingTradeId)?
-        .get(1)
-        .ok_or(ParseFailure::TradeIdFormattingChanged)?
-        .as_str();
+    let inv_id = inv_id_from_node(gained_item)?;
+    let history_id = history_id_from_node(case_node)?;
 
     // TODO: Want to check to see if asset_id is monotonically increasing
-    if last_seen_inventory_id
-        .map(|l| l == &inv_id)
-        .unwrap_or(false)
-    {
+    if filter.is_last_seen(&inv_id) {
         return Ok(ParseSuccess::TooOld);
     }
     let key = key_node.map(item_from_node).transpose()?;
 
-    Ok(ParseSuccess::ValidItem(RawUnlock {
-        history_id: history_id.to_string(),
+    Ok(ParseSuccess::Event(InventoryEvent::Unbox(RawUnlock {
+        history_id,
 
         case: item_from_node(case_node)?,
         key,
         item: inv_id,
 
         at: datetime,
-    }))
+    })))
+}
+
+fn parse_raw_trade_up(trade: ElementRef<'_>, filter: &HistoryFilter<'_>) -> ParseResult {
+    let datetime = parse_row_datetime(trade, filter.language)?;
+    if filter.is_too_old(&datetime) {
+        return Ok(ParseSuccess::TooOld);
+    }
+
+    let mut sides = trade.select(&INFO_SELECTOR);
+    let input_nodes: Vec<_> = sides
+        .next()
+        .ok_or(ParseFailure::MissingLostItems)?
+        .select(&TRADE_ITEM_SELECTOR)
+        .collect();
+    let first_input = input_nodes
+        .first()
+        .ok_or(ParseFailure::MissingTradeUpInputs)?;
+
+    let gained_items = sides.next().ok_or(ParseFailure::MissingGainedItems)?;
+    let output_node = gained_items
+        .select(&TRADE_ITEM_SELECTOR)
+        .next()
+        .ok_or(ParseFailure::MissingTradeUpOutput)?;
+
+    let inv_id = inv_id_from_node(output_node)?;
+    let history_id = history_id_from_node(*first_input)?;
+
+    if filter.is_last_seen(&inv_id) {
+        return Ok(ParseSuccess::TooOld);
+    }
+
+    let inputs = input_nodes
+        .into_iter()
+        .map(item_from_node)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(ParseSuccess::Event(InventoryEvent::TradeUp(RawTradeUp {
+        history_id,
+
+        inputs,
+        item: inv_id,
+
+        at: datetime,
+    })))
+}
+
+fn parse_raw_transfer(
+    trade: ElementRef<'_>,
+    kind: EventKind,
+    description: String,
+    filter: &HistoryFilter<'_>,
+) -> ParseResult {
+    let datetime = parse_row_datetime(trade, filter.language)?;
+    if filter.is_too_old(&datetime) {
+        return Ok(ParseSuccess::TooOld);
+    }
+
+    // Unlike unboxings, transfers may have only one side (e.g. a market sale
+    // only loses an item), so we have to check which side we're looking at.
+    let mut lost_nodes = Vec::new();
+    let mut gained_nodes = Vec::new();
+    for side in trade.select(&INFO_SELECTOR) {
+        let sign = side
+            .select(&PLUS_MINUS_SELECTOR)
+            .next()
+            .and_then(|n| n.text().next())
+            .map(str::trim);
+        let items = side.select(&TRADE_ITEM_SELECTOR);
+        match sign {
+            Some("+") => gained_nodes.extend(items),
+            Some("-") => lost_nodes.extend(items),
+            _ => return Err(ParseFailure::MissingTransferDirection),
+        }
+    }
+
+    let first_node = lost_nodes
+        .first()
+        .or_else(|| gained_nodes.first())
+        .ok_or(ParseFailure::MissingTransferItems)?;
+    let history_id = history_id_from_node(*first_node)?;
+
+    let gained = gained_nodes
+        .into_iter()
+        .map(|n| {
+            Ok(GainedItem {
+                item: item_from_node(n)?,
+                id: inv_id_from_node(n)?,
+            })
+        })
+        .collect::<Result<Vec<_>, ParseFailure>>()?;
+
+    if gained
+        .first()
+        .map(|g| filter.is_last_seen(&g.id))
+        .unwrap_or(false)
+    {
+        return Ok(ParseSuccess::TooOld);
+    }
+
+    let lost = lost_nodes
+        .into_iter()
+        .map(item_from_node)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let transfer = RawTransfer {
+        history_id,
+        description,
+
+        lost,
+        gained,
+
+        at: datetime,
+    };
+
+    // Safe, as unboxings and trade-ups are handled separately
+    Ok(ParseSuccess::Event(
+        InventoryEvent::from_transfer(kind, transfer).unwrap(),
+    ))
+}
+
+fn parse_row_datetime(
+    trade: ElementRef<'_>,
+    language: Language,
+) -> Result<DateTime<Utc>, ParseFailure> {
+    let mut date_nodes = trade
+        .select(&TRADE_DATE_SELECTOR)
+        .next()
+        .ok_or(ParseFailure::MissingDate)?
+        .text();
+
+    let date = date_nodes
+        .next()
+        .map(|i| i.trim())
+        .ok_or(ParseFailure::MissingDate)?;
+    let time = date_nodes
+        .next()
+        .map(|i| i.trim())
+        .ok_or(ParseFailure::MissingTime)?;
+
+    language
+        .parse_datetime(date, time)
+        .ok_or_else(|| ParseFailure::UnrecognisedDate {
+            language,
+            date: format!("{date} {time}"),
+        })
+}
+
+fn history_id_from_node(r: ElementRef<'_>) -> Result<String, ParseFailure> {
+    let history_id_attr = r.value().id().ok_or(ParseFailure::MissingTradeId)?;
+    let history_id = HISTORY_ID_REGEX
+        .captures(history_id_attr)
+        .ok_or(ParseFailure::MissingTradeId)?
+        .get(1)
+        .ok_or(ParseFailure::TradeIdFormattingChanged)?
+        .as_str();
+
+    Ok(history_id.to_string())
 }
 
 fn item_from_node(r: ElementRef<'_>) -> Result<TrivialItem, TrivialItemParseError> {
-    let name = r
+    let name_node = r
         .select(&TRADE_ITEM_NAME_SELECTOR)
         .next()
-        .ok_or(TrivialItemParseError::MissingNameNode)?
+        .ok_or(TrivialItemParseError::MissingNameNode)?;
+    // Coloured by rarity, for items that have one.
+    let color = name_node
+        .value()
+        .attr("style")
+        .and_then(|s| NAME_COLOR_REGEX.captures(s))
+        .map(|c| c[1].to_string());
+    let name = name_node
         .text()
         .next()
         .ok_or(TrivialItemParseError::MissingNameText)?
@@ -373,7 +837,7 @@ fn item_from_node(r: ElementRef<'_>) -> Result<TrivialItem, TrivialItemParseErro
         .ok_or(TrivialItemParseError::MissingImageText)?
         .split('/')
         .nth(5)
-        .unwrap();
+        .ok_or(TrivialItemParseError::ImageURLFormatChanged)?;
 
     let image_url = format!(
         "https://community.cloudflare.steamstatic.com/economy/image/{}",
@@ -382,18 +846,209 @@ fn item_from_node(r: ElementRef<'_>) -> Result<TrivialItem, TrivialItemParseErro
 
     Ok(TrivialItem {
         name,
-        color: None,
+        color,
         image_url,
     })
 }
 
-fn inv_id_from_node(r: ElementRef<'_>) -> InventoryId {
+fn inv_id_from_node(r: ElementRef<'_>) -> Result<InventoryId, ParseFailure> {
     let v = r.value();
-    let class_id = v.attr("data-classid").unwrap().parse().unwrap();
-    let instance_id = v.attr("data-instanceid").unwrap().parse().unwrap();
+    let class_id = v
+        .attr("data-classid")
+        .ok_or(ParseFailure::MissingInventoryId)?
+        .parse()?;
+    let instance_id = v
+        .attr("data-instanceid")
+        .ok_or(ParseFailure::MissingInventoryId)?
+        .parse()?;
 
-    InventoryId {
+    Ok(InventoryId {
         class_id,
         instance_id,
+    })
+}
+
+/// Makes a best-effort attempt to find the history id of a row, for
+/// diagnostics.
+pub(crate) fn history_id_from_row(trade: ElementRef<'_>) -> Option<String> {
+    trade
+        .select(&TRADE_ITEM_SELECTOR)
+        .find_map(|n| history_id_from_node(n).ok())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+    use scraper::Html;
+
+    use super::{
+        parse_history_cursor, parse_history_row, parse_history_rows, HistoryFilter, InventoryId,
+        ParseSuccess, TRADE_SELECTOR,
+    };
+    use crate::event::{EventKind, EventKinds, InventoryEvent};
+    use crate::language::Language;
+    use crate::test_util::{item_html, row_html, HISTORY_ID};
+
+    fn parse(html: &str) -> ParseSuccess {
+        let kinds: EventKinds = EventKind::ALL.into_iter().collect();
+        parse_with_kinds(html, &kinds)
+    }
+
+    fn parse_with_kinds(html: &str, kinds: &EventKinds) -> ParseSuccess {
+        let page = Html::parse_document(html);
+        let row = page.select(&TRADE_SELECTOR).next().unwrap();
+        let filter = HistoryFilter {
+            since: None,
+            last_seen_inventory_id: None,
+            kinds,
+            language: Language::English,
+        };
+        parse_history_row(row, &filter).unwrap()
+    }
+
+    #[test]
+    fn test_parse_unlock() {
+        let html = row_html(
+            "Unlocked a container",
+            &[
+                item_html(0, "Clutch Case", 1).replace(
+                    r#"class="history_item_name""#,
+                    r#"class="history_item_name" style="color: #D2D2D2""#,
+                ),
+                item_html(1, "Clutch Case Key", 2),
+            ],
+            &[item_html(2, "P90 | Facility Negative (Minimal Wear)", 3)],
+        );
+
+        let unlock = match parse(&html) {
+            ParseSuccess::Event(InventoryEvent::Unbox(u)) => u,
+            _ => panic!("expected an unlock"),
+        };
+
+        assert_eq!(unlock.history_id, HISTORY_ID);
+        assert_eq!(unlock.case.get_name(), "Clutch Case");
+        assert_eq!(unlock.key.unwrap().get_name(), "Clutch Case Key");
+        assert_eq!(unlock.case.get_color(), Some("D2D2D2"));
+        assert_eq!(unlock.item, InventoryId::new(3, 0));
+        assert_eq!(
+            unlock.at,
+            Utc.with_ymd_and_hms(2021, 10, 31, 13, 50, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_trade_up() {
+        let inputs: Vec<String> = (0..10)
+            .map(|i| item_html(i, "P250 | Sand Dune (Field-Tested)", 10 + i as u64))
+            .collect();
+        let html = row_html(
+            "Completed a trade-up contract",
+            &inputs,
+            &[item_html(10, "AK-47 | Safari Mesh (Field-Tested)", 99)],
+        );
+
+        let trade_up = match parse(&html) {
+            ParseSuccess::Event(InventoryEvent::TradeUp(t)) => t,
+            _ => panic!("expected a trade-up"),
+        };
+
+        assert_eq!(trade_up.history_id, HISTORY_ID);
+        assert_eq!(trade_up.inputs.len(), 10);
+        assert_eq!(trade_up.item, InventoryId::new(99, 0));
+    }
+
+    #[test]
+    fn test_parse_other_transaction() {
+        let html = row_html(
+            "Listed on the Community Market",
+            &[item_html(0, "Clutch Case", 1)],
+            &[],
+        );
+
+        assert!(matches!(parse(&html), ParseSuccess::WrongTransactionType));
+    }
+
+    #[test]
+    fn test_parse_market_sale() {
+        let html = row_html(
+            "Sold on the Community Market",
+            &[item_html(0, "Clutch Case", 1)],
+            &[],
+        );
+
+        let sale = match parse(&html) {
+            ParseSuccess::Event(InventoryEvent::MarketSale(t)) => t,
+            _ => panic!("expected a market sale"),
+        };
+
+        assert_eq!(sale.history_id, HISTORY_ID);
+        assert_eq!(sale.lost.len(), 1);
+        assert!(sale.gained.is_empty());
+    }
+
+    #[test]
+    fn test_parse_unwanted_kind() {
+        let html = row_html(
+            "You traded with <a href=\"#\">someone</a>",
+            &[item_html(0, "Clutch Case", 1)],
+            &[item_html(1, "Clutch Case Key", 2)],
+        );
+
+        let kinds: EventKinds = EventKind::DEFAULT.into_iter().collect();
+        assert!(matches!(
+            parse_with_kinds(&html, &kinds),
+            ParseSuccess::WrongTransactionType
+        ));
+
+        let trade = match parse(&html) {
+            ParseSuccess::Event(InventoryEvent::Trade(t)) => t,
+            _ => panic!("expected a trade"),
+        };
+        assert_eq!(trade.description, "You traded with someone");
+        assert_eq!(trade.gained[0].id, InventoryId::new(2, 0));
+    }
+
+    #[test]
+    fn test_parse_history_cursor() {
+        let page = r#"<script>
+            var g_historyCursor = {"time":1666393920,"time_frac":0,"s":"12052458927"};
+        </script>"#;
+
+        let cursor = parse_history_cursor(page).unwrap();
+        assert_eq!(cursor.time, 1666393920);
+        assert_eq!(cursor.time_frac, 0);
+        assert_eq!(cursor.s, "12052458927");
+
+        assert!(parse_history_cursor("<html></html>").is_none());
+    }
+
+    #[test]
+    fn test_parse_rows_skips_malformed() {
+        let good = row_html(
+            "Unlocked a container",
+            &[item_html(0, "Clutch Case", 1)],
+            &[item_html(1, "AK-47 | Redline (Field-Tested)", 2)],
+        );
+        let bad = good.replace(r#"data-classid="2""#, "");
+        let page = Html::parse_document(&format!(
+            "{}{}",
+            bad.replace("</body></html>", ""),
+            good.replace("<html><body>", "")
+        ));
+
+        let kinds: EventKinds = EventKind::ALL.into_iter().collect();
+        let filter = HistoryFilter {
+            since: None,
+            last_seen_inventory_id: None,
+            kinds: &kinds,
+            language: Language::English,
+        };
+        let parsed = parse_history_rows(&page, &filter);
+
+        assert_eq!(parsed.rows, 2);
+        assert_eq!(parsed.events.len(), 1);
+        assert_eq!(parsed.failures.len(), 1);
+        assert_eq!(parsed.failures[0].history_id.as_deref(), Some(HISTORY_ID));
+        assert!(parsed.failures[0].html.contains("tradehistoryrow"));
     }
 }
@@ -1,15 +1,21 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use bb8_redis::bb8::Pool;
 use bb8_redis::redis::IntoConnectionInfo;
 use bb8_redis::redis::RedisError;
 use bb8_redis::RedisConnectionManager;
-use cache::Cache;
+use chrono::Utc;
 use reqwest::Client;
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use super::errors::MarketPriceFetchError;
+use super::price_history::{PriceHistory, PriceSnapshot};
+use super::price_source::{PriceSource, PriceSources, SteamMarket};
+use super::retry::RetryPolicy;
+use super::Currency;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RawMarketPrices {
@@ -18,44 +24,92 @@ pub struct RawMarketPrices {
     volume: Option<String>,
 }
 
-impl From<RawMarketPrices> for MarketPrices {
-    fn from(raw: RawMarketPrices) -> Self {
-        let volume = raw.volume.map(|v| v.replace(',', "").parse().unwrap());
-        Self {
-            lowest_price: raw.lowest_price.as_deref().and_then(parse_currency),
-            median_price: raw.median_price.as_deref().and_then(parse_currency),
+impl RawMarketPrices {
+    fn parse(self, currency: Currency) -> MarketPrices {
+        // Volume is grouped with whatever separator the currency uses.
+        let volume = self.volume.and_then(|v| {
+            v.chars()
+                .filter(char::is_ascii_digit)
+                .collect::<String>()
+                .parse()
+                .ok()
+        });
+
+        MarketPrices {
+            currency,
+            lowest_price: self
+                .lowest_price
+                .as_deref()
+                .and_then(|p| currency.parse_price(p)),
+            median_price: self
+                .median_price
+                .as_deref()
+                .and_then(|p| currency.parse_price(p)),
             volume,
+            source: None,
         }
     }
 }
 
-fn parse_currency(amt: &str) -> Option<f32> {
-    let chars = amt.replace(',', "");
-    let mut chars = chars.chars();
-    chars.next();
-    chars.as_str().parse::<f32>().ok()
-}
-
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MarketPrices {
-    lowest_price: Option<f32>,
-    median_price: Option<f32>,
-    volume: Option<i32>,
+    // Prices cached before we tracked currency were all in dollars.
+    #[serde(default)]
+    pub currency: Currency,
+    #[serde(with = "rust_decimal::serde::float_option")]
+    pub lowest_price: Option<Decimal>,
+    #[serde(with = "rust_decimal::serde::float_option")]
+    pub median_price: Option<Decimal>,
+    pub volume: Option<i32>,
+    /// The name of the [`PriceSource`] these prices came from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+impl MarketPrices {
+    /// Prices for an item nobody had any data for.
+    pub fn empty(currency: Currency) -> Self {
+        Self {
+            currency,
+            lowest_price: None,
+            median_price: None,
+            volume: None,
+            source: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lowest_price.is_none() && self.median_price.is_none()
+    }
+
+    /// What we'd expect to pay for the item: the lowest listing if there is
+    /// one, otherwise the median sale price.
+    pub fn price(&self) -> Option<Decimal> {
+        self.lowest_price.or(self.median_price)
+    }
 }
 
 pub async fn get_market_price(
     client: &Client,
+    retry_policy: &RetryPolicy,
+    base_url: &str,
+    currency: Currency,
     market_name: &str,
 ) -> Result<MarketPrices, MarketPriceFetchError> {
-    let url = format!(
-        "https://steamcommunity.com/market/priceoverview/?appid=730&currency=1&market_hash_name={}",
-        market_name
-    );
-
-    let resp = client.get(url).send().await?.text().await?;
+    let url = format!("{}/market/priceoverview/", base_url);
+
+    let req = client
+        .get(url)
+        .query(&[
+            ("appid", "730"),
+            ("currency", &currency.steam_id().to_string()),
+            ("market_hash_name", market_name),
+        ])
+        .build()?;
+    let resp = retry_policy.execute(client, req).await?.text().await?;
     let parsed: RawMarketPrices = serde_json::from_str(&resp)?;
 
-    Ok(parsed.into())
+    Ok(parsed.parse(currency))
 }
 
 #[derive(Debug, Error)]
@@ -66,9 +120,12 @@ pub enum MarketPriceClientCreateError {
     Redis(#[from] RedisError),
 }
 
+/// Fetches market prices from a chain of sources, keeping a history of them.
+#[derive(Clone)]
 pub struct MarketPriceClient {
-    client: Client,
-    cache: Cache<MarketPrices>,
+    sources: PriceSources,
+    currency: Currency,
+    history: PriceHistory,
 }
 
 impl MarketPriceClient {
@@ -78,26 +135,118 @@ impl MarketPriceClient {
             .map_err(MarketPriceClientCreateError::InvalidRedisUrl)?;
         let mgr = RedisConnectionManager::new(conn_info.clone())?;
         let pool = Arc::new(Pool::builder().build(mgr).await?);
-        let client = Client::new();
+        let sources = PriceSources::new(vec![Arc::new(SteamMarket::new())]);
+        let currency = Currency::default();
+
+        let history = PriceHistory::new(pool, currency);
 
-        let cache = Cache::new(pool, "market".to_string());
+        Ok(Self {
+            sources,
+            currency,
+            history,
+        })
+    }
 
-        Ok(Self { client, cache })
+    /// Sets the sources this client asks for prices, in priority order,
+    /// instead of only the Steam Market.
+    pub fn with_sources<I: IntoIterator<Item = Arc<dyn PriceSource>>>(
+        mut self,
+        sources: I,
+    ) -> Self {
+        self.sources = PriceSources::new(sources.into_iter().collect());
+        self
     }
 
+    /// Sets the currency this client fetches prices in.
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.currency = currency;
+        self.history = self.history.with_currency(currency);
+        self
+    }
+
+    /// The currency this client fetches prices in.
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    /// The prices we have recorded so far.
+    pub fn history(&self) -> &PriceHistory {
+        &self.history
+    }
+
+    /// Fetches the current prices of an item from our sources, and records
+    /// them. If no source has any, the last prices we recorded are kept and
+    /// returned instead, where we have some.
+    pub async fn refresh(&self, market_name: &str) -> Result<PriceSnapshot, MarketPriceFetchError> {
+        let prices = self.sources.fetch(market_name, self.currency).await?;
+        let snapshot = PriceSnapshot {
+            at: Utc::now(),
+            prices,
+        };
+
+        if snapshot.prices.is_empty() {
+            return match self.history.latest(market_name).await {
+                Ok(previous) => Ok(previous.unwrap_or(snapshot)),
+                Err(e) => {
+                    log::warn!("failed to read price history: {}", e);
+                    Ok(snapshot)
+                }
+            };
+        }
+
+        if let Err(e) = self.history.record(market_name, &snapshot).await {
+            log::warn!("error recording market price: {}", e);
+        }
+
+        Ok(snapshot)
+    }
+
+    /// The latest prices we have for an item, only asking Steam if we've
+    /// never seen it before. [`MarketPriceClient::run_refresher`] keeps
+    /// these up to date.
     pub async fn get(&self, market_name: &str) -> Result<MarketPrices, MarketPriceFetchError> {
-        match self.cache.get(market_name).await {
-            Ok(Some(price)) => return Ok(price),
+        match self.history.latest(market_name).await {
+            Ok(Some(snapshot)) => return Ok(snapshot.prices),
             Ok(None) => (),
-            Err(e) => log::warn!("failed to read entry from cache: {}", e),
+            Err(e) => log::warn!("failed to read price history: {}", e),
         };
 
-        let price = get_market_price(&self.client, market_name).await?;
+        Ok(self.refresh(market_name).await?.prices)
+    }
 
-        if let Err(e) = self.cache.set(market_name, &price).await {
-            log::warn!("error updating market cache: {}", e);
+    /// Re-fetches the prices of every item we know of each `interval`,
+    /// waiting `request_delay` between requests to stay within Steam's rate
+    /// limits. Runs forever.
+    pub async fn run_refresher(&self, interval: Duration, request_delay: Duration) {
+        let mut tick = tokio::time::interval(interval);
+        loop {
+            tick.tick().await;
+
+            let items = match self.history.items().await {
+                Ok(items) => items,
+                Err(e) => {
+                    log::warn!("error listing items to refresh prices of: {}", e);
+                    continue;
+                }
+            };
+            log::info!("refreshing market prices of {} items", items.len());
+
+            // Items looked up for the first time since the last round are
+            // fresh enough already.
+            let fresh_from = Utc::now()
+                - chrono::Duration::from_std(interval).unwrap_or_else(|_| chrono::Duration::zero());
+            for item in items {
+                match self.history.latest(&item).await {
+                    Ok(Some(snapshot)) if snapshot.at > fresh_from => continue,
+                    Ok(_) => (),
+                    Err(e) => log::warn!("failed to read price history: {}", e),
+                }
+
+                if let Err(e) = self.refresh(&item).await {
+                    log::warn!("error refreshing market price of {}: {}", item, e);
+                }
+                tokio::time::sleep(request_delay).await;
+            }
         }
-
-        Ok(price)
     }
 }
@@ -0,0 +1,157 @@
+#![allow(clippy::let_unit_value)]
+//! Timestamped snapshots of Steam Market prices, kept in Redis.
+//!
+//! Each item's snapshots live in a sorted set scored by the time they were
+//! taken, so we can find the latest price, the price at some point in time,
+//! or every price in a range.
+
+use std::sync::Arc;
+
+use bb8_redis::bb8::{Pool, PooledConnection, RunError};
+use bb8_redis::redis::{AsyncCommands, RedisError};
+use bb8_redis::RedisConnectionManager;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{Currency, MarketPrices};
+
+const KEY_PREFIX: &str = "price_history";
+
+type Result<T> = std::result::Result<T, PriceHistoryError>;
+
+#[derive(Debug, Error)]
+pub enum PriceHistoryError {
+    #[error("redis error: {0}")]
+    Redis(#[from] RedisError),
+    #[error("ser/deserialisation error: {0}")]
+    Serde(#[from] serde_json::Error),
+    #[error("could not acquire a connection in time")]
+    ConnectionTimeout,
+}
+
+impl From<RunError<RedisError>> for PriceHistoryError {
+    fn from(e: RunError<RedisError>) -> Self {
+        match e {
+            RunError::User(e) => Self::Redis(e),
+            RunError::TimedOut => Self::ConnectionTimeout,
+        }
+    }
+}
+
+/// The prices of an item at a point in time.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PriceSnapshot {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub prices: MarketPrices,
+}
+
+/// Price snapshots for every item we've looked up, in one currency.
+#[derive(Clone)]
+pub struct PriceHistory {
+    pool: Arc<Pool<RedisConnectionManager>>,
+    currency: Currency,
+}
+
+impl PriceHistory {
+    pub fn new(pool: Arc<Pool<RedisConnectionManager>>, currency: Currency) -> Self {
+        Self { pool, currency }
+    }
+
+    /// The same history, in a different currency.
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.currency = currency;
+        self
+    }
+
+    async fn get_conn<'a, 'b>(&'a self) -> Result<PooledConnection<'b, RedisConnectionManager>>
+    where
+        'a: 'b,
+    {
+        Ok(self.pool.get().await?)
+    }
+
+    fn items_key(&self) -> String {
+        format!("{KEY_PREFIX}_{}_items", self.currency)
+    }
+
+    fn item_key(&self, market_name: &str) -> String {
+        format!("{KEY_PREFIX}_{}_{}", self.currency, market_name)
+    }
+
+    fn decode(raw: Vec<String>) -> Result<Vec<PriceSnapshot>> {
+        Ok(raw
+            .iter()
+            .map(|s| serde_json::from_str(s))
+            .collect::<std::result::Result<_, _>>()?)
+    }
+
+    /// Records a snapshot, and remembers the item so it gets refreshed.
+    pub async fn record(&self, market_name: &str, snapshot: &PriceSnapshot) -> Result<()> {
+        let data = serde_json::to_string(snapshot)?;
+        let mut conn = self.get_conn().await?;
+        let _: () = bb8_redis::redis::pipe()
+            .zadd(
+                self.item_key(market_name),
+                data,
+                snapshot.at.timestamp_millis(),
+            )
+            .sadd(self.items_key(), market_name)
+            .query_async(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// The most recent snapshot of an item.
+    pub async fn latest(&self, market_name: &str) -> Result<Option<PriceSnapshot>> {
+        let mut conn = self.get_conn().await?;
+        let raw: Vec<String> = conn.zrevrange(self.item_key(market_name), 0, 0).await?;
+
+        Ok(Self::decode(raw)?.pop())
+    }
+
+    /// The last snapshot of an item taken at or before the given time.
+    pub async fn at(&self, market_name: &str, at: DateTime<Utc>) -> Result<Option<PriceSnapshot>> {
+        let mut conn = self.get_conn().await?;
+        let raw: Vec<String> = conn
+            .zrevrangebyscore_limit(
+                self.item_key(market_name),
+                at.timestamp_millis(),
+                "-inf",
+                0,
+                1,
+            )
+            .await?;
+
+        Ok(Self::decode(raw)?.pop())
+    }
+
+    /// Every snapshot of an item taken between the given times, oldest first.
+    pub async fn between(
+        &self,
+        market_name: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<PriceSnapshot>> {
+        let mut conn = self.get_conn().await?;
+        let raw: Vec<String> = conn
+            .zrangebyscore(
+                self.item_key(market_name),
+                from.timestamp_millis(),
+                to.timestamp_millis(),
+            )
+            .await?;
+
+        Self::decode(raw)
+    }
+
+    /// The market names of every item we have a price for.
+    pub async fn items(&self) -> Result<Vec<String>> {
+        let mut conn = self.get_conn().await?;
+        let items: Vec<String> = conn.smembers(self.items_key()).await?;
+
+        Ok(items)
+    }
+}
@@ -0,0 +1,518 @@
+//! Places we can get market prices from, tried in order until one has data
+//! for the item.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use reqwest::Client;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+use crate::errors::MarketPriceFetchError;
+use crate::price_client::{get_market_price, MarketPrices};
+use crate::retry::RetryPolicy;
+use crate::Currency;
+
+const COMMUNITY_URL: &str = "https://steamcommunity.com";
+const SKINPORT_URL: &str = "https://api.skinport.com";
+// Skinport only refreshes its listing this often, and rate-limits anyone
+// asking more.
+const SKINPORT_LISTING_TTL: Duration = Duration::from_secs(5 * 60);
+const SKINPORT_CURRENCIES: &[Currency] = &[
+    Currency::AUD,
+    Currency::BRL,
+    Currency::CAD,
+    Currency::CHF,
+    Currency::CNY,
+    Currency::EUR,
+    Currency::GBP,
+    Currency::NOK,
+    Currency::PLN,
+    Currency::RUB,
+    Currency::TRY,
+    Currency::USD,
+];
+
+/// Somewhere we can find out what an item is worth.
+#[async_trait]
+pub trait PriceSource: Send + Sync {
+    /// Short name recorded against the prices this source supplies.
+    fn name(&self) -> &str;
+
+    /// Fetches prices for an item, or `None` if this source doesn't know
+    /// about it or can't price things in the given currency.
+    async fn fetch(
+        &self,
+        market_name: &str,
+        currency: Currency,
+    ) -> Result<Option<MarketPrices>, MarketPriceFetchError>;
+}
+
+/// The Steam Community Market's price overview.
+pub struct SteamMarket {
+    client: Client,
+    retry_policy: RetryPolicy,
+    base_url: String,
+}
+
+impl Default for SteamMarket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SteamMarket {
+    pub fn new() -> Self {
+        Self {
+            client: Client::new(),
+            retry_policy: RetryPolicy::default(),
+            base_url: COMMUNITY_URL.to_string(),
+        }
+    }
+
+    /// Sets how this source backs off and retries requests that Steam
+    /// rejects.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Points this source at a different Steam Community host, instead of
+    /// steamcommunity.com.
+    pub fn with_base_url<S: Into<String>>(mut self, base_url: S) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+}
+
+#[async_trait]
+impl PriceSource for SteamMarket {
+    fn name(&self) -> &str {
+        "steam"
+    }
+
+    async fn fetch(
+        &self,
+        market_name: &str,
+        currency: Currency,
+    ) -> Result<Option<MarketPrices>, MarketPriceFetchError> {
+        let prices = get_market_price(
+            &self.client,
+            &self.retry_policy,
+            &self.base_url,
+            currency,
+            market_name,
+        )
+        .await?;
+
+        Ok(Some(prices))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PriceSheetLoadError {
+    #[error("io error: {0}")]
+    IO(#[from] io::Error),
+    #[error("error parsing json price sheet: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("error parsing csv price sheet: {0}")]
+    Csv(#[from] csv::Error),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SheetEntry {
+    Price(Decimal),
+    Prices {
+        lowest_price: Option<Decimal>,
+        median_price: Option<Decimal>,
+        volume: Option<i32>,
+    },
+}
+
+#[derive(Deserialize)]
+struct SheetRow {
+    market_hash_name: String,
+    lowest_price: Option<Decimal>,
+    median_price: Option<Decimal>,
+    volume: Option<i32>,
+}
+
+/// Fixed prices for items the market doesn't have data for, read from a
+/// file.
+pub struct PriceSheet {
+    currency: Currency,
+    prices: HashMap<String, MarketPrices>,
+}
+
+impl PriceSheet {
+    fn prices(
+        currency: Currency,
+        lowest_price: Option<Decimal>,
+        median_price: Option<Decimal>,
+        volume: Option<i32>,
+    ) -> MarketPrices {
+        MarketPrices {
+            currency,
+            lowest_price,
+            median_price,
+            volume,
+            source: None,
+        }
+    }
+
+    /// Reads a JSON object of market names to either a price, or an object
+    /// with `lowest_price`, `median_price` and `volume`.
+    pub fn from_json(contents: &str, currency: Currency) -> Result<Self, PriceSheetLoadError> {
+        let entries: HashMap<String, SheetEntry> = serde_json::from_str(contents)?;
+        let prices = entries
+            .into_iter()
+            .map(|(name, entry)| {
+                let prices = match entry {
+                    SheetEntry::Price(p) => Self::prices(currency, Some(p), Some(p), None),
+                    SheetEntry::Prices {
+                        lowest_price,
+                        median_price,
+                        volume,
+                    } => Self::prices(currency, lowest_price, median_price, volume),
+                };
+                (name, prices)
+            })
+            .collect();
+
+        Ok(Self { currency, prices })
+    }
+
+    /// Reads a CSV with a `market_hash_name,lowest_price,median_price,volume`
+    /// header. Only the first two columns are required.
+    pub fn from_csv(contents: &str, currency: Currency) -> Result<Self, PriceSheetLoadError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(contents.as_bytes());
+        let prices = reader
+            .deserialize()
+            .map(|row| {
+                let row: SheetRow = row?;
+                let prices = Self::prices(currency, row.lowest_price, row.median_price, row.volume);
+                Ok((row.market_hash_name, prices))
+            })
+            .collect::<Result<_, csv::Error>>()?;
+
+        Ok(Self { currency, prices })
+    }
+
+    /// Reads a price sheet in the given currency, as CSV if the file ends in
+    /// `.csv` and JSON otherwise.
+    pub async fn load<P: AsRef<Path>>(
+        path: P,
+        currency: Currency,
+    ) -> Result<Self, PriceSheetLoadError> {
+        let path = path.as_ref();
+        let contents = tokio::fs::read_to_string(path).await?;
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => Self::from_csv(&contents, currency),
+            _ => Self::from_json(&contents, currency),
+        }
+    }
+}
+
+#[async_trait]
+impl PriceSource for PriceSheet {
+    fn name(&self) -> &str {
+        "sheet"
+    }
+
+    async fn fetch(
+        &self,
+        market_name: &str,
+        currency: Currency,
+    ) -> Result<Option<MarketPrices>, MarketPriceFetchError> {
+        if currency != self.currency {
+            return Ok(None);
+        }
+
+        Ok(self.prices.get(market_name).cloned())
+    }
+}
+
+#[derive(Deserialize)]
+struct SkinportItem {
+    market_hash_name: String,
+    min_price: Option<Decimal>,
+    median_price: Option<Decimal>,
+}
+
+struct SkinportListing {
+    currency: Currency,
+    fetched_at: Instant,
+    prices: HashMap<String, MarketPrices>,
+}
+
+/// Skinport's marketplace, which lists most items in one request.
+pub struct Skinport {
+    client: Client,
+    retry_policy: RetryPolicy,
+    base_url: String,
+    listing: Mutex<Option<SkinportListing>>,
+}
+
+impl Default for Skinport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Skinport {
+    pub fn new() -> Self {
+        Self {
+            client: Client::new(),
+            retry_policy: RetryPolicy::default(),
+            base_url: SKINPORT_URL.to_string(),
+            listing: Mutex::new(None),
+        }
+    }
+
+    /// Sets how this source backs off and retries requests that Skinport
+    /// rejects.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Points this source at a different host, instead of api.skinport.com.
+    pub fn with_base_url<S: Into<String>>(mut self, base_url: S) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    async fn fetch_listing(
+        &self,
+        currency: Currency,
+    ) -> Result<HashMap<String, MarketPrices>, MarketPriceFetchError> {
+        let req = self
+            .client
+            .get(format!("{}/v1/items", self.base_url))
+            .query(&[
+                ("app_id", "730"),
+                ("currency", currency.as_str()),
+                ("tradable", "0"),
+            ])
+            .build()?;
+        let resp = self
+            .retry_policy
+            .execute(&self.client, req)
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let items: Vec<SkinportItem> = serde_json::from_str(&resp)?;
+
+        Ok(items
+            .into_iter()
+            .map(|i| {
+                let prices = MarketPrices {
+                    currency,
+                    lowest_price: i.min_price,
+                    median_price: i.median_price,
+                    // Skinport only tells us how many are listed, not sold.
+                    volume: None,
+                    source: None,
+                };
+                (i.market_hash_name, prices)
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl PriceSource for Skinport {
+    fn name(&self) -> &str {
+        "skinport"
+    }
+
+    async fn fetch(
+        &self,
+        market_name: &str,
+        currency: Currency,
+    ) -> Result<Option<MarketPrices>, MarketPriceFetchError> {
+        if !SKINPORT_CURRENCIES.contains(&currency) {
+            return Ok(None);
+        }
+
+        // Held across the fetch, so concurrent lookups share one request.
+        let mut listing = self.listing.lock().await;
+        let stale = match listing.as_ref() {
+            Some(l) => l.currency != currency || l.fetched_at.elapsed() >= SKINPORT_LISTING_TTL,
+            None => true,
+        };
+        if stale {
+            *listing = Some(SkinportListing {
+                currency,
+                fetched_at: Instant::now(),
+                prices: self.fetch_listing(currency).await?,
+            });
+        }
+
+        Ok(listing
+            .as_ref()
+            .and_then(|l| l.prices.get(market_name))
+            .cloned())
+    }
+}
+
+/// Price sources in priority order.
+#[derive(Clone)]
+pub(crate) struct PriceSources(Vec<Arc<dyn PriceSource>>);
+
+impl PriceSources {
+    pub(crate) fn new(sources: Vec<Arc<dyn PriceSource>>) -> Self {
+        Self(sources)
+    }
+
+    /// Asks each source in turn for prices, returning the first with any,
+    /// tagged with where they came from. If none had any, fails if any
+    /// source did, since it may have had prices.
+    pub(crate) async fn fetch(
+        &self,
+        market_name: &str,
+        currency: Currency,
+    ) -> Result<MarketPrices, MarketPriceFetchError> {
+        let mut first_err = None;
+        for source in self.0.iter() {
+            match source.fetch(market_name, currency).await {
+                Ok(Some(prices)) if !prices.is_empty() => {
+                    return Ok(MarketPrices {
+                        source: Some(source.name().to_string()),
+                        ..prices
+                    });
+                }
+                Ok(_) => (),
+                Err(e) => {
+                    log::warn!(
+                        "error fetching price of {} from {}: {}",
+                        market_name,
+                        source.name(),
+                        e
+                    );
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+
+        match first_err {
+            Some(e) => Err(e),
+            None => Ok(MarketPrices::empty(currency)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use rust_decimal::Decimal;
+
+    use mock_steam::{Failure, Fixtures};
+
+    use super::{PriceSheet, PriceSources, Skinport, SteamMarket};
+    use crate::errors::{MarketPriceFetchError, RequestError};
+    use crate::retry::RetryPolicy;
+    use crate::Currency;
+
+    #[test]
+    fn test_price_sheet() {
+        let csv = "market_hash_name,lowest_price,median_price\n\
+                   \"Sticker | Team Dignitas, Cologne 2014\",1.5,1.75\n\
+                   Clutch Case,0.5\n";
+        let sheet = PriceSheet::from_csv(csv, Currency::USD).unwrap();
+        let sticker = &sheet.prices["Sticker | Team Dignitas, Cologne 2014"];
+        assert_eq!(sticker.median_price, Some(Decimal::new(175, 2)));
+        assert_eq!(sheet.prices["Clutch Case"].median_price, None);
+
+        let json = r#"{"Clutch Case": 0.5, "Sticker | Rare": {"median_price": 12}}"#;
+        let sheet = PriceSheet::from_json(json, Currency::USD).unwrap();
+        assert_eq!(
+            sheet.prices["Clutch Case"].lowest_price,
+            Some(Decimal::new(5, 1))
+        );
+        assert_eq!(
+            sheet.prices["Sticker | Rare"].median_price,
+            Some(Decimal::new(12, 0))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fallback_chain() {
+        // The market has no prices for anything.
+        let base = mock_steam::spawn(Fixtures {
+            price_overview: r#"{"success": true}"#.to_string(),
+            ..Default::default()
+        });
+        let sheet = PriceSheet::from_json(r#"{"Sticker | Rare": 0.5}"#, Currency::USD).unwrap();
+        let sources = PriceSources::new(vec![
+            Arc::new(SteamMarket::new().with_base_url(&base)),
+            Arc::new(Skinport::new().with_base_url(&base)),
+            Arc::new(sheet),
+        ]);
+
+        let ak = sources
+            .fetch("AK-47 | Redline (Field-Tested)", Currency::USD)
+            .await
+            .unwrap();
+        assert_eq!(ak.source.as_deref(), Some("skinport"));
+        assert_eq!(ak.lowest_price, Some(Decimal::new(95, 1)));
+
+        let sticker = sources
+            .fetch("Sticker | Rare", Currency::USD)
+            .await
+            .unwrap();
+        assert_eq!(sticker.source.as_deref(), Some("sheet"));
+
+        let unknown = sources.fetch("Nothing", Currency::USD).await.unwrap();
+        assert!(unknown.is_empty() && unknown.source.is_none());
+
+        // Sheets only price things in their own currency.
+        let eur = sources
+            .fetch("Sticker | Rare", Currency::EUR)
+            .await
+            .unwrap();
+        assert!(eur.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fallback_chain_reports_failures() {
+        let base = mock_steam::spawn(Fixtures {
+            failures: vec![Failure {
+                path: "/market/priceoverview/".to_string(),
+                status: 429,
+                retry_after: None,
+                times: 1,
+            }],
+            ..Default::default()
+        });
+        let sheet = PriceSheet::from_json(r#"{"Sticker | Rare": 0.5}"#, Currency::USD).unwrap();
+        let sources = PriceSources::new(vec![
+            Arc::new(
+                SteamMarket::new()
+                    .with_base_url(&base)
+                    .with_retry_policy(RetryPolicy::none()),
+            ),
+            Arc::new(sheet),
+        ]);
+
+        // The sheet answered, but the market may have had prices.
+        let res = sources.fetch("Nothing", Currency::USD).await;
+        assert!(matches!(
+            res,
+            Err(MarketPriceFetchError::Request(RequestError::RateLimited(_)))
+        ));
+    }
+}
@@ -1,10 +1,11 @@
 use bb8_redis::redis::{self, from_redis_value, FromRedisValue, RedisResult, ToRedisArgs};
 use chrono::{DateTime, Utc};
 pub use csgofloat::ItemDescription;
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
-use crate::parsing::TrivialItem;
-use crate::{MarketPrices, UnhydratedUnlock};
+use crate::parsing::{ItemTraits, TrivialItem};
+use crate::{MarketPrices, UnhydratedTradeUp, UnhydratedTransfer, UnhydratedUnlock};
 
 impl FromRedisValue for UnhydratedUnlock {
     fn from_redis_value(v: &redis::Value) -> RedisResult<Self> {
@@ -23,13 +24,68 @@ impl ToRedisArgs for UnhydratedUnlock {
     }
 }
 
+/// What a case and the item in it were worth when it was opened.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UnboxValue {
+    pub case_value: MarketPrices,
+    pub item_value: MarketPrices,
+}
+
+/// An unlock as we persist it, along with its value when it was opened.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredUnlock {
+    #[serde(flatten)]
+    pub entry: UnhydratedUnlock,
+    /// Missing for unlocks stored before we started recording this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unbox_value: Option<UnboxValue>,
+}
+
+impl FromRedisValue for StoredUnlock {
+    fn from_redis_value(v: &redis::Value) -> RedisResult<Self> {
+        let data: Vec<u8> = from_redis_value(v)?;
+        Ok(serde_json::from_slice(&data).unwrap())
+    }
+}
+
+impl ToRedisArgs for StoredUnlock {
+    fn write_redis_args<W>(&self, out: &mut W)
+    where
+        W: ?Sized + redis::RedisWrite,
+    {
+        let data = serde_json::to_vec(self).unwrap();
+        out.write_arg(&data)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Unlock {
     pub key: Option<TrivialItem>,
     pub case: TrivialItem,
+    /// The current value of the case.
     pub case_value: MarketPrices,
     pub item: ItemDescription,
+    /// What Steam told us about the item, which we have even when CSGOFloat
+    /// doesn't.
+    #[serde(flatten)]
+    pub item_traits: ItemTraits,
+    /// The current value of the item.
     pub item_value: MarketPrices,
+    /// Whether `item` was made without CSGOFloat, and so has no float yet.
+    #[serde(default)]
+    pub float_pending: bool,
+    /// What the case and item were worth when this was opened, if we know.
+    #[serde(default)]
+    pub unbox_value: Option<UnboxValue>,
+    /// What the key cost, if one was used and we know its price.
+    #[serde(default, with = "rust_decimal::serde::float_option")]
+    pub key_cost: Option<Decimal>,
+    /// What opening this cost: the case when it was opened, plus the key.
+    #[serde(default, with = "rust_decimal::serde::float_option")]
+    pub cost_basis: Option<Decimal>,
+    /// The current value of the item, less the cost basis.
+    #[serde(default, with = "rust_decimal::serde::float_option")]
+    pub profit: Option<Decimal>,
 
     pub at: DateTime<Utc>,
     pub name: String,
@@ -51,3 +107,67 @@ impl ToRedisArgs for Unlock {
         out.write_arg(&data)
     }
 }
+
+impl FromRedisValue for UnhydratedTradeUp {
+    fn from_redis_value(v: &redis::Value) -> RedisResult<Self> {
+        let data: Vec<u8> = from_redis_value(v)?;
+        Ok(serde_json::from_slice(&data).unwrap())
+    }
+}
+
+impl ToRedisArgs for UnhydratedTradeUp {
+    fn write_redis_args<W>(&self, out: &mut W)
+    where
+        W: ?Sized + redis::RedisWrite,
+    {
+        let data = serde_json::to_vec(self).unwrap();
+        out.write_arg(&data)
+    }
+}
+
+impl FromRedisValue for UnhydratedTransfer {
+    fn from_redis_value(v: &redis::Value) -> RedisResult<Self> {
+        let data: Vec<u8> = from_redis_value(v)?;
+        Ok(serde_json::from_slice(&data).unwrap())
+    }
+}
+
+impl ToRedisArgs for UnhydratedTransfer {
+    fn write_redis_args<W>(&self, out: &mut W)
+    where
+        W: ?Sized + redis::RedisWrite,
+    {
+        let data = serde_json::to_vec(self).unwrap();
+        out.write_arg(&data)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TradeUp {
+    pub inputs: Vec<TrivialItem>,
+    pub item: ItemDescription,
+    pub item_value: MarketPrices,
+    /// Whether `item` was made without CSGOFloat, and so has no float yet.
+    #[serde(default)]
+    pub float_pending: bool,
+
+    pub at: DateTime<Utc>,
+    pub name: String,
+}
+
+impl FromRedisValue for TradeUp {
+    fn from_redis_value(v: &redis::Value) -> RedisResult<Self> {
+        let data: Vec<u8> = from_redis_value(v)?;
+        Ok(serde_json::from_slice(&data).unwrap())
+    }
+}
+
+impl ToRedisArgs for TradeUp {
+    fn write_redis_args<W>(&self, out: &mut W)
+    where
+        W: ?Sized + redis::RedisWrite,
+    {
+        let data = serde_json::to_vec(self).unwrap();
+        out.write_arg(&data)
+    }
+}
@@ -0,0 +1,210 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, Request, Response, StatusCode};
+
+use crate::errors::{RateLimited, RequestError};
+
+/// How we back off and retry when Steam rate-limits us or has a transient
+/// failure.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Number of times to retry a request before giving up.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each one after.
+    pub initial_backoff: Duration,
+    /// Longest we are willing to wait before a retry. If Steam asks us to
+    /// wait longer than this, we give up instead.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_secs(2),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy which never retries.
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff)
+    }
+
+    /// Executes the request, retrying on rate-limiting, server errors and
+    /// transient network failures.
+    ///
+    /// Responses with other statuses are returned as-is, for the caller to
+    /// handle.
+    pub async fn execute(&self, client: &Client, req: Request) -> Result<Response, RequestError> {
+        let mut attempt = 0;
+        loop {
+            // Requests with streaming bodies can't be retried, but we only
+            // ever make simple GETs and small POSTs.
+            let this_req = req.try_clone().ok_or(RequestError::Unretryable)?;
+            let retries_left = attempt < self.max_retries;
+
+            let wait = match client.execute(this_req).await {
+                Ok(resp) if resp.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    let retry_after = retry_after(&resp);
+                    let wait = retry_after.unwrap_or_else(|| self.backoff(attempt));
+                    if !retries_left || wait > self.max_backoff {
+                        return Err(RateLimited { retry_after }.into());
+                    }
+
+                    wait
+                }
+                Ok(resp) if resp.status().is_server_error() && retries_left => {
+                    self.backoff(attempt)
+                }
+                Ok(resp) => return Ok(resp),
+                Err(e) if (e.is_timeout() || e.is_connect()) && retries_left => {
+                    self.backoff(attempt)
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            log::warn!(
+                "request to {} failed, retrying in {}s",
+                req.url(),
+                wait.as_secs()
+            );
+            tokio::time::sleep(wait).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Reads the Retry-After header, which may be either a number of seconds or
+/// a date.
+fn retry_after(resp: &Response) -> Option<Duration> {
+    let value = resp.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    (at.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use mock_steam::{Failure, Fixtures};
+    use reqwest::{Client, StatusCode};
+
+    use super::RetryPolicy;
+    use crate::errors::RequestError;
+
+    const PATH: &str = "/market/priceoverview/";
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+
+    async fn execute(failure: Failure) -> (Result<StatusCode, RequestError>, usize) {
+        let (base, log) = mock_steam::spawn_logged(Fixtures {
+            failures: vec![failure],
+            ..Default::default()
+        });
+        let client = Client::new();
+        let req = client.get(format!("{base}{PATH}")).build().unwrap();
+
+        let res = policy().execute(&client, req).await;
+        (res.map(|r| r.status()), log.count(PATH))
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(10),
+        };
+
+        assert_eq!(policy.backoff(0), Duration::from_secs(1));
+        assert_eq!(policy.backoff(2), Duration::from_secs(4));
+        assert_eq!(policy.backoff(4), Duration::from_secs(10));
+        assert_eq!(policy.backoff(40), Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn test_waits_as_asked_when_rate_limited() {
+        let (res, requests) = execute(Failure {
+            path: PATH.to_string(),
+            status: 429,
+            retry_after: Some("1".to_string()),
+            times: 1,
+        })
+        .await;
+
+        assert_eq!(res.unwrap(), StatusCode::OK);
+        assert_eq!(requests, 2);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_when_asked_to_wait_too_long() {
+        let (res, requests) = execute(Failure {
+            path: PATH.to_string(),
+            status: 429,
+            retry_after: Some("120".to_string()),
+            times: 1,
+        })
+        .await;
+
+        match res {
+            Err(RequestError::RateLimited(e)) => {
+                assert_eq!(e.retry_after, Some(Duration::from_secs(120)))
+            }
+            res => panic!("expected to be rate limited, got {res:?}"),
+        }
+        assert_eq!(requests, 1);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_retries() {
+        let (res, requests) = execute(Failure {
+            path: PATH.to_string(),
+            status: 429,
+            retry_after: None,
+            times: 10,
+        })
+        .await;
+
+        match res {
+            Err(RequestError::RateLimited(e)) => assert_eq!(e.retry_after, None),
+            res => panic!("expected to be rate limited, got {res:?}"),
+        }
+        assert_eq!(requests, 3);
+
+        // Server errors are retried too, but returned once we run out.
+        let (res, requests) = execute(Failure {
+            path: PATH.to_string(),
+            status: 503,
+            retry_after: None,
+            times: 10,
+        })
+        .await;
+
+        assert_eq!(res.unwrap(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(requests, 3);
+    }
+}
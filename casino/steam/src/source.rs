@@ -0,0 +1,114 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{AuthenticationCheckError, FetchItemsError, LoginError};
+use crate::{FetchedHistory, InventoryId, SteamClient, SteamCredentials};
+
+/// Whether a source can still fetch history with the session it has.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum SessionState {
+    /// Logged in, until the given time if we know it.
+    Authenticated {
+        expires_at: Option<DateTime<Utc>>,
+    },
+    NotAuthenticated,
+}
+
+/// Somewhere we can find out about new events in a user's inventory.
+#[async_trait]
+pub trait HistorySource: Send + Sync {
+    /// Fetches events since the given time, or after the given item was
+    /// received, ready for upload.
+    async fn fetch_history_for_new_items(
+        &self,
+        since: Option<&DateTime<Utc>>,
+        last_item: Option<&InventoryId>,
+    ) -> Result<FetchedHistory, FetchItemsError>;
+
+    /// Checks whether this source's session is still valid. Sources which
+    /// don't log in are always authenticated.
+    async fn session_state(&self) -> Result<SessionState, AuthenticationCheckError> {
+        Ok(SessionState::Authenticated { expires_at: None })
+    }
+
+    /// Replaces the credentials this source logs in with. Sources which don't
+    /// log in ignore this.
+    fn set_credentials(&mut self, _creds: SteamCredentials) {}
+
+    /// Renews this source's session without asking the user, returning the
+    /// new credentials so they can be saved. Sources which can't do this
+    /// return `None`.
+    async fn refresh_session(&mut self) -> Result<Option<SteamCredentials>, LoginError> {
+        Ok(None)
+    }
+}
+
+/// Scrapes the Steam Community Inventory History page, using the user's
+/// browser cookies.
+#[async_trait]
+impl HistorySource for SteamClient {
+    async fn fetch_history_for_new_items(
+        &self,
+        since: Option<&DateTime<Utc>>,
+        last_item: Option<&InventoryId>,
+    ) -> Result<FetchedHistory, FetchItemsError> {
+        SteamClient::fetch_history_for_new_items(self, since, last_item).await
+    }
+
+    async fn session_state(&self) -> Result<SessionState, AuthenticationCheckError> {
+        let state = match self.is_authenticated().await? {
+            true => SessionState::Authenticated {
+                expires_at: self.session_expires_at(),
+            },
+            false => SessionState::NotAuthenticated,
+        };
+
+        Ok(state)
+    }
+
+    fn set_credentials(&mut self, creds: SteamCredentials) {
+        SteamClient::set_credentials(self, creds)
+    }
+
+    async fn refresh_session(&mut self) -> Result<Option<SteamCredentials>, LoginError> {
+        SteamClient::refresh_session(self).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mock_steam::Fixtures;
+
+    use super::HistorySource;
+    use crate::test_util::{item_html, mock_client, row_html};
+    use crate::EventKind;
+
+    #[tokio::test]
+    async fn test_community_source() {
+        let page = row_html(
+            r##"You traded with <a href="#">someone</a>"##,
+            &[],
+            &[item_html(0, "AK-47 | Redline", 100)],
+        );
+        let base = mock_steam::spawn(Fixtures {
+            history: page,
+            inventory: r#"{"total_inventory_count": 0, "success": 1}"#.to_string(),
+            ..Default::default()
+        });
+
+        let client = mock_client(&base).with_event_kinds([EventKind::Trade]);
+        let source: &dyn HistorySource = &client;
+
+        let fetched = source
+            .fetch_history_for_new_items(None, None)
+            .await
+            .unwrap();
+
+        let transfers = fetched.batch.transfers;
+        assert_eq!(transfers.len(), 1);
+        assert_eq!(transfers[0].kind, EventKind::Trade);
+        assert_eq!(transfers[0].gained[0].item.get_name(), "AK-47 | Redline");
+    }
+}
@@ -0,0 +1,373 @@
+use std::fmt;
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+use regex::Regex;
+use thiserror::Error;
+
+lazy_static::lazy_static! {
+    static ref STEAM2_REGEX: Regex = Regex::new(r"^STEAM_([0-5]):([01]):([0-9]+)$").unwrap();
+    static ref STEAM3_REGEX: Regex = Regex::new(r"^\[([a-zA-Z]):([0-5]):([0-9]+)(?::([0-9]+))?\]$").unwrap();
+    static ref PROFILES_URL_REGEX: Regex = Regex::new(r"steamcommunity\.com/profiles/([^/?#]+)").unwrap();
+}
+
+const ACCOUNT_ID_MASK: u64 = 0xFFFF_FFFF;
+const INSTANCE_SHIFT: u64 = 32;
+const INSTANCE_MASK: u64 = 0xF_FFFF;
+const ACCOUNT_TYPE_SHIFT: u64 = 52;
+const ACCOUNT_TYPE_MASK: u64 = 0xF;
+const UNIVERSE_SHIFT: u64 = 56;
+
+// Users logged in from a desktop client are always given this instance.
+const DESKTOP_INSTANCE: u32 = 1;
+
+/// Which Steam "universe" an account belongs to. Everything we see in
+/// practice is [`Universe::Public`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Universe {
+    Public = 1,
+    Beta = 2,
+    Internal = 3,
+    Dev = 4,
+}
+
+impl TryFrom<u8> for Universe {
+    type Error = SteamIdParseError;
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            1 => Ok(Universe::Public),
+            2 => Ok(Universe::Beta),
+            3 => Ok(Universe::Internal),
+            4 => Ok(Universe::Dev),
+            v => Err(SteamIdParseError::InvalidUniverse(v)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AccountType {
+    Individual = 1,
+    Multiseat = 2,
+    GameServer = 3,
+    AnonGameServer = 4,
+    Pending = 5,
+    ContentServer = 6,
+    Clan = 7,
+    Chat = 8,
+    AnonUser = 10,
+}
+
+impl AccountType {
+    /// The letter used for this account type in SteamID3s.
+    pub fn letter(&self) -> char {
+        match self {
+            AccountType::Individual => 'U',
+            AccountType::Multiseat => 'M',
+            AccountType::GameServer => 'G',
+            AccountType::AnonGameServer => 'A',
+            AccountType::Pending => 'P',
+            AccountType::ContentServer => 'C',
+            AccountType::Clan => 'g',
+            AccountType::Chat => 'T',
+            AccountType::AnonUser => 'a',
+        }
+    }
+
+    fn from_letter(c: char) -> Result<Self, SteamIdParseError> {
+        match c {
+            'U' => Ok(AccountType::Individual),
+            'M' => Ok(AccountType::Multiseat),
+            'G' => Ok(AccountType::GameServer),
+            'A' => Ok(AccountType::AnonGameServer),
+            'P' => Ok(AccountType::Pending),
+            'C' => Ok(AccountType::ContentServer),
+            'g' => Ok(AccountType::Clan),
+            // Chat rooms use different letters depending on what they're
+            // attached to.
+            'T' | 'c' | 'L' => Ok(AccountType::Chat),
+            'a' => Ok(AccountType::AnonUser),
+            c => Err(SteamIdParseError::InvalidAccountTypeLetter(c)),
+        }
+    }
+
+    fn default_instance(&self) -> u32 {
+        match self {
+            AccountType::Individual => DESKTOP_INSTANCE,
+            _ => 0,
+        }
+    }
+}
+
+impl TryFrom<u8> for AccountType {
+    type Error = SteamIdParseError;
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            1 => Ok(AccountType::Individual),
+            2 => Ok(AccountType::Multiseat),
+            3 => Ok(AccountType::GameServer),
+            4 => Ok(AccountType::AnonGameServer),
+            5 => Ok(AccountType::Pending),
+            6 => Ok(AccountType::ContentServer),
+            7 => Ok(AccountType::Clan),
+            8 => Ok(AccountType::Chat),
+            10 => Ok(AccountType::AnonUser),
+            v => Err(SteamIdParseError::InvalidAccountType(v)),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SteamIdParseError {
+    #[error("not a recognised steam id format: {0}")]
+    UnrecognisedFormat(String),
+    #[error("error parsing number in steam id: {0}")]
+    Number(#[from] ParseIntError),
+    #[error("invalid universe: {0}")]
+    InvalidUniverse(u8),
+    #[error("invalid account type: {0}")]
+    InvalidAccountType(u8),
+    #[error("invalid account type letter: {0}")]
+    InvalidAccountTypeLetter(char),
+}
+
+/// A validated Steam account identifier.
+///
+/// Parses from SteamID64s (`76561198000494793`), SteamID3s
+/// (`[U:1:40229065]`), legacy SteamIDs (`STEAM_0:1:20114532`), bare account
+/// ids and `steamcommunity.com/profiles/` URLs. Displays as a SteamID64.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SteamId(u64);
+
+impl SteamId {
+    pub fn new(
+        universe: Universe,
+        account_type: AccountType,
+        instance: u32,
+        account_id: u32,
+    ) -> Self {
+        let raw = (universe as u64) << UNIVERSE_SHIFT
+            | (account_type as u64) << ACCOUNT_TYPE_SHIFT
+            | (instance as u64 & INSTANCE_MASK) << INSTANCE_SHIFT
+            | account_id as u64;
+
+        Self(raw)
+    }
+
+    /// An ordinary user account in the public universe.
+    pub fn from_account_id(account_id: u32) -> Self {
+        Self::new(
+            Universe::Public,
+            AccountType::Individual,
+            DESKTOP_INSTANCE,
+            account_id,
+        )
+    }
+
+    pub fn steam64(&self) -> u64 {
+        self.0
+    }
+
+    pub fn account_id(&self) -> u32 {
+        (self.0 & ACCOUNT_ID_MASK) as u32
+    }
+
+    pub fn instance(&self) -> u32 {
+        ((self.0 >> INSTANCE_SHIFT) & INSTANCE_MASK) as u32
+    }
+
+    pub fn account_type(&self) -> AccountType {
+        // Validated on construction.
+        AccountType::try_from(((self.0 >> ACCOUNT_TYPE_SHIFT) & ACCOUNT_TYPE_MASK) as u8).unwrap()
+    }
+
+    pub fn universe(&self) -> Universe {
+        // Validated on construction.
+        Universe::try_from((self.0 >> UNIVERSE_SHIFT) as u8).unwrap()
+    }
+
+    /// Formats as a SteamID3, e.g. `[U:1:40229065]`.
+    pub fn steam3(&self) -> String {
+        let account_type = self.account_type();
+        let mut s = format!(
+            "[{}:{}:{}",
+            account_type.letter(),
+            self.universe() as u8,
+            self.account_id()
+        );
+        if self.instance() != account_type.default_instance() {
+            s.push_str(&format!(":{}", self.instance()));
+        }
+        s.push(']');
+
+        s
+    }
+
+    /// Formats as a legacy SteamID, e.g. `STEAM_0:1:20114532`.
+    pub fn steam2(&self) -> String {
+        // Older games always used 0 for the public universe.
+        let universe = match self.universe() {
+            Universe::Public => 0,
+            u => u as u8,
+        };
+        let account_id = self.account_id();
+
+        format!("STEAM_{}:{}:{}", universe, account_id & 1, account_id >> 1)
+    }
+
+    pub fn profile_url(&self) -> String {
+        format!("https://steamcommunity.com/profiles/{}", self.0)
+    }
+
+    fn parse_steam2(s: &str) -> Option<Result<Self, SteamIdParseError>> {
+        let captures = STEAM2_REGEX.captures(s)?;
+        let parse = || {
+            let universe = match captures[1].parse::<u8>()? {
+                0 => Universe::Public,
+                u => Universe::try_from(u)?,
+            };
+            let low: u32 = captures[2].parse()?;
+            let high: u32 = captures[3].parse()?;
+
+            Ok(Self::new(
+                universe,
+                AccountType::Individual,
+                DESKTOP_INSTANCE,
+                high << 1 | low,
+            ))
+        };
+
+        Some(parse())
+    }
+
+    fn parse_steam3(s: &str) -> Option<Result<Self, SteamIdParseError>> {
+        let captures = STEAM3_REGEX.captures(s)?;
+        let parse = || {
+            // Regex guarantees a single letter
+            let letter = captures[1].chars().next().unwrap();
+            let account_type = AccountType::from_letter(letter)?;
+            let universe = Universe::try_from(captures[2].parse::<u8>()?)?;
+            let account_id: u32 = captures[3].parse()?;
+            let instance = match captures.get(4) {
+                Some(i) => i.as_str().parse()?,
+                None => account_type.default_instance(),
+            };
+
+            Ok(Self::new(universe, account_type, instance, account_id))
+        };
+
+        Some(parse())
+    }
+}
+
+impl TryFrom<u64> for SteamId {
+    type Error = SteamIdParseError;
+
+    /// Validates a SteamID64.
+    fn try_from(raw: u64) -> Result<Self, Self::Error> {
+        Universe::try_from((raw >> UNIVERSE_SHIFT) as u8)?;
+        AccountType::try_from(((raw >> ACCOUNT_TYPE_SHIFT) & ACCOUNT_TYPE_MASK) as u8)?;
+
+        Ok(Self(raw))
+    }
+}
+
+impl From<SteamId> for u64 {
+    fn from(id: SteamId) -> Self {
+        id.0
+    }
+}
+
+impl FromStr for SteamId {
+    type Err = SteamIdParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(captures) = PROFILES_URL_REGEX.captures(s) {
+            return captures[1].parse();
+        }
+        if let Some(parsed) = Self::parse_steam3(s) {
+            return parsed;
+        }
+        if let Some(parsed) = Self::parse_steam2(s) {
+            return parsed;
+        }
+        if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) {
+            let raw: u64 = s.parse()?;
+            return match u32::try_from(raw) {
+                Ok(account_id) => Ok(Self::from_account_id(account_id)),
+                Err(_) => Self::try_from(raw),
+            };
+        }
+
+        Err(SteamIdParseError::UnrecognisedFormat(s.to_string()))
+    }
+}
+
+impl fmt::Display for SteamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AccountType, SteamId, SteamIdParseError, Universe};
+
+    const STEAM64: u64 = 76561198000494793;
+
+    #[test]
+    fn test_parse_formats() {
+        let expected = SteamId::try_from(STEAM64).unwrap();
+        let cases = [
+            "76561198000494793",
+            "[U:1:40229065]",
+            "STEAM_0:1:20114532",
+            "STEAM_1:1:20114532",
+            "40229065",
+            "https://steamcommunity.com/profiles/76561198000494793",
+            "https://steamcommunity.com/profiles/[U:1:40229065]/",
+        ];
+
+        for case in cases {
+            assert_eq!(case.parse::<SteamId>().unwrap(), expected, "{case}");
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let id = SteamId::try_from(STEAM64).unwrap();
+
+        assert_eq!(id.to_string(), "76561198000494793");
+        assert_eq!(id.steam3(), "[U:1:40229065]");
+        assert_eq!(id.steam2(), "STEAM_0:1:20114532");
+        assert_eq!(id.account_id(), 40229065);
+        assert_eq!(id.universe(), Universe::Public);
+        assert_eq!(id.account_type(), AccountType::Individual);
+
+        let clan: SteamId = "[g:1:4]".parse().unwrap();
+        assert_eq!(clan.account_type(), AccountType::Clan);
+        assert_eq!(clan.steam3(), "[g:1:4]");
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(matches!(
+            "[U:0:40229065]".parse::<SteamId>(),
+            Err(SteamIdParseError::InvalidUniverse(0))
+        ));
+        assert!(matches!(
+            "[X:1:40229065]".parse::<SteamId>(),
+            Err(SteamIdParseError::InvalidAccountTypeLetter('X'))
+        ));
+        // Account type 0 (invalid) in the public universe
+        assert!(matches!(
+            SteamId::try_from(0x0100_0000_0000_0001),
+            Err(SteamIdParseError::InvalidAccountType(0))
+        ));
+        assert!("badcop_".parse::<SteamId>().is_err());
+    }
+}
@@ -0,0 +1,41 @@
+//! Builders shared between tests.
+
+use crate::{Id, SteamClient, SteamCredentials};
+
+pub(crate) const HISTORY_ID: &str = "0123456789abcdef0123456789abcdef01234567";
+
+/// A client for the mock user on a mock Steam, with placeholder
+/// credentials.
+pub(crate) fn mock_client(base: &str) -> SteamClient {
+    let id = Id::with_base_url(mock_steam::STEAM_ID.parse().unwrap(), None, base);
+    let creds = SteamCredentials::try_from_cookie_str("sessionid=abc").unwrap();
+    SteamClient::new(id, creds)
+}
+
+pub(crate) fn item_html(idx: usize, name: &str, class_id: u64) -> String {
+    format!(
+        r#"<span class="history_item economy_item_hoverable" id="history{HISTORY_ID}_{idx}" data-appid="730" data-classid="{class_id}" data-instanceid="0">
+            <img class="tradehistory_received_item_img" src="https://community.cloudflare.steamstatic.com/economy/image/img{idx}/120x40">
+            <span class="history_item_name">{name}</span>
+        </span>"#
+    )
+}
+
+/// A logged-in Inventory History page with a single row on it.
+pub(crate) fn row_html(description: &str, lost: &[String], gained: &[String]) -> String {
+    format!(
+        r#"<html><body>
+            <div id="global_actions"><a id="account_pulldown">me</a></div>
+            <div class="tradehistoryrow">
+                <div class="tradehistory_date">Oct 31, 2021<div class="tradehistory_timestamp">1:50pm</div></div>
+                <div class="tradehistory_content">
+                    <div class="tradehistory_event_description">{description}</div>
+                    <div class="tradehistory_items"><div class="tradehistory_items_plusminus">-</div><div class="tradehistory_items_group">{}</div></div>
+                    <div class="tradehistory_items"><div class="tradehistory_items_plusminus">+</div><div class="tradehistory_items_group">{}</div></div>
+                </div>
+            </div>
+        </body></html>"#,
+        lost.join(""),
+        gained.join(""),
+    )
+}
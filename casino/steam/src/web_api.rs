@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use serde_aux::field_attributes::deserialize_number_from_string;
+
+use crate::errors::{FetchItemsError, FetchNewUnpreparedItemsError};
+use crate::retry::RetryPolicy;
+use crate::source::HistorySource;
+use crate::{
+    EventKind, EventKinds, FetchedHistory, GainedItem, HistoryBatch, InventoryId, TrivialItem,
+    UnhydratedTransfer,
+};
+
+const WEB_API_URL: &str = "https://api.steampowered.com";
+const CS_APP_ID: u32 = 730;
+// k_ETradeStatus_Complete
+const TRADE_STATUS_COMPLETE: u32 = 3;
+const MAX_TRADES: u32 = 100;
+
+#[derive(Debug, Deserialize)]
+struct TradeHistoryResponse {
+    response: TradeHistory,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct TradeHistory {
+    /// Whether there are older trades than these.
+    #[serde(default)]
+    more: bool,
+    #[serde(default)]
+    trades: Vec<Trade>,
+    #[serde(default)]
+    descriptions: Vec<TradeDescription>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Trade {
+    tradeid: String,
+    steamid_other: String,
+    time_init: i64,
+    status: u32,
+    #[serde(default)]
+    assets_given: Vec<TradeAsset>,
+    #[serde(default)]
+    assets_received: Vec<TradeAsset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TradeAsset {
+    appid: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    classid: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    instanceid: u64,
+}
+
+impl TradeAsset {
+    fn id(&self) -> InventoryId {
+        InventoryId::new(self.classid, self.instanceid)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TradeDescription {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    classid: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    instanceid: u64,
+    market_hash_name: String,
+    icon_url: String,
+    name_color: Option<String>,
+}
+
+impl From<&TradeDescription> for TrivialItem {
+    fn from(d: &TradeDescription) -> Self {
+        let image_url = format!(
+            "https://community.cloudflare.steamstatic.com/economy/image/{}",
+            d.icon_url
+        );
+
+        TrivialItem::new(&d.market_hash_name, image_url, d.name_color.clone())
+    }
+}
+
+/// Fetches history from the official Steam Web API, using an API key.
+///
+/// The Web API only exposes completed trades, so this can only produce
+/// [`EventKind::Trade`] events.
+pub struct WebApiClient {
+    http_client: Client,
+    retry_policy: RetryPolicy,
+    base_url: String,
+    api_key: String,
+    event_kinds: EventKinds,
+}
+
+impl WebApiClient {
+    pub fn new(api_key: String) -> Self {
+        Self {
+            http_client: Client::new(),
+            retry_policy: RetryPolicy::default(),
+            base_url: WEB_API_URL.to_string(),
+            api_key,
+            event_kinds: EventKind::DEFAULT.into_iter().collect(),
+        }
+    }
+
+    /// Points this client at a different host, instead of
+    /// api.steampowered.com.
+    pub fn with_base_url<S: Into<String>>(mut self, base_url: S) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Sets the kinds of event this client will collect.
+    pub fn with_event_kinds<I: IntoIterator<Item = EventKind>>(mut self, kinds: I) -> Self {
+        self.event_kinds = kinds.into_iter().collect();
+        self
+    }
+
+    /// Sets how this client backs off and retries requests that Steam
+    /// rejects.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Fetches every trade since the given time, or all of them, a page at
+    /// a time from the most recent.
+    async fn fetch_trade_history(
+        &self,
+        since: Option<&DateTime<Utc>>,
+    ) -> Result<TradeHistory, FetchNewUnpreparedItemsError> {
+        let mut history = TradeHistory::default();
+        let mut start_after = None;
+        loop {
+            let page = self.fetch_trade_history_page(start_after.as_ref()).await?;
+            history.descriptions.extend(page.descriptions);
+
+            let last = page.trades.last().map(|t| (t.time_init, t.tradeid.clone()));
+            history.trades.extend(page.trades);
+            start_after = match last {
+                Some((at, _)) if since.map(|s| at < s.timestamp()).unwrap_or(false) => break,
+                Some(last) if page.more => Some(last),
+                _ => break,
+            };
+        }
+
+        Ok(history)
+    }
+
+    /// Fetches a page of trades, starting after the given trade time and id,
+    /// or the most recent page if none is given.
+    async fn fetch_trade_history_page(
+        &self,
+        start_after: Option<&(i64, String)>,
+    ) -> Result<TradeHistory, FetchNewUnpreparedItemsError> {
+        let url = format!("{}/IEconService/GetTradeHistory/v1/", self.base_url);
+        let mut req = self.http_client.get(url).query(&[
+            ("key", self.api_key.as_str()),
+            ("max_trades", &MAX_TRADES.to_string()),
+            ("get_descriptions", "1"),
+            ("include_failed", "0"),
+        ]);
+        if let Some((time, tradeid)) = start_after {
+            req = req.query(&[
+                ("start_after_time", time.to_string().as_str()),
+                ("start_after_tradeid", tradeid.as_str()),
+            ]);
+        }
+        let req = req.build()?;
+        let resp = self.retry_policy.execute(&self.http_client, req).await?;
+
+        match resp.status() {
+            StatusCode::OK => (),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                return Err(FetchNewUnpreparedItemsError::Authentication)
+            }
+            status => return Err(FetchNewUnpreparedItemsError::UnhandledStatusCode(status)),
+        }
+
+        let data = resp.text().await?;
+        let parsed: TradeHistoryResponse = serde_json::from_str(&data)?;
+
+        Ok(parsed.response)
+    }
+}
+
+#[async_trait]
+impl HistorySource for WebApiClient {
+    async fn fetch_history_for_new_items(
+        &self,
+        since: Option<&DateTime<Utc>>,
+        _last_item: Option<&InventoryId>,
+    ) -> Result<FetchedHistory, FetchItemsError> {
+        if !self.event_kinds.contains(&EventKind::Trade) {
+            return Ok(FetchedHistory::default());
+        }
+
+        let history = self.fetch_trade_history(since).await?;
+        let descriptions: HashMap<InventoryId, &TradeDescription> = history
+            .descriptions
+            .iter()
+            .map(|d| (InventoryId::new(d.classid, d.instanceid), d))
+            .collect();
+        let item = |a: &TradeAsset| descriptions.get(&a.id()).map(|d| TrivialItem::from(*d));
+
+        let mut batch = HistoryBatch::default();
+        for trade in history.trades {
+            let at = match Utc.timestamp_opt(trade.time_init, 0).single() {
+                Some(at) => at,
+                None => continue,
+            };
+            if trade.status != TRADE_STATUS_COMPLETE || since.map(|s| &at < s).unwrap_or(false) {
+                continue;
+            }
+
+            let lost = trade
+                .assets_given
+                .iter()
+                .filter(|a| a.appid == CS_APP_ID)
+                .filter_map(item)
+                .collect::<Vec<_>>();
+            let gained = trade
+                .assets_received
+                .iter()
+                .filter(|a| a.appid == CS_APP_ID)
+                .filter_map(|a| {
+                    Some(GainedItem {
+                        item: item(a)?,
+                        id: a.id(),
+                    })
+                })
+                .collect::<Vec<_>>();
+            if lost.is_empty() && gained.is_empty() {
+                continue;
+            }
+
+            batch.transfers.push(UnhydratedTransfer {
+                kind: EventKind::Trade,
+                history_id: trade.tradeid,
+                description: format!("You traded with {}", trade.steamid_other),
+
+                lost,
+                gained,
+
+                at,
+                // The Web API doesn't tell us whose history this is.
+                name: String::new(),
+            });
+        }
+
+        Ok(FetchedHistory {
+            batch,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+    use mock_steam::Fixtures;
+    use serde_json::{json, Value};
+
+    use super::WebApiClient;
+    use crate::source::HistorySource;
+    use crate::EventKind;
+
+    #[tokio::test]
+    async fn test_fetch_trades() {
+        let base = mock_steam::spawn(Fixtures::default());
+        let client = WebApiClient::new("key".to_string())
+            .with_base_url(base)
+            .with_event_kinds([EventKind::Trade]);
+
+        let since = Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap();
+        let fetched = client
+            .fetch_history_for_new_items(Some(&since), None)
+            .await
+            .unwrap();
+
+        let transfers = fetched.batch.transfers;
+        assert_eq!(transfers.len(), 1);
+        assert_eq!(transfers[0].history_id, "3");
+        assert_eq!(transfers[0].lost[0].get_name(), "Clutch Case");
+        assert_eq!(
+            transfers[0].gained[0].item.get_name(),
+            "AK-47 | Redline (Field-Tested)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_trades_pages() {
+        // Split the trades over two pages, following on from the first.
+        let mut first: Value = serde_json::from_str(&Fixtures::default().trade_history).unwrap();
+        let mut rest = first.clone();
+        let trades = first["response"]["trades"].as_array_mut().unwrap();
+        let older = trades.split_off(1);
+        first["response"]["more"] = json!(true);
+        rest["response"]["trades"] = json!(older);
+
+        let (base, log) = mock_steam::spawn_logged(Fixtures {
+            trade_history: first.to_string(),
+            more_trade_history: [("3".to_string(), rest.to_string())].into(),
+            ..Default::default()
+        });
+        let client = WebApiClient::new("key".to_string())
+            .with_base_url(base)
+            .with_event_kinds([EventKind::Trade]);
+
+        let fetched = client
+            .fetch_history_for_new_items(None, None)
+            .await
+            .unwrap();
+
+        let ids: Vec<_> = fetched
+            .batch
+            .transfers
+            .iter()
+            .map(|t| t.history_id.as_str())
+            .collect();
+        assert_eq!(ids, ["3", "1"]);
+        assert_eq!(log.count("/IEconService/GetTradeHistory/v1/"), 2);
+    }
+
+    #[tokio::test]
+    async fn test_default_kinds_drop_trades() {
+        let base = mock_steam::spawn(Fixtures::default());
+        let client = WebApiClient::new("key".to_string())
+            .with_base_url(base)
+            .with_event_kinds([EventKind::Trade]);
+
+        let mut batch = client
+            .fetch_history_for_new_items(None, None)
+            .await
+            .unwrap()
+            .batch;
+
+        let dropped = batch.retain_kinds(&EventKind::DEFAULT.into_iter().collect());
+        assert_eq!(dropped.get(&EventKind::Trade), Some(&2));
+        assert!(batch.is_empty());
+    }
+}
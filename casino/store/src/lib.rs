@@ -11,11 +11,13 @@ use futures_util::{Stream, StreamExt};
 use thiserror::Error;
 
 use countdown::CountdownRequest;
-use steam::{UnhydratedUnlock, Unlock};
+use steam::{StoredUnlock, TradeUp, UnhydratedTradeUp, UnhydratedTransfer, Unlock};
 
 type Result<T> = std::result::Result<T, StoreError>;
 
 const UNLOCK_EVENT_KEY: &str = "new_unlock_events";
+const TRADE_UP_EVENT_KEY: &str = "new_trade_up_events";
+const TRANSFER_EVENT_KEY: &str = "new_transfer_events";
 const SYNC_EVENT_KEY: &str = "new_sync_events";
 
 /// Persists information about our application state.
@@ -73,13 +75,47 @@ impl Store {
         Ok(self.pool.get().await?)
     }
 
-    pub async fn get_entries(&self) -> Result<Vec<UnhydratedUnlock>> {
+    pub async fn get_entries(&self) -> Result<Vec<StoredUnlock>> {
+        self.get_indexed("entries", "unlock").await
+    }
+
+    pub async fn append_entry(&self, stored: &StoredUnlock) -> Result<()> {
+        let ts = stored.entry.at.timestamp_millis();
+        self.append_indexed("entries", "unlock", ts, &stored.entry.history_id, stored)
+            .await
+    }
+
+    pub async fn get_trade_ups(&self) -> Result<Vec<UnhydratedTradeUp>> {
+        self.get_indexed("trade_ups", "trade_up").await
+    }
+
+    pub async fn append_trade_up(&self, entry: &UnhydratedTradeUp) -> Result<()> {
+        let ts = entry.at.timestamp_millis();
+        self.append_indexed("trade_ups", "trade_up", ts, &entry.history_id, entry)
+            .await
+    }
+
+    pub async fn get_transfers(&self) -> Result<Vec<UnhydratedTransfer>> {
+        self.get_indexed("transfers", "transfer").await
+    }
+
+    pub async fn append_transfer(&self, entry: &UnhydratedTransfer) -> Result<()> {
+        let ts = entry.at.timestamp_millis();
+        self.append_indexed("transfers", "transfer", ts, &entry.history_id, entry)
+            .await
+    }
+
+    async fn get_indexed<T: redis::FromRedisValue>(
+        &self,
+        index: &str,
+        prefix: &str,
+    ) -> Result<Vec<T>> {
         let mut conn = self.get_conn().await?;
-        let keys: Vec<String> = match conn.zrevrange("entries", 0, -1).await? {
+        let keys: Vec<String> = match conn.zrevrange(index, 0, -1).await? {
             Some(keys) => keys,
             None => return Ok(Vec::new()),
         };
-        let redis_keys: Vec<String> = keys.iter().map(|k| format!("unlock_{}", k)).collect();
+        let redis_keys: Vec<String> = keys.iter().map(|k| format!("{}_{}", prefix, k)).collect();
         Ok(match &redis_keys[..] {
             [] => vec![],
             [only] => conn.get(only).await?,
@@ -87,15 +123,20 @@ impl Store {
         })
     }
 
-    pub async fn append_entry(&self, entry: &UnhydratedUnlock) -> Result<()> {
+    async fn append_indexed<T: serde::Serialize>(
+        &self,
+        index: &str,
+        prefix: &str,
+        ts: i64,
+        id: &str,
+        entry: &T,
+    ) -> Result<()> {
         let mut conn = self.get_conn().await?;
-        let ts = entry.at.timestamp_millis();
-        let id = &entry.history_id;
-        let data_key = format!("unlock_{}", id);
-        let data = serde_json::to_vec(&entry)?;
+        let data_key = format!("{}_{}", prefix, id);
+        let data = serde_json::to_vec(entry)?;
         let _res: () = redis::pipe()
             .cmd("ZADD")
-            .arg("entries")
+            .arg(index)
             .arg(ts)
             .arg(id)
             .cmd("SET")
@@ -111,6 +152,14 @@ impl Store {
         self.publish(UNLOCK_EVENT_KEY, entry).await
     }
 
+    pub async fn publish_trade_up(&self, entry: &TradeUp) -> Result<()> {
+        self.publish(TRADE_UP_EVENT_KEY, entry).await
+    }
+
+    pub async fn publish_transfer(&self, entry: &UnhydratedTransfer) -> Result<()> {
+        self.publish(TRANSFER_EVENT_KEY, entry).await
+    }
+
     pub async fn start_countdown(&self, entry: &CountdownRequest) -> Result<()> {
         self.publish(SYNC_EVENT_KEY, entry).await
     }
@@ -130,6 +179,14 @@ impl Store {
         self.get_redis_stream(UNLOCK_EVENT_KEY).await
     }
 
+    pub async fn get_trade_up_stream(&self) -> Result<impl Stream<Item = TradeUp>> {
+        self.get_redis_stream(TRADE_UP_EVENT_KEY).await
+    }
+
+    pub async fn get_transfer_stream(&self) -> Result<impl Stream<Item = UnhydratedTransfer>> {
+        self.get_redis_stream(TRANSFER_EVENT_KEY).await
+    }
+
     pub async fn get_sync_stream(&self) -> Result<impl Stream<Item = CountdownRequest>> {
         self.get_redis_stream(SYNC_EVENT_KEY).await
     }
@@ -1,5 +1,7 @@
 // Estimate the net cost (value?) of an item.
-const value_estimator = ({ item_value, case_value, key }) => {
+const value_estimator = ({ item_value, case_value, key, profit }) => {
+  // Newer aggregators work this out for us.
+  if (profit !== undefined && profit !== null) return profit;
   const key_actual = key ? 2.49 : 0;
   const case_actual = case_value.lowest_price || case_value.median_price || 0;
   const item_actual = item_value.lowest_price || item_value.median_price || 0;
@@ -60,7 +62,8 @@ const makeRow = (item) => {
   unboxer.innerText = `Unboxed by `;
   unboxer.appendChild(unboxer_name);
   const price = document.createElement("p");
-  price.innerText = `$${(item.item_value.lowest_price || item.item_value.median_price || 0).toLocaleString(undefined, { minimumFractionDigits: 2, maximumFractionDigits: 2 })} USD`;
+  const currency = item.item_value.currency || "USD";
+  price.innerText = `${(item.item_value.lowest_price || item.item_value.median_price || 0).toLocaleString(undefined, { style: "currency", currency, currencyDisplay: "narrowSymbol" })} ${currency}`;
 
   img.setAttribute("src", image_url);
   div.appendChild(img);